@@ -0,0 +1,137 @@
+//! Reads into and writes from buffers of the `bytes` crate.
+//!
+//! Bytes are copied between the pipe and the buffers directly, so there's no need for an
+//! intermediate slice.
+
+use std::future;
+use std::io::{self, Read, Write};
+use std::mem::MaybeUninit;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, BufMut};
+
+use crate::{copy_uninit, Blocking, Source};
+
+impl<T: Read + Send + 'static> Blocking<T> {
+    /// Attempts to read bytes into `buf`, advancing it by the number of bytes read.
+    ///
+    /// Bytes are copied from the internal pipe straight into `buf`, so there's no need to
+    /// initialize a slice first. On success, the number of bytes read is returned, and zero means
+    /// the end of the I/O handle has been reached or `buf` has no remaining capacity.
+    ///
+    /// This method is only available with the `bytes` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use bytes::BytesMut;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::open("file.txt")?);
+    ///
+    /// let mut buf = BytesMut::with_capacity(4096);
+    /// let n = future::poll_fn(|cx| file.poll_read_buf(cx, &mut buf)).await?;
+    /// println!("read {} bytes", n);
+    /// # std::io::Result::Ok(()) });
+    /// # use std::future;
+    /// ```
+    pub fn poll_read_buf<B: BufMut>(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<io::Result<usize>> {
+        if !buf.has_remaining_mut() {
+            return Poll::Ready(Ok(0));
+        }
+        self.poll_read_with(cx, |reader, cx| reader.poll_read(cx, (&mut *buf).writer()))
+    }
+}
+
+impl<T: Write + Send + 'static> Blocking<T> {
+    /// Attempts to write bytes from `buf`, advancing it by the number of bytes written.
+    ///
+    /// Bytes are copied from `buf` straight into the internal pipe. On success, the number of
+    /// bytes written is returned.
+    ///
+    /// This method is only available with the `bytes` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use bytes::Bytes;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::create("file.txt")?);
+    ///
+    /// let mut buf = Bytes::from_static(b"hello");
+    /// let n = future::poll_fn(|cx| file.poll_write_buf(cx, &mut buf)).await?;
+    /// println!("wrote {} bytes", n);
+    /// # std::io::Result::Ok(()) });
+    /// # use std::future;
+    /// ```
+    pub fn poll_write_buf<B: Buf>(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<io::Result<usize>> {
+        if !buf.has_remaining() {
+            return Poll::Ready(Ok(0));
+        }
+        self.poll_write_with(cx, |writer, cx| writer.poll_write(cx, BufSource(&mut *buf)))
+    }
+
+    /// Writes all bytes from `buf`.
+    ///
+    /// Like with [`AsyncWriteExt::write_all()`][write_all], the bytes may still be buffered
+    /// afterwards, so make sure to flush the handle to write them into the I/O handle. To forward
+    /// a stream of [`Bytes`][`bytes::Bytes`], use [`Blocking::into_sink()`] instead.
+    ///
+    /// This method is only available with the `bytes` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use bytes::Bytes;
+    /// use futures::prelude::*;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::create("file.txt")?);
+    ///
+    /// file.write_bytes(Bytes::from_static(b"hello")).await?;
+    /// file.flush().await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    ///
+    /// [write_all]: https://docs.rs/futures/0.3/futures/io/trait.AsyncWriteExt.html#method.write_all
+    pub async fn write_bytes<B: Buf>(&mut self, mut buf: B) -> io::Result<()> {
+        while buf.has_remaining() {
+            let n = future::poll_fn(|cx| self.poll_write_buf(cx, &mut buf)).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A source of bytes copied out of a [`Buf`], advancing it.
+struct BufSource<'a, B>(&'a mut B);
+
+impl<B: Buf> Source for BufSource<'_, B> {
+    fn fill(&mut self, dest: &mut [MaybeUninit<u8>], init: &mut usize) -> io::Result<usize> {
+        let mut count = 0;
+        while count < dest.len() && self.0.has_remaining() {
+            let n = copy_uninit(self.0.chunk(), &mut dest[count..]);
+            self.0.advance(n);
+            count += n;
+        }
+        *init = (*init).max(count);
+        Ok(count)
+    }
+}
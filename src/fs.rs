@@ -0,0 +1,515 @@
+//! Async filesystem operations.
+//!
+//! Blocking filesystem calls are moved onto the thread pool, so they can be awaited without
+//! blocking the executor.
+//!
+//! # Examples
+//!
+//! Read a whole file:
+//!
+//! ```no_run
+//! # futures::executor::block_on(async {
+//! let contents = blocking::fs::read_to_string("file.txt").await?;
+//! # std::io::Result::Ok(()) });
+//! ```
+//!
+//! Move a file and inspect it:
+//!
+//! ```no_run
+//! # futures::executor::block_on(async {
+//! blocking::fs::rename("a.txt", "b.txt").await?;
+//! let metadata = blocking::fs::metadata("b.txt").await?;
+//! println!("{} bytes", metadata.len());
+//! # std::io::Result::Ok(()) });
+//! ```
+//!
+//! Write into a file piece by piece:
+//!
+//! ```no_run
+//! use blocking::fs::File;
+//! use futures::prelude::*;
+//!
+//! # futures::executor::block_on(async {
+//! let mut file = File::create("file.txt").await?;
+//! file.write_all(b"hello").await?;
+//! file.sync_all().await?;
+//! # std::io::Result::Ok(()) });
+//! ```
+
+use std::fs::{self, Metadata};
+use std::future;
+use std::io::{self, IoSlice, IoSliceMut, Read, SeekFrom};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_io::{AsyncBufRead, AsyncRead, AsyncSeek, AsyncWrite};
+
+use crate::{unblock, Blocking};
+
+/// Reads the entire contents of a file into a byte vector.
+///
+/// This is an async version of [`std::fs::read()`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// let contents = blocking::fs::read("file.bin").await?;
+/// println!("{} bytes", contents.len());
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let path = path.as_ref().to_owned();
+    unblock(move || fs::read(path)).await
+}
+
+/// Reads the entire contents of a file into a string.
+///
+/// This is an async version of [`std::fs::read_to_string()`]. An error is returned if the file
+/// is not valid UTF-8.
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// let contents = blocking::fs::read_to_string("file.txt").await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn read_to_string(path: impl AsRef<Path>) -> io::Result<String> {
+    let path = path.as_ref().to_owned();
+    unblock(move || fs::read_to_string(path)).await
+}
+
+/// Reads the entire contents of a file, appending them to `buf`.
+///
+/// Unlike [`read()`], this reuses the capacity of `buf`. On success, the number of bytes read is
+/// returned. If an error occurs, `buf` holds the bytes read before it.
+///
+/// The buffer is moved onto the thread pool while reading, so if the returned future is dropped
+/// before completion, `buf` is left empty.
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// let mut buf = Vec::new();
+/// for path in ["a.txt", "b.txt"] {
+///     buf.clear();
+///     let n = blocking::fs::read_to_end(path, &mut buf).await?;
+///     println!("{}: {} bytes", path, n);
+/// }
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn read_to_end(path: impl AsRef<Path>, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let path = path.as_ref().to_owned();
+    let mut vec = mem::take(buf);
+    let (res, vec) = unblock(move || {
+        let res = fs::File::open(path).and_then(|mut file| file.read_to_end(&mut vec));
+        (res, vec)
+    })
+    .await;
+    *buf = vec;
+    res
+}
+
+/// Writes a byte slice as the entire contents of a file.
+///
+/// This is an async version of [`std::fs::write()`]. The file is created if it doesn't exist and
+/// truncated if it does. The contents are copied so they can be moved onto the thread pool.
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// blocking::fs::write("file.txt", "hello").await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    let contents = contents.as_ref().to_vec();
+    unblock(move || fs::write(path, contents)).await
+}
+
+/// Queries metadata about a file or directory, following symbolic links.
+///
+/// This is an async version of [`std::fs::metadata()`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// let metadata = blocking::fs::metadata("file.txt").await?;
+/// println!("{} bytes", metadata.len());
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn metadata(path: impl AsRef<Path>) -> io::Result<Metadata> {
+    let path = path.as_ref().to_owned();
+    unblock(move || fs::metadata(path)).await
+}
+
+/// Queries metadata about a file or directory without following symbolic links.
+///
+/// This is an async version of [`std::fs::symlink_metadata()`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// let metadata = blocking::fs::symlink_metadata("link").await?;
+/// println!("symlink: {}", metadata.file_type().is_symlink());
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn symlink_metadata(path: impl AsRef<Path>) -> io::Result<Metadata> {
+    let path = path.as_ref().to_owned();
+    unblock(move || fs::symlink_metadata(path)).await
+}
+
+/// Returns the canonical, absolute form of a path with all symbolic links resolved.
+///
+/// This is an async version of [`std::fs::canonicalize()`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// let path = blocking::fs::canonicalize("../a/../foo.txt").await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn canonicalize(path: impl AsRef<Path>) -> io::Result<PathBuf> {
+    let path = path.as_ref().to_owned();
+    unblock(move || fs::canonicalize(path)).await
+}
+
+/// Copies the contents and permissions of a file into another file.
+///
+/// This is an async version of [`std::fs::copy()`]. The destination is overwritten if it exists.
+/// On success, the number of copied bytes is returned.
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// let n = blocking::fs::copy("a.txt", "b.txt").await?;
+/// println!("copied {} bytes", n);
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn copy(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<u64> {
+    let src = src.as_ref().to_owned();
+    let dst = dst.as_ref().to_owned();
+    unblock(move || fs::copy(src, dst)).await
+}
+
+/// Renames a file or directory, replacing the destination if it exists.
+///
+/// This is an async version of [`std::fs::rename()`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// blocking::fs::rename("a.txt", "b.txt").await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+    let from = from.as_ref().to_owned();
+    let to = to.as_ref().to_owned();
+    unblock(move || fs::rename(from, to)).await
+}
+
+/// Removes a file.
+///
+/// This is an async version of [`std::fs::remove_file()`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// blocking::fs::remove_file("file.txt").await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn remove_file(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    unblock(move || fs::remove_file(path)).await
+}
+
+/// Removes an empty directory.
+///
+/// This is an async version of [`std::fs::remove_dir()`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// blocking::fs::remove_dir("empty").await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn remove_dir(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    unblock(move || fs::remove_dir(path)).await
+}
+
+/// Removes a directory and everything inside it.
+///
+/// This is an async version of [`std::fs::remove_dir_all()`]. Removal happens in a single
+/// blocking task, so it occupies one thread for the whole directory tree.
+///
+/// # Examples
+///
+/// ```no_run
+/// # futures::executor::block_on(async {
+/// blocking::fs::remove_dir_all("target").await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn remove_dir_all(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    unblock(move || fs::remove_dir_all(path)).await
+}
+
+/// An open file on the filesystem.
+///
+/// This is an async version of [`std::fs::File`]. Reads, writes, and seeks go through a
+/// [`Blocking`] handle, and the remaining operations like [`metadata()`][`File::metadata()`] and
+/// [`sync_all()`][`File::sync_all()`] run on the thread pool as well.
+///
+/// Like with [`Blocking`], written bytes may still be buffered, so make sure to flush the file
+/// or call [`sync_all()`][`File::sync_all()`] before dropping it, or else errors won't be reported.
+///
+/// # Examples
+///
+/// ```no_run
+/// use blocking::fs::File;
+/// use futures::prelude::*;
+///
+/// # futures::executor::block_on(async {
+/// let mut file = File::open("file.txt").await?;
+///
+/// let mut contents = String::new();
+/// file.read_to_string(&mut contents).await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+#[derive(Debug)]
+pub struct File {
+    inner: Blocking<fs::File>,
+}
+
+impl File {
+    /// Opens a file in read-only mode.
+    ///
+    /// This is an async version of [`std::fs::File::open()`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let file = File::open("file.txt").await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<File> {
+        let path = path.as_ref().to_owned();
+        let file = unblock(move || fs::File::open(path)).await?;
+        Ok(File::from(file))
+    }
+
+    /// Opens a file in write-only mode, creating it if it doesn't exist and truncating it if it
+    /// does.
+    ///
+    /// This is an async version of [`std::fs::File::create()`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let file = File::create("file.txt").await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn create(path: impl AsRef<Path>) -> io::Result<File> {
+        let path = path.as_ref().to_owned();
+        let file = unblock(move || fs::File::create(path)).await?;
+        Ok(File::from(file))
+    }
+
+    /// Queries metadata about the file.
+    ///
+    /// Pending writes are written into the file first, so the reported length includes them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = File::open("file.txt").await?;
+    /// let metadata = file.metadata().await?;
+    /// println!("{} bytes", metadata.len());
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn metadata(&mut self) -> io::Result<Metadata> {
+        self.flush_pending().await?;
+        self.inner.with(|file| file.metadata()).await
+    }
+
+    /// Truncates or extends the file to `size` bytes.
+    ///
+    /// Pending writes are written into the file first. The cursor is not moved, so it may end up
+    /// past the end of the file.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = File::create("file.txt").await?;
+    /// file.set_len(1024).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.flush_pending().await?;
+        self.inner.with(move |file| file.set_len(size)).await
+    }
+
+    /// Writes pending bytes and synchronizes all file contents and metadata to disk.
+    ///
+    /// This is an async version of [`std::fs::File::sync_all()`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::fs::File;
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = File::create("file.txt").await?;
+    /// file.write_all(b"hello").await?;
+    /// file.sync_all().await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn sync_all(&mut self) -> io::Result<()> {
+        self.flush_pending().await?;
+        self.inner.with(|file| file.sync_all()).await
+    }
+
+    /// Writes pending bytes and synchronizes file contents to disk, but not necessarily its
+    /// metadata.
+    ///
+    /// This is an async version of [`std::fs::File::sync_data()`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::fs::File;
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = File::create("file.txt").await?;
+    /// file.write_all(b"hello").await?;
+    /// file.sync_data().await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn sync_data(&mut self) -> io::Result<()> {
+        self.flush_pending().await?;
+        self.inner.with(|file| file.sync_data()).await
+    }
+
+    /// Converts into a [`std::fs::File`].
+    ///
+    /// Pending writes are written into the file first, but errors are ignored. Flush the file to
+    /// observe them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let file = File::open("file.txt").await?;
+    /// let file: std::fs::File = file.into_std().await;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn into_std(self) -> fs::File {
+        self.inner.into_inner().await
+    }
+
+    /// Writes pending bytes into the file, reporting errors.
+    async fn flush_pending(&mut self) -> io::Result<()> {
+        future::poll_fn(|cx| Pin::new(&mut self.inner).poll_flush(cx)).await
+    }
+}
+
+impl From<fs::File> for File {
+    fn from(file: fs::File) -> File {
+        File {
+            inner: Blocking::new(file),
+        }
+    }
+}
+
+impl AsyncRead for File {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read_vectored(cx, bufs)
+    }
+}
+
+impl AsyncBufRead for File {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.inner).consume(amt)
+    }
+}
+
+impl AsyncWrite for File {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl AsyncSeek for File {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_seek(cx, pos)
+    }
+}
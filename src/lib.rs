@@ -30,6 +30,17 @@
 //! # std::io::Result::Ok(()) });
 //! ```
 //!
+//! Or run a blocking closure with [`unblock()`]:
+//!
+//! ```no_run
+//! use blocking::unblock;
+//! use std::fs;
+//!
+//! # futures::executor::block_on(async {
+//! let contents = unblock(|| fs::read_to_string("file.txt")).await?;
+//! # std::io::Result::Ok(()) });
+//! ```
+//!
 //! Or do the same with the [`blocking!`] macro:
 //!
 //! ```no_run
@@ -56,6 +67,20 @@
 //! # std::io::Result::Ok(()) });
 //! ```
 //!
+//! Or use the async [`File`](fs::File) type, which wraps a [`Blocking`] handle and also offloads
+//! operations like [`sync_all()`](fs::File::sync_all()):
+//!
+//! ```no_run
+//! use blocking::fs::File;
+//! use futures::prelude::*;
+//!
+//! # futures::executor::block_on(async {
+//! let mut file = File::create("file.txt").await?;
+//! file.write_all(b"hello").await?;
+//! file.sync_all().await?;
+//! # std::io::Result::Ok(()) });
+//! ```
+//!
 //! Iterate over the contents of a directory:
 //!
 //! ```no_run
@@ -71,996 +96,9760 @@
 //! }
 //! # std::io::Result::Ok(()) });
 //! ```
+//!
+//! # WebAssembly
+//!
+//! On `wasm32` targets without threads, blocking tasks run on the thread that schedules them
+//! instead of on a pool, so libraries can depend on this crate unconditionally. Timeouts need a
+//! timer thread and are not supported there.
+//!
+//! # Cargo features
+//!
+//! - `stream` (enabled by default): implements [`Stream`] for [`Blocking`] handles wrapping an
+//!   [`Iterator`], and provides [`Blocking::from_try_iter()`] for fallible iterators, and
+//!   [`Blocking::into_byte_stream()`] and [`Blocking::into_lines()`] for streams of byte chunks
+//!   and lines. It also implements [`Stream`] for the [`Receiver`] of a [`channel()`]. Without it,
+//!   spawning tasks and the [`AsyncRead`] and [`AsyncWrite`] impls still work.
+//!
+//! - `sink` (enabled by default): provides [`Blocking::into_sink()`], which turns a writer into a
+//!   [`Sink`] of byte buffers so that streams of buffers can be forwarded into it.
+//!
+//! - `bytes`: provides [`Blocking::poll_read_buf()`], [`Blocking::poll_write_buf()`], and
+//!   [`Blocking::write_bytes()`], which read into and write from [`bytes`](https://docs.rs/bytes)
+//!   buffers without intermediate slices.
+//!
+//! - `io-uring`: on Linux, reads and writes [`File`](std::fs::File) handles wrapped in
+//!   [`Blocking`] through io_uring instead of on pool threads, falling back to the thread pool if
+//!   io_uring is not available. This feature is ignored on other platforms.
+//!
+//! - `tokio`: implements the [`tokio`](https://docs.rs/tokio) I/O traits `AsyncRead`,
+//!   `AsyncBufRead`, `AsyncWrite`, and `AsyncSeek` for [`Blocking`], so handles can be used with
+//!   tokio codecs and `tokio::io::copy()` without compatibility adapters.
+//!
+//! - `test-util`: provides [`TestPool`], a pool whose tasks only run when stepped manually,
+//!   for deterministic tests of code built on [`Blocking`], and [`FaultInjection`] for testing
+//!   under slow I/O and pool saturation.
+//!
+//! - `tracing`: emits [`tracing`](https://docs.rs/tracing) events for task scheduling, execution
+//!   and pool growth, and runs spawned futures inside the span that was current when they were
+//!   spawned.
+//!
+//! - `log`: logs [`log`](https://docs.rs/log) messages when the pool grows or reaches its thread
+//!   limit, and when a blocking task panics.
+//!
+//! # Configuration
+//!
+//! The thread pool can be configured with [`PoolBuilder`] before the first blocking task is
+//! spawned. For example, give threads larger stacks for deeply recursive work:
+//!
+//! ```
+//! use blocking::PoolBuilder;
+//!
+//! PoolBuilder::new()
+//!     .stack_size(16 * 1024 * 1024)
+//!     .build_global()
+//!     .expect("the global pool has already been initialized");
+//! ```
 
 use std::any::Any;
-use std::collections::VecDeque;
-use std::io::{self, Read, Write};
-use std::mem;
+use std::cell::{Cell, UnsafeCell};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::fmt;
+use std::future::{self, Future};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+#[cfg(feature = "stream")]
+use std::io::{BufRead, BufReader, Cursor};
+use std::mem::{self, MaybeUninit};
+use std::num::NonZeroUsize;
 use std::panic;
-use std::pin::Pin;
+use std::pin::{self, Pin};
+use std::ptr;
 use std::slice;
 use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
-use std::sync::{Arc, Condvar, Mutex, MutexGuard};
-use std::task::{Context, Poll};
+#[cfg(not(all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::task::{ready, Context, Poll, Wake, Waker};
 use std::thread;
-use std::time::Duration;
-
-use futures::channel::mpsc;
-use futures::prelude::*;
-use futures::task::AtomicWaker;
-use once_cell::sync::Lazy;
+use std::time::{Duration, Instant};
+
+use atomic_waker::AtomicWaker;
+use concurrent_queue::ConcurrentQueue;
+use event_listener::Event;
+#[cfg(feature = "stream")]
+use futures_core::Stream;
+use futures_io::{AsyncBufRead, AsyncRead, AsyncSeek, AsyncWrite};
+#[cfg(feature = "sink")]
+use futures_sink::Sink;
+use once_cell::sync::{Lazy, OnceCell};
+
+pub mod fs;
+
+#[cfg(feature = "bytes")]
+mod bytes_io;
+#[cfg(feature = "tokio")]
+mod tokio_io;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
 
 /// A runnable future, ready for execution.
 ///
 /// When a future is internally spawned using `async_task::spawn()` or `async_task::spawn_local()`,
 /// we get back two values:
 ///
-/// 1. an `async_task::Task<()>`, which we refer to as a `Runnable`
-/// 2. an `async_task::JoinHandle<T, ()>`, which is wrapped inside a `Task<T>`
+/// 1. an `async_task::Task<TaskInfo>`, which we refer to as a `Runnable`
+/// 2. an `async_task::JoinHandle<T, TaskInfo>`, which is wrapped inside a `Task<T>`
 ///
 /// Once a `Runnable` is run, it "vanishes" and only reappears when its future is woken. When it's
 /// woken up, its schedule function is called, which means the `Runnable` gets pushed into the main
 /// task queue in the executor.
-type Runnable = async_task::Task<()>;
+type Runnable = async_task::Task<TaskInfo>;
 
-struct Task<T>(Option<async_task::JoinHandle<T, ()>>);
+/// Information stored inside every task.
+#[derive(Debug)]
+struct TaskInfo {
+    /// The priority of the task in the queue.
+    priority: Priority,
 
-impl<T> Drop for Task<T> {
-    fn drop(&mut self) {
-        if let Some(handle) = &self.0 {
-            handle.cancel();
-        }
-    }
-}
+    /// The name of the task, used for diagnostics.
+    name: Option<Arc<str>>,
 
-impl<T> Future for Task<T> {
-    type Output = T;
+    /// Set to `true` when the task is canceled, so that blocking code can stop early.
+    canceled: AtomicBool,
+}
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        match Pin::new(&mut self.0.as_mut().unwrap()).poll(cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(output) => Poll::Ready(output.expect("task has failed")),
+impl TaskInfo {
+    /// Creates information for a new task.
+    fn new(priority: Priority, name: Option<Arc<str>>) -> TaskInfo {
+        TaskInfo {
+            priority,
+            name,
+            canceled: AtomicBool::new(false),
         }
     }
 }
 
-/// The blocking executor.
-struct Executor {
-    /// Inner state of the executor.
-    inner: Mutex<Inner>,
-
-    /// Used to put idle threads to sleep and wake them up when new work comes in.
-    cvar: Condvar,
-}
-
-/// Inner state of the blocking executor.
-struct Inner {
-    /// Number of idle threads in the pool.
-    ///
-    /// Idle threads are sleeping, waiting to get a task to run.
-    idle_count: usize,
-
-    /// Total number of threads in the pool.
-    ///
-    /// This is the number of idle threads + the number of active threads.
-    thread_count: usize,
+thread_local! {
+    /// Information about the task running on the current thread.
+    static CURRENT: Cell<*const TaskInfo> = const { Cell::new(ptr::null()) };
 
-    /// The queue of blocking tasks.
-    queue: VecDeque<Runnable>,
+    /// The executor the current thread belongs to, if it's a thread in a pool.
+    static WORKER: Cell<*const Executor> = const { Cell::new(ptr::null()) };
 }
 
-impl Executor {
-    /// Spawns a future onto this executor.
-    ///
-    /// Returns a [`Task`] handle for the spawned task.
-    fn spawn<T: Send + 'static>(future: impl Future<Output = T> + Send + 'static) -> Task<T> {
-        static EXECUTOR: Lazy<Executor> = Lazy::new(|| Executor {
-            inner: Mutex::new(Inner {
-                idle_count: 0,
-                thread_count: 0,
-                queue: VecDeque::new(),
-            }),
-            cvar: Condvar::new(),
-        });
+/// Runs a task, making it the current task on this thread while it runs.
+fn run_current(runnable: Runnable) {
+    /// Restores the previous current task, even if running the task panics.
+    struct Restore(*const TaskInfo);
 
-        // Create a task, schedule it, and return its `Task` handle.
-        let (runnable, handle) = async_task::spawn(future, |r| EXECUTOR.schedule(r), ());
-        runnable.schedule();
-        Task(Some(handle))
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.set(self.0));
+        }
     }
 
-    /// Runs the main loop on the current thread.
-    ///
-    /// This function runs blocking tasks until it becomes idle and times out.
-    fn main_loop(&'static self) {
-        let mut inner = self.inner.lock().unwrap();
-        loop {
-            // This thread is not idle anymore because it's going to run tasks.
-            inner.idle_count -= 1;
-
-            // Run tasks in the queue.
-            while let Some(runnable) = inner.queue.pop_front() {
-                // We have found a task - grow the pool if needed.
-                self.grow_pool(inner);
+    let _restore = Restore(CURRENT.with(|current| current.replace(runnable.tag())));
+    runnable.run();
+}
 
-                // Run the task.
-                let _ = panic::catch_unwind(|| runnable.run());
+/// Returns `true` if the blocking task running on the current thread has been canceled.
+///
+/// Blocking code can't be canceled forcibly, so a task whose handle has been dropped or that has
+/// timed out keeps running until it yields. Long-running blocking loops can check this flag to
+/// stop early instead. Outside of blocking tasks, this function always returns `false`.
+///
+/// # Examples
+///
+/// ```
+/// use blocking::Blocking;
+///
+/// # futures::executor::block_on(async {
+/// let task = Blocking::spawn(async {
+///     for chunk in 0..1000 {
+///         if blocking::is_canceled() {
+///             break;
+///         }
+///         // Process the chunk...
+///     }
+/// });
+/// # task.await;
+/// # });
+/// ```
+pub fn is_canceled() -> bool {
+    CURRENT.with(|current| {
+        let info = current.get();
+        // SAFETY: The pointer is set only while the task is running, which keeps it alive.
+        !info.is_null() && unsafe { (*info).canceled.load(Ordering::SeqCst) }
+    })
+}
 
-                // Re-lock the inner state and continue.
-                inner = self.inner.lock().unwrap();
-            }
+/// Returns `true` if the current thread belongs to a blocking pool.
+///
+/// Library code can use this to assert that blocking helpers aren't accidentally called from an
+/// async executor, or to call them directly when already running on a pool.
+///
+/// # Examples
+///
+/// ```
+/// use blocking::Blocking;
+///
+/// # futures::executor::block_on(async {
+/// assert!(!blocking::is_blocking_thread());
+/// assert!(Blocking::spawn(async { blocking::is_blocking_thread() }).await);
+/// # });
+/// ```
+pub fn is_blocking_thread() -> bool {
+    WORKER.with(|worker| !worker.get().is_null())
+}
 
-            // This thread is now becoming idle.
-            inner.idle_count += 1;
+/// Blocks the current thread on a future.
+///
+/// This lets blocking code await async operations, like sending into an async channel, without
+/// pulling in another executor. The thread is parked while the future is pending.
+///
+/// Note that this must not be called from async code running on an async executor because it
+/// blocks the executor's thread.
+///
+/// # Examples
+///
+/// ```
+/// use blocking::Blocking;
+/// use futures::channel::oneshot;
+///
+/// # futures::executor::block_on(async {
+/// let (sender, receiver) = oneshot::channel();
+/// let task = Blocking::spawn(async move {
+///     // This is blocking code, so wait for the message synchronously.
+///     blocking::block_on(receiver).unwrap()
+/// });
+///
+/// sender.send(7).unwrap();
+/// assert_eq!(task.await, 7);
+/// # });
+/// ```
+pub fn block_on<T>(future: impl Future<Output = T>) -> T {
+    /// Unparks the blocked thread when the future is woken.
+    struct Unparker {
+        /// The blocked thread.
+        thread: thread::Thread,
+
+        /// Set to `true` when the future is woken.
+        notified: AtomicBool,
+    }
 
-            // Put the thread to sleep until another task is scheduled.
-            let timeout = Duration::from_millis(500);
-            let (lock, res) = self.cvar.wait_timeout(inner, timeout).unwrap();
-            inner = lock;
+    impl Wake for Unparker {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
 
-            // If there are no tasks after a while, stop this thread.
-            if res.timed_out() && inner.queue.is_empty() {
-                inner.idle_count -= 1;
-                inner.thread_count -= 1;
-                break;
+        fn wake_by_ref(self: &Arc<Self>) {
+            if !self.notified.swap(true, Ordering::SeqCst) {
+                self.thread.unpark();
             }
         }
     }
 
-    /// Schedules a runnable task for execution.
-    fn schedule(&'static self, runnable: Runnable) {
-        let mut inner = self.inner.lock().unwrap();
-        inner.queue.push_back(runnable);
-
-        // Notify a sleeping thread and spawn more threads if needed.
-        self.cvar.notify_one();
-        self.grow_pool(inner);
-    }
-
-    /// Spawns more blocking threads if the pool is overloaded with work.
-    fn grow_pool(&'static self, mut inner: MutexGuard<'static, Inner>) {
-        // If runnable tasks greatly outnumber idle threads and there aren't too many threads
-        // already, then be aggressive: wake all idle threads and spawn one more thread.
-        while inner.queue.len() > inner.idle_count * 5 && inner.thread_count < 500 {
-            // The new thread starts in idle state.
-            inner.idle_count += 1;
-            inner.thread_count += 1;
+    let unparker = Arc::new(Unparker {
+        thread: thread::current(),
+        notified: AtomicBool::new(false),
+    });
+    let waker = Waker::from(unparker.clone());
+    let cx = &mut Context::from_waker(&waker);
 
-            // Notify all existing idle threads because we need to hurry up.
-            self.cvar.notify_all();
+    let mut future = pin::pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return output;
+        }
 
-            // Spawn the new thread.
-            thread::spawn(move || self.main_loop());
+        // Park until the future is woken, ignoring spurious wakeups.
+        while !unparker.notified.swap(false, Ordering::SeqCst) {
+            thread::park();
         }
     }
 }
 
-/// Spawns blocking I/O onto a thread.
+/// A cooperative checkpoint for long-running blocking code.
 ///
-/// Note that `blocking!(expr)` is just syntax sugar for
-/// `Blocking::spawn(async move { expr }).await`.
+/// Awaiting a checkpoint periodically, e.g. between chunks of work in a long loop, lets the task
+/// be canceled and the thread be shared fairly:
 ///
-/// # Examples
+/// - If the task has been canceled (see [`is_canceled()`]), it stops at the checkpoint and its
+///   future gets dropped instead of resuming.
+/// - If other tasks are waiting in the pool's queue, the task yields and gets rescheduled behind
+///   them so that they get a chance to run on this thread.
 ///
-/// Read a file into a string:
+/// Otherwise, the checkpoint completes immediately.
 ///
-/// ```no_run
-/// use blocking::blocking;
-/// use std::fs;
+/// # Examples
+///
+/// ```
+/// use blocking::Blocking;
 ///
 /// # futures::executor::block_on(async {
-/// let contents = blocking!(fs::read_to_string("file.txt"))?;
-/// # std::io::Result::Ok(()) });
+/// let sum = Blocking::spawn(async {
+///     let mut sum = 0u64;
+///     for chunk in 0..100u64 {
+///         sum += (chunk * 1000..(chunk + 1) * 1000).sum::<u64>();
+///         blocking::checkpoint().await;
+///     }
+///     sum
+/// })
+/// .await;
+/// # assert_eq!(sum, (0..100_000).sum());
+/// # });
 /// ```
+pub async fn checkpoint() {
+    let mut yielded = false;
+    future::poll_fn(|cx| {
+        // A canceled task gets dropped once it stops running, so never resume it.
+        if is_canceled() {
+            return Poll::Pending;
+        }
+
+        if !yielded && has_waiting_tasks() {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        Poll::Ready(())
+    })
+    .await
+}
+
+/// Returns `true` if tasks are waiting in the queue of the pool the current thread belongs to.
+fn has_waiting_tasks() -> bool {
+    WORKER.with(|worker| {
+        let executor = worker.get();
+        // SAFETY: The pointer is set by threads in a pool, which keep their executor alive.
+        !executor.is_null() && unsafe { !(*executor).queue.is_empty() }
+    })
+}
+
+/// The priority of a blocking task.
 ///
-/// Spawn a process:
+/// Idle threads in a pool always pick up the runnable task with the highest priority first, and
+/// tasks with the same priority run in the order they were scheduled. Note that a steady stream
+/// of higher priority tasks can starve lower priority ones.
+///
+/// # Examples
 ///
 /// ```no_run
-/// use blocking::blocking;
-/// use std::process::Command;
+/// use blocking::{Blocking, Priority};
+/// use std::io::{stdin, BufRead};
 ///
 /// # futures::executor::block_on(async {
-/// let out = blocking!(Command::new("dir").output())?;
+/// let line = Blocking::spawn_with_priority(Priority::High, async {
+///     let mut line = String::new();
+///     stdin().lock().read_line(&mut line).map(|_| line)
+/// })
+/// .await?;
 /// # std::io::Result::Ok(()) });
 /// ```
-#[macro_export]
-macro_rules! blocking {
-    ($($expr:tt)*) => {
-        $crate::Blocking::spawn(async move { $($expr)* }).await
-    };
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Runs before tasks with normal and low priority.
+    High,
+
+    /// The default priority.
+    #[default]
+    Normal,
+
+    /// Runs only when there are no tasks with high or normal priority.
+    Low,
 }
 
-/// Async I/O that runs on a thread.
-///
-/// This handle represents a future performing some blocking I/O on the special thread pool. The
-/// output of the future can be awaited because [`Blocking`] itself is a future.
-///
-/// It's also possible to interact with [`Blocking`] through [`Stream`], [`AsyncRead`] and
-/// [`AsyncWrite`] traits if the inner type implements [`Iterator`], [`Read`], or [`Write`].
-///
-/// To spawn a future and start it immediately, use [`Blocking::spawn()`]. To create an I/O handle
-/// that will lazily spawn an I/O future on its own, use [`Blocking::new()`].
+/// A handle to a spawned blocking task.
 ///
-/// If the [`Blocking`] handle is dropped, the future performing I/O will be canceled if it hasn't
-/// completed yet. However, note that it's not possible to forcibly cancel blocking I/O, so if the
-/// future is currently running, it won't be canceled until it yields.
+/// Awaiting a [`Task`] returns the output of its future. Dropping a [`Task`] cancels it, but a
+/// task can also be canceled explicitly with [`Task::cancel()`] or left to run in the background
+/// with [`Task::detach()`].
 ///
-/// If writing some data through the [`AsyncWrite`] trait, make sure to flush before dropping the
-/// [`Blocking`] handle or some written data might get lost. Alternatively, await the handle to
-/// complete the pending work and extract the inner blocking I/O handle.
+/// Note that it's not possible to forcibly cancel blocking I/O, so if the future is currently
+/// running, it won't be canceled until it yields.
 ///
 /// # Examples
 ///
 /// ```
-/// use blocking::Blocking;
-/// use futures::prelude::*;
-/// use std::io::stdout;
+/// use blocking::Task;
 ///
 /// # futures::executor::block_on(async {
-/// let mut stdout = Blocking::new(stdout());
-/// stdout.write_all(b"Hello world!").await?;
-///
-/// let inner = stdout.await;
-/// # std::io::Result::Ok(()) });
+/// let task = Task::spawn(async { 1 + 2 });
+/// assert_eq!(task.await, 3);
+/// # });
 /// ```
-pub struct Blocking<T>(State<T>);
+pub struct Task<T> {
+    /// The join handle, which yields an error if the future panicked.
+    handle: Option<async_task::JoinHandle<Result<T, JoinError>, TaskInfo>>,
 
-impl<T> Blocking<T> {
-    /// Wraps a blocking I/O handle into an async interface.
+    /// Turns a failure of the task into its output.
+    on_error: fn(JoinError) -> T,
+
+    /// A timer after which the task is canceled, and the output to return in that case.
+    timeout: Option<(Timer, fn() -> T)>,
+}
+
+impl<T: Send + 'static> Task<T> {
+    /// Spawns a future that is allowed to do blocking I/O onto the global pool.
+    ///
+    /// If the future panics, awaiting the task resumes the panic with its original payload.
     ///
     /// # Examples
     ///
     /// ```no_run
-    /// use blocking::Blocking;
-    /// use std::io::stdin;
+    /// use blocking::Task;
+    /// use std::fs;
     ///
     /// # futures::executor::block_on(async {
-    /// // Create an async handle to standard input.
-    /// let stdin = Blocking::new(stdin());
+    /// let contents = Task::spawn(async { fs::read_to_string("file.txt") }).await?;
     /// # std::io::Result::Ok(()) });
     /// ```
-    pub fn new(io: T) -> Blocking<T> {
-        Blocking(State::Idle(Some(Box::new(io))))
+    pub fn spawn(future: impl Future<Output = T> + Send + 'static) -> Task<T> {
+        Executor::global().spawn(future)
     }
 
-    /// Gets a mutable reference to the blocking I/O handle.
-    ///
-    /// This is an async method because the I/O handle might be on a different thread and needs to
-    /// be moved onto the current thread before we can get a reference to it.
+    /// Spawns a future that is allowed to do blocking I/O onto the given pool.
     ///
     /// # Examples
     ///
     /// ```no_run
-    /// use blocking::Blocking;
-    /// use std::fs::File;
+    /// use blocking::{Pool, Task};
+    /// use std::fs;
     ///
     /// # futures::executor::block_on(async {
-    /// let mut file = Blocking::new(File::create("file.txt")?);
-    /// let metadata = file.get_mut().await.metadata()?;
+    /// let pool = Pool::new();
+    /// let contents = Task::spawn_on(&pool, async { fs::read_to_string("file.txt") }).await?;
     /// # std::io::Result::Ok(()) });
     /// ```
-    pub async fn get_mut(&mut self) -> &mut T {
-        // Wait for the running task to stop and ignore I/O errors if there are any.
-        let _ = future::poll_fn(|cx| self.poll_stop(cx)).await;
-
-        // Assume idle state and get a reference to the inner value.
-        match &mut self.0 {
-            State::Idle(t) => t.as_mut().expect("inner value was taken out"),
-            State::Streaming(..) | State::Reading(..) | State::Writing(..) | State::Task(..) => {
-                unreachable!("when stopped, the state machine must be in idle state");
-            }
-        }
+    pub fn spawn_on(pool: &Pool, future: impl Future<Output = T> + Send + 'static) -> Task<T> {
+        pool.executor.spawn(future)
     }
 
-    /// Extracts the inner blocking I/O handle.
+    /// Spawns a future that doesn't implement [`Send`] onto a dedicated thread.
     ///
-    /// This is an async method because the I/O handle might be on a different thread and needs to
-    /// be moved onto the current thread before we can extract it.
+    /// The future is created by calling `f` on the new thread and stays on that thread until it
+    /// completes, so it may hold values like [`Rc`](std::rc::Rc). Only `f` and the output of the
+    /// future have to be [`Send`]. The thread doesn't belong to any pool and stops once the task
+    /// completes or is canceled.
     ///
-    /// Note that awaiting this method is equivalent to awaiting the [`Blocking`] handle.
+    /// # Panics
+    ///
+    /// Panics if the thread can't be spawned.
     ///
     /// # Examples
     ///
-    /// ```no_run
-    /// use blocking::Blocking;
-    /// use futures::prelude::*;
-    /// use std::fs::File;
+    /// ```
+    /// use blocking::Task;
+    /// use std::rc::Rc;
     ///
     /// # futures::executor::block_on(async {
-    /// let mut file = Blocking::new(File::create("file.txt")?);
-    /// file.write_all(b"Hello world!").await?;
-    ///
-    /// let file = file.into_inner().await;
-    /// # std::io::Result::Ok(()) });
+    /// let task = Task::spawn_local(|| async {
+    ///     let value = Rc::new(7);
+    ///     *value * 6
+    /// });
+    /// assert_eq!(task.await, 42);
+    /// # });
     /// ```
-    pub async fn into_inner(self) -> T {
-        // There's a bug in rustdoc causing it to render `mut self` as `__arg0: Self`, so we just
-        // bind `self` to a local mutable variable.
-        let mut this = self;
+    pub fn spawn_local<F, Fut>(f: F) -> Task<T>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = T> + 'static,
+    {
+        // Runnables are sent to the dedicated thread, which runs them until the task is destroyed
+        // and the sender inside the schedule function gets dropped.
+        #[cfg(not(all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+        let (sender, receiver) = std_mpsc::channel::<Runnable>();
+        #[cfg(not(all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+        let schedule = move |runnable| {
+            let _ = sender.send(runnable);
+        };
 
-        // Wait for the running task to stop and ignore I/O errors if there are any.
-        let _ = future::poll_fn(|cx| this.poll_stop(cx)).await;
+        // Without threads, every task already runs on the thread that schedules it.
+        #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+        let schedule = |runnable| Executor::global().schedule(runnable);
+
+        let future = LocalFuture(async move { f().await });
+        let future = async move { catch_unwind(future).await.map_err(JoinError::Panicked) };
+        let info = TaskInfo::new(Priority::Normal, None);
+        let (runnable, handle) = async_task::spawn(future, schedule, info);
+
+        #[cfg(not(all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+        thread::Builder::new()
+            .name("blocking-local".to_string())
+            .spawn(move || {
+                for runnable in receiver {
+                    let _ = panic::catch_unwind(|| run_current(runnable));
+                }
+            })
+            .expect("cannot spawn local task thread");
 
-        // Assume idle state and extract the inner value.
-        match &mut this.0 {
-            State::Idle(t) => *t.take().expect("inner value was taken out"),
-            State::Streaming(..) | State::Reading(..) | State::Writing(..) | State::Task(..) => {
-                unreachable!("when stopped, the state machine must be in idle state");
-            }
+        runnable.schedule();
+        Task {
+            handle: Some(handle),
+            on_error: task_failed,
+            timeout: None,
         }
     }
+}
 
-    /// Waits for the running task to stop.
+impl<T> Task<T> {
+    /// Cancels the task and waits until it stops.
     ///
-    /// On success, the state machine is moved into the idle state.
-    fn poll_stop(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        loop {
-            match &mut self.0 {
-                State::Idle(_) => return Poll::Ready(Ok(())),
-
-                State::Streaming(any, task) => {
-                    // Drop the receiver to close the channel. This stops the `send()` operation in
-                    // the task, after which the task returns the iterator back.
-                    any.take();
-
-                    // Poll the task to retrieve the iterator.
-                    let iter = futures::ready!(Pin::new(task).poll(cx));
-                    self.0 = State::Idle(Some(iter));
-                }
-
-                State::Reading(reader, task) => {
-                    // Drop the reader to close the pipe. This stops the `futures::io::copy`
-                    // operation in the task, after which the task returns the I/O handle back.
-                    reader.take();
-
-                    // Poll the task to retrieve the I/O handle.
-                    let (res, io) = futures::ready!(Pin::new(task).poll(cx));
-                    // Make sure to move into the idle state before reporting errors.
-                    self.0 = State::Idle(Some(io));
-                    res?;
-                }
-
-                State::Writing(writer, task) => {
-                    // Drop the writer to close the pipe. This stops the `futures::io::copy`
-                    // operation in the task, after which the task flushes the I/O handle and
-                    // returns it back.
-                    writer.take();
-
-                    // Poll the task to retrieve the I/O handle.
-                    let (res, io) = futures::ready!(Pin::new(task).poll(cx));
-                    // Make sure to move into the idle state before reporting errors.
-                    self.0 = State::Idle(Some(io));
-                    res?;
-                }
-
-                State::Task(task) => {
-                    // Poll the task to retrieve the inner value.
-                    let t = futures::ready!(Pin::new(task).poll(cx));
-                    self.0 = State::Idle(Some(Box::new(t)));
-                }
-            }
+    /// If the future is currently running on a thread, this waits until it yields and gets
+    /// dropped. Any output the task may have produced is dropped as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Task;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let task = Task::spawn(async { thread::sleep(Duration::from_millis(100)) });
+    /// task.cancel().await;
+    /// # });
+    /// ```
+    pub async fn cancel(mut self) {
+        self.cancel_handle();
+        if let Some(handle) = self.handle.take() {
+            handle.await;
         }
     }
-}
 
-impl<T: Send + 'static> Blocking<T> {
-    /// Spawns a future that is allowed to do blocking I/O.
+    /// Detaches the task to let it run to completion in the background.
     ///
-    /// If the [`Blocking`] handle is dropped, the future will be canceled if it hasn't completed
-    /// yet. However, note that it's not possible to forcibly cancel blocking I/O, so if the future
-    /// is currently running, it won't be canceled until it yields.
+    /// The output of the task is dropped once it completes.
     ///
     /// # Examples
     ///
-    /// ```no_run
-    /// use blocking::Blocking;
-    /// use std::fs;
+    /// ```
+    /// use blocking::Task;
     ///
-    /// # futures::executor::block_on(async {
-    /// let contents = Blocking::spawn(async { fs::read_to_string("file.txt") }).await?;
-    /// # std::io::Result::Ok(()) });
+    /// Task::spawn(async { println!("Hello from the background!") }).detach();
     /// ```
-    pub fn spawn(future: impl Future<Output = T> + Send + 'static) -> Blocking<T> {
-        let task = Executor::spawn(future);
-        Blocking(State::Task(task))
+    pub fn detach(mut self) {
+        // Dropping a join handle detaches the task.
+        self.handle.take();
     }
 }
 
-impl<T> Future for Blocking<T> {
+impl<T> Task<T> {
+    /// Cancels the task and flags it as canceled for blocking code that checks the flag.
+    fn cancel_handle(&self) {
+        if let Some(handle) = &self.handle {
+            handle.tag().canceled.store(true, Ordering::SeqCst);
+            handle.cancel();
+        }
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        self.cancel_handle();
+    }
+}
+
+impl<T> Future for Task<T> {
     type Output = T;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        // Wait for the running task to stop and ignore I/O errors if there are any.
-        let _ = futures::ready!(self.poll_stop(cx));
-
-        // Assume idle state and extract the inner value.
-        match &mut self.0 {
-            State::Idle(t) => Poll::Ready(*t.take().expect("inner value was taken out")),
-            State::Streaming(..) | State::Reading(..) | State::Writing(..) | State::Task(..) => {
-                unreachable!("when stopped, the state machine must be in idle state");
+        let output = match Pin::new(self.handle.as_mut().unwrap()).poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => {
+                // If the task has timed out, cancel it and return right away rather than waiting
+                // for the blocking code to yield.
+                if let Some((timer, on_timeout)) = &mut self.timeout {
+                    if Pin::new(timer).poll(cx).is_ready() {
+                        let on_timeout = *on_timeout;
+                        self.timeout = None;
+                        self.cancel_handle();
+                        return Poll::Ready(on_timeout());
+                    }
+                }
+                return Poll::Pending;
             }
+        };
+        match output.unwrap_or(Err(JoinError::Canceled)) {
+            Ok(value) => Poll::Ready(value),
+            Err(err) => Poll::Ready((self.on_error)(err)),
         }
     }
 }
 
-/// Current state of a blocking task.
-enum State<T> {
-    /// There is no blocking task.
-    ///
-    /// The inner value is readily available, unless it has already been extracted. The value is
-    /// extracted out by [`Blocking::into_inner()`], [`AsyncWrite::poll_close()`], or by awaiting
-    /// [`Blocking`].
-    Idle(Option<Box<T>>),
-
-    /// A task was spawned by [`Blocking::spawn()`] and is still running.
-    Task(Task<T>),
+/// A future that doesn't implement [`Send`], spawned by [`Task::spawn_local()`].
+struct LocalFuture<F>(F);
 
-    /// The inner value is an [`Iterator`] currently iterating in a task.
-    ///
-    /// The `dyn Any` value here is a `mpsc::Receiver<<T as Iterator>::Item>`.
-    Streaming(Option<Box<dyn Any>>, Task<Box<T>>),
+/// The future is created on the dedicated thread of its task, and only that thread ever polls or
+/// drops it because it's the only one running the task.
+unsafe impl<F> Send for LocalFuture<F> {}
 
-    /// The inner value is a [`Read`] currently reading in a task.
-    Reading(Option<Reader>, Task<(io::Result<()>, Box<T>)>),
+impl<F: Future> Future for LocalFuture<F> {
+    type Output = F::Output;
 
-    /// The inner value is a [`Write`] currently writing in a task.
-    Writing(Option<Writer>, Task<(io::Result<()>, Box<T>)>),
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: The inner future is never moved out of the wrapper.
+        unsafe { self.map_unchecked_mut(|f| &mut f.0) }.poll(cx)
+    }
 }
 
-impl<T: Iterator + Send + 'static> Stream for Blocking<T>
-where
-    T::Item: Send + 'static,
-{
-    type Item = T::Item;
-
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T::Item>> {
-        loop {
-            match &mut self.0 {
-                // If not in idle or active streaming state, stop the running task.
-                State::Task(..)
-                | State::Streaming(None, _)
-                | State::Reading(..)
-                | State::Writing(..) => {
-                    // Wait for the running task to stop.
-                    let _ = futures::ready!(self.poll_stop(cx));
-                }
-
-                // If idle, start a streaming task.
-                State::Idle(iter) => {
-                    // If idle, take the iterator out to run it on a blocking task.
-                    let mut iter = iter.take().unwrap();
+/// Runs a future to completion, catching panics raised while polling it.
+async fn catch_unwind<F: Future>(future: F) -> Result<F::Output, Box<dyn Any + Send>> {
+    let mut future = pin::pin!(future);
+    future::poll_fn(|cx| {
+        match panic::catch_unwind(panic::AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    })
+    .await
+}
 
-                    // This channel capacity seems to work well in practice. If it's too low, there
-                    // will be too much synchronization between tasks. If too high, memory
-                    // consumption increases.
-                    let (mut sender, receiver) = mpsc::channel(8 * 1024); // 8192 items
+/// Panics on a failed task, which is the default way of handling failures.
+///
+/// If the task panicked, its original panic payload is propagated.
+fn task_failed<T>(err: JoinError) -> T {
+    match err {
+        JoinError::Panicked(payload) => panic::resume_unwind(payload),
+        JoinError::Canceled => panic!("task was canceled"),
+    }
+}
 
-                    // Spawn a blocking task that runs the iterator and returns it when done.
-                    let task = Executor::spawn(async move {
-                        for item in &mut iter {
-                            if sender.send(item).await.is_err() {
-                                break;
-                            }
-                        }
-                        iter
-                    });
+/// The reason a blocking task has failed.
+///
+/// This error is returned by tasks spawned with [`Blocking::spawn_catching()`].
+pub enum JoinError {
+    /// The task panicked, with the given payload.
+    Panicked(Box<dyn Any + Send>),
 
-                    // Move into the busy state and poll again.
-                    self.0 = State::Streaming(Some(Box::new(receiver)), task);
-                }
+    /// The task was canceled before it completed, e.g. because the pool shut down.
+    Canceled,
+}
 
-                // If streaming, receive an item.
-                State::Streaming(Some(any), task) => {
-                    let receiver = any.downcast_mut::<mpsc::Receiver<T::Item>>().unwrap();
+impl JoinError {
+    /// Returns `true` if the task panicked.
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panicked(_))
+    }
 
-                    // Poll the channel.
-                    let opt = futures::ready!(Pin::new(receiver).poll_next(cx));
+    /// Returns `true` if the task was canceled.
+    pub fn is_canceled(&self) -> bool {
+        matches!(self, JoinError::Canceled)
+    }
+}
 
-                    // If the channel is closed, retrieve the iterator back from the blocking task.
-                    // This is not really a required step, but it's cleaner to drop the iterator on
-                    // the same thread that created it.
-                    if opt.is_none() {
-                        // Poll the task to retrieve the iterator.
-                        let iter = futures::ready!(Pin::new(task).poll(cx));
-                        self.0 = State::Idle(Some(iter));
-                    }
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Panicked(payload) => f
+                .debug_tuple("Panicked")
+                .field(&panic_message(&**payload))
+                .finish(),
+            JoinError::Canceled => f.write_str("Canceled"),
+        }
+    }
+}
 
-                    return Poll::Ready(opt);
-                }
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Panicked(payload) => {
+                write!(f, "task panicked: {}", panic_message(&**payload))
             }
+            JoinError::Canceled => f.write_str("task was canceled"),
         }
     }
 }
 
-impl<T: Read + Send + 'static> AsyncRead for Blocking<T> {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut [u8],
-    ) -> Poll<io::Result<usize>> {
-        loop {
-            match &mut self.0 {
-                // If not in idle or active reading state, stop the running task.
-                State::Task(..)
-                | State::Reading(None, _)
-                | State::Streaming(..)
-                | State::Writing(..) => {
-                    // Wait for the running task to stop.
-                    futures::ready!(self.poll_stop(cx))?;
-                }
-
-                // If idle, start a reading task.
-                State::Idle(io) => {
-                    // If idle, take the I/O handle out to read it on a blocking task.
-                    let mut io = io.take().unwrap();
-
-                    // This pipe capacity seems to work well in practice. If it's too low, there
-                    // will be too much synchronization between tasks. If too high, memory
-                    // consumption increases.
-                    let (reader, mut writer) = pipe(8 * 1024 * 1024); // 8 MB
-
-                    // Spawn a blocking task that reads and returns the I/O handle when done.
-                    let task = Executor::spawn(async move {
-                        // Copy bytes from the I/O handle into the pipe until the pipe is closed or
-                        // an error occurs.
-                        loop {
-                            match future::poll_fn(|cx| writer.poll_write(cx, &mut io)).await {
-                                Ok(0) => return (Ok(()), io),
-                                Ok(_) => {}
-                                Err(err) => return (Err(err), io),
-                            }
-                        }
-                    });
+impl std::error::Error for JoinError {}
 
-                    // Move into the busy state and poll again.
-                    self.0 = State::Reading(Some(reader), task);
-                }
+/// An error returned when a blocking task didn't complete in time.
+///
+/// This error is returned by tasks spawned with [`Blocking::spawn_timeout()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimedOut(());
 
-                // If reading, read bytes from the pipe.
-                State::Reading(Some(reader), task) => {
-                    // Poll the pipe.
-                    let n = futures::ready!(Pin::new(reader).poll_read(cx, buf))?;
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("blocking task timed out")
+    }
+}
 
-                    // If the pipe is closed, retrieve the I/O handle back from the blocking task.
-                    // This is not really a required step, but it's cleaner to drop the handle on
-                    // the same thread that created it.
-                    if n == 0 {
-                        // Poll the task to retrieve the I/O handle.
-                        let (res, io) = futures::ready!(Pin::new(task).poll(cx));
-                        // Make sure to move into the idle state before reporting errors.
-                        self.0 = State::Idle(Some(io));
-                        res?;
-                    }
+impl std::error::Error for TimedOut {}
 
-                    return Poll::Ready(Ok(n));
-                }
-            }
-        }
+impl From<TimedOut> for io::Error {
+    fn from(err: TimedOut) -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, err)
     }
 }
 
-impl<T: Write + Send + 'static> AsyncWrite for Blocking<T> {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<io::Result<usize>> {
-        loop {
-            match &mut self.0 {
-                // If not in idle or active writing state, stop the running task.
-                State::Task(..)
-                | State::Writing(None, _)
-                | State::Streaming(..)
-                | State::Reading(..) => {
-                    // Wait for the running task to stop.
-                    futures::ready!(self.poll_stop(cx))?;
-                }
+/// The default limit on the number of threads in the pool.
+const DEFAULT_MAX_THREADS: usize = 500;
 
-                // If idle, start the writing task.
-                State::Idle(io) => {
-                    // If idle, take the I/O handle out to write on a blocking task.
-                    let mut io = io.take().unwrap();
-
-                    // This pipe capacity seems to work well in practice. If it's too low, there will
-                    // be too much synchronization between tasks. If too high, memory consumption
-                    // increases.
-                    let (mut reader, writer) = pipe(8 * 1024 * 1024); // 8 MB
-
-                    // Spawn a blocking task that writes and returns the I/O handle when done.
-                    let task = Executor::spawn(async move {
-                        // Copy bytes from the pipe into the I/O handle until the pipe is closed or an
-                        // error occurs. Flush the I/O handle at the end.
-                        loop {
-                            match future::poll_fn(|cx| reader.poll_read(cx, &mut io)).await {
-                                Ok(0) => return (io.flush(), io),
-                                Ok(_) => {}
-                                Err(err) => {
-                                    let _ = io.flush();
-                                    return (Err(err), io);
-                                }
-                            }
-                        }
-                    });
+/// The default limit on the number of threads per available CPU on Linux.
+#[cfg(target_os = "linux")]
+const DEFAULT_THREADS_PER_CPU: usize = 128;
 
-                    // Move into the busy state.
-                    self.0 = State::Writing(Some(writer), task);
-                }
+/// The environment variable that overrides the default limit on the number of threads.
+const MAX_THREADS_ENV: &str = "BLOCKING_MAX_THREADS";
 
-                // If writing,write more bytes into the pipe.
-                State::Writing(Some(writer), _) => return Pin::new(writer).poll_write(cx, buf),
-            }
-        }
-    }
+/// The default capacity of the pipe a [`Blocking`] handle reads and writes through.
+///
+/// This capacity seems to work well in practice. If it's too low, there will be too much
+/// synchronization between tasks. If too high, memory consumption increases.
+const DEFAULT_PIPE_CAPACITY: usize = 8 * 1024 * 1024; // 8 MB
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        loop {
-            match &mut self.0 {
-                // If not in idle state, stop the running task.
-                State::Task(..)
-                | State::Streaming(..)
-                | State::Writing(..)
-                | State::Reading(..) => {
-                    // Wait for the running task to stop.
-                    futures::ready!(self.poll_stop(cx))?;
-                }
+/// Initial capacity of a pipe, which then grows up to its maximum capacity.
+const INITIAL_PIPE_CAPACITY: usize = 64 * 1024; // 64 KB
 
-                // Idle implies flushed.
-                State::Idle(_) => return Poll::Ready(Ok(())),
-            }
-        }
-    }
+/// The capacity of the pipe of the [`stdin()`] handle.
+const STDIN_PIPE_CAPACITY: usize = 8 * 1024; // 8 KB
 
-    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        // First, make sure the I/O handle is flushed.
-        futures::ready!(Pin::new(&mut *self).poll_flush(cx))?;
+/// The capacity of the pipes of the [`stdout()`] and [`stderr()`] handles.
+const STDOUT_PIPE_CAPACITY: usize = 64 * 1024; // 64 KB
 
-        // Then move into the idle state with no I/O handle, thus dropping it.
-        self.0 = State::Idle(None);
-        Poll::Ready(Ok(()))
-    }
-}
+/// The maximum total size of spare pipe buffers kept for reuse.
+const SPARE_BUFFERS_CAPACITY: usize = 8 * 1024 * 1024; // 8 MB
 
-/// Creates a bounded single-producer single-consumer pipe.
-///
-/// A pipe is a ring buffer of `cap` bytes that implements traits [`AsyncRead`] and [`AsyncWrite`].
+/// The default number of bytes copied into or out of a pipe before waking the other side.
+const DEFAULT_CHUNK_SIZE: usize = 128 * 1024; // 128 KB
+
+/// The default number of items buffered when streaming an iterator in a [`Blocking`] handle.
 ///
-/// When the sender is dropped, remaining bytes in the pipe can still be read. After that, attempts
-/// to read will result in `Ok(0)`, i.e. they will always 'successfully' read 0 bytes.
+/// This channel capacity seems to work well in practice. If it's too low, there will be too much
+/// synchronization between tasks. If too high, memory consumption increases.
+#[cfg(feature = "stream")]
+const DEFAULT_STREAM_CAPACITY: usize = 8 * 1024; // 8192 items
+
+/// The default amount of time an idle thread waits for a task before shutting down.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The global blocking executor.
 ///
-/// When the receiver is dropped, the pipe is closed and no more bytes and be written into it.
-/// Further writes will result in `Ok(0)`, i.e. they will always 'successfully' write 0 bytes.
-fn pipe(cap: usize) -> (Reader, Writer) {
-    assert!(cap > 0, "capacity must be positive");
-    assert!(cap.checked_mul(2).is_some(), "capacity is too large");
+/// It is initialized either explicitly by [`PoolBuilder::build_global()`] or lazily with the
+/// default configuration the first time a blocking task is spawned.
+static EXECUTOR: OnceCell<Arc<Executor>> = OnceCell::new();
 
-    // Allocate the ring buffer.
-    let mut v = Vec::with_capacity(cap);
-    let buffer = v.as_mut_ptr();
-    mem::forget(v);
+/// A callback invoked on a thread in the pool.
+type Hook = Arc<dyn Fn() + Send + Sync>;
 
-    let inner = Arc::new(Pipe {
-        head: AtomicUsize::new(0),
-        tail: AtomicUsize::new(0),
-        reader: AtomicWaker::new(),
-        writer: AtomicWaker::new(),
-        closed: AtomicBool::new(false),
-        buffer,
-        cap,
-    });
+/// A callback invoked when a task panics.
+type PanicHandler = Arc<dyn Fn(Option<&str>, &(dyn Any + Send)) + Send + Sync>;
 
-    let r = Reader {
-        inner: inner.clone(),
-        head: 0,
-        tail: 0,
-    };
+/// A callback invoked when a task runs for too long.
+type WatchdogHook = Arc<dyn Fn(&LongRunningTask) + Send + Sync>;
 
-    let w = Writer {
-        inner,
-        head: 0,
-        tail: 0,
-        zeroed_until: 0,
-    };
+/// A callback deciding whether a failed operation is retried.
+type RetryPredicate = Arc<dyn Fn(&io::Error) -> bool + Send + Sync>;
 
-    (r, w)
-}
+/// Configuration for a blocking thread pool.
+///
+/// The global pool is created lazily the first time a blocking task is spawned. Its configuration
+/// can be changed by calling [`PoolBuilder::build_global()`] before that happens. Additional
+/// pools can be created with [`PoolBuilder::build()`].
+///
+/// # Examples
+///
+/// ```
+/// use blocking::PoolBuilder;
+/// use std::time::Duration;
+///
+/// PoolBuilder::new()
+///     .max_threads(64)
+///     .idle_timeout(Duration::from_secs(5))
+///     .thread_name("blocking")
+///     .stack_size(256 * 1024)
+///     .build_global()
+///     .expect("the global pool has already been initialized");
+/// ```
+pub struct PoolBuilder {
+    /// Maximum number of threads in the pool.
+    max_threads: usize,
 
-/// The reading side of a pipe.
-#[derive(Debug)]
-struct Reader {
-    /// The inner ring buffer.
-    inner: Arc<Pipe>,
+    /// Number of threads kept alive even when idle.
+    min_threads: usize,
 
-    /// The head index, moved by the reader, in the range `0..2*cap`.
+    /// How long an idle thread waits for a task before shutting down.
+    idle_timeout: Duration,
+
+    /// Prefix for names of threads in the pool.
+    thread_name: Option<String>,
+
+    /// Stack size for threads in the pool.
+    stack_size: Option<usize>,
+
+    /// Scheduling priority of threads in the pool.
+    thread_priority: Option<ThreadPriority>,
+
+    /// Quality-of-service class of threads in the pool on Apple platforms.
+    qos_class: Option<QosClass>,
+
+    /// CPUs threads in the pool are pinned to.
+    cpu_affinity: Option<Vec<usize>>,
+
+    /// Called on each thread right after it starts.
+    after_start: Option<Hook>,
+
+    /// Called on each thread right before it stops.
+    before_stop: Option<Hook>,
+
+    /// Observes events in the pool.
+    observer: Option<Arc<dyn ExecutorObserver>>,
+
+    /// Reports tasks running for longer than a threshold.
+    watchdog: Option<(Duration, WatchdogHook)>,
+
+    /// Called when a task panics.
+    panic_handler: Option<PanicHandler>,
+
+    /// Whether tasks spawned from threads in the pool first run inline.
+    run_nested_inline: bool,
+
+    /// Whether tasks run on the Windows system thread pool.
+    system_thread_pool: bool,
+
+    /// Runs tasks instead of threads spawned by the pool.
+    backend: Option<Arc<dyn BlockingBackend>>,
+
+    /// Whether tasks only run when stepped manually.
+    manual: bool,
+
+    /// Faults injected into the pool for testing.
+    #[cfg(feature = "test-util")]
+    fault_injection: FaultInjection,
+}
+
+impl PoolBuilder {
+    /// Creates a builder with the default configuration.
+    ///
+    /// By default, the pool grows up to 500 threads, idle threads shut down after 500
+    /// milliseconds, threads are unnamed, and they get the default stack size of the standard
+    /// library.
+    ///
+    /// On Linux, the default limit is further capped at 128 threads per available CPU, taking the
+    /// CPU quota and cpuset of the container into account. This way, a container with half a CPU
+    /// doesn't end up with hundreds of runnable threads fighting over a single core.
+    ///
+    /// The default limit on the number of threads can be overridden by setting the
+    /// `BLOCKING_MAX_THREADS` environment variable to a positive number.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let builder = PoolBuilder::new();
+    /// ```
+    pub fn new() -> PoolBuilder {
+        // Read the limit from the environment, ignoring values that are not positive numbers.
+        let max_threads = env::var(MAX_THREADS_ENV)
+            .ok()
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or_else(default_max_threads);
+
+        PoolBuilder {
+            max_threads,
+            min_threads: 0,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            thread_name: None,
+            stack_size: None,
+            thread_priority: None,
+            qos_class: None,
+            cpu_affinity: None,
+            after_start: None,
+            before_stop: None,
+            observer: None,
+            watchdog: None,
+            panic_handler: None,
+            run_nested_inline: false,
+            system_thread_pool: false,
+            backend: None,
+            manual: false,
+            #[cfg(feature = "test-util")]
+            fault_injection: FaultInjection::new(),
+        }
+    }
+
+    /// Sets the maximum number of threads in the pool.
+    ///
+    /// Once this limit is hit, a running task has to complete or yield before other tasks get a
+    /// chance to continue running.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_threads` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let builder = PoolBuilder::new().max_threads(16);
+    /// ```
+    pub fn max_threads(mut self, max_threads: usize) -> PoolBuilder {
+        assert!(
+            max_threads > 0,
+            "maximum number of threads must be positive"
+        );
+        self.max_threads = max_threads;
+        self
+    }
+
+    /// Sets the number of threads that are spawned up front and kept alive even when idle.
+    ///
+    /// Spawning a thread takes a while, so the first blocking task after an idle period normally
+    /// has to wait for it. Keeping a few warm threads around removes that latency at the cost of
+    /// some memory. The minimum is capped by the maximum number of threads, and the threads are
+    /// released once all handles to the pool have been dropped. By default, no threads are kept
+    /// alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let builder = PoolBuilder::new().min_threads(2);
+    /// ```
+    pub fn min_threads(mut self, min_threads: usize) -> PoolBuilder {
+        self.min_threads = min_threads;
+        self
+    }
+
+    /// Sets how long an idle thread waits for a task before shutting down.
+    ///
+    /// Each wait is stretched by a random amount of up to half the timeout, so that when the load
+    /// drops, idle threads shut down gradually rather than all at once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    /// use std::time::Duration;
+    ///
+    /// let builder = PoolBuilder::new().idle_timeout(Duration::from_secs(1));
+    /// ```
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> PoolBuilder {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Sets the name prefix for threads in the pool.
+    ///
+    /// Threads are named by appending a sequence number to the prefix, e.g. `blocking-0`,
+    /// `blocking-1`, and so on.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let builder = PoolBuilder::new().thread_name("blocking");
+    /// ```
+    pub fn thread_name(mut self, prefix: impl Into<String>) -> PoolBuilder {
+        self.thread_name = Some(prefix.into());
+        self
+    }
+
+    /// Sets the stack size in bytes for threads in the pool.
+    ///
+    /// Blocking tasks that recurse deeply (for example, parsers and deserializers) may need
+    /// larger stacks, while small stacks make it cheaper to run many threads at once. If not set,
+    /// threads get the default stack size of the standard library, which can also be changed
+    /// through the `RUST_MIN_STACK` environment variable.
+    ///
+    /// Note that the operating system may round the stack size up to its minimum or page size.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let builder = PoolBuilder::new().stack_size(8 * 1024 * 1024);
+    /// ```
+    pub fn stack_size(mut self, stack_size: usize) -> PoolBuilder {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Sets the scheduling priority of threads in the pool.
+    ///
+    /// For example, bulk background I/O can run with a lower priority so that it doesn't compete
+    /// with latency-sensitive threads. The priority is set using platform APIs on Linux, Android,
+    /// macOS, iOS, and Windows, and is ignored on other platforms. Raising the priority above
+    /// normal may require special privileges, and if the priority can't be set, threads simply
+    /// run with the default priority.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::{PoolBuilder, ThreadPriority};
+    ///
+    /// let builder = PoolBuilder::new().thread_priority(ThreadPriority::BelowNormal);
+    /// ```
+    pub fn thread_priority(mut self, priority: ThreadPriority) -> PoolBuilder {
+        self.thread_priority = Some(priority);
+        self
+    }
+
+    /// Sets the quality-of-service class of threads in the pool on macOS and iOS.
+    ///
+    /// The QoS class tells the system how important the work is, which affects CPU scheduling, I/O
+    /// throttling, and timer coalescing. Tagging blocking I/O as [`QosClass::Utility`] or
+    /// [`QosClass::Background`] keeps it from competing with UI or audio threads. This setting is
+    /// ignored on other platforms.
+    ///
+    /// On Apple platforms, QoS classes are the preferred way of prioritizing threads, so this
+    /// shouldn't be combined with [`PoolBuilder::thread_priority()`], which opts threads out of
+    /// QoS scheduling.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::{PoolBuilder, QosClass};
+    ///
+    /// let builder = PoolBuilder::new().qos_class(QosClass::Utility);
+    /// ```
+    pub fn qos_class(mut self, qos_class: QosClass) -> PoolBuilder {
+        self.qos_class = Some(qos_class);
+        self
+    }
+
+    /// Pins threads in the pool to the given set of CPUs.
+    ///
+    /// This keeps blocking threads off the cores used by latency-sensitive threads, like the
+    /// async reactor. CPUs are identified by their zero-based index. Affinity is supported on
+    /// Linux, Android, and Windows (where only the first 64 CPUs can be used), and is ignored on
+    /// other platforms. If the affinity can't be set, threads simply run on any CPU.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// // Keep CPU 0 free for the async reactor.
+    /// let builder = PoolBuilder::new().cpu_affinity(1..4);
+    /// ```
+    pub fn cpu_affinity(mut self, cpus: impl IntoIterator<Item = usize>) -> PoolBuilder {
+        self.cpu_affinity = Some(cpus.into_iter().collect());
+        self
+    }
+
+    /// Sets a callback invoked on each thread in the pool right after it starts.
+    ///
+    /// This is useful for initializing thread-local state, e.g. COM on Windows.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let builder = PoolBuilder::new().after_start(|| println!("thread started"));
+    /// ```
+    pub fn after_start(mut self, f: impl Fn() + Send + Sync + 'static) -> PoolBuilder {
+        self.after_start = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets a callback invoked on each thread in the pool right before it stops.
+    ///
+    /// This is useful for tearing down thread-local state set up in
+    /// [`PoolBuilder::after_start()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let builder = PoolBuilder::new().before_stop(|| println!("thread stopping"));
+    /// ```
+    pub fn before_stop(mut self, f: impl Fn() + Send + Sync + 'static) -> PoolBuilder {
+        self.before_stop = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets an observer that gets notified of events in the pool.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::{ExecutorObserver, PoolBuilder};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// struct SpawnCounter(AtomicUsize);
+    ///
+    /// impl ExecutorObserver for SpawnCounter {
+    ///     fn task_spawned(&self) {
+    ///         self.0.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// }
+    ///
+    /// let builder = PoolBuilder::new().observer(SpawnCounter(AtomicUsize::new(0)));
+    /// ```
+    pub fn observer(mut self, observer: impl ExecutorObserver + 'static) -> PoolBuilder {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Sets a watchdog that reports tasks monopolizing a thread for longer than `threshold`.
+    ///
+    /// The callback is invoked once for every run of a task that exceeds the threshold, while the
+    /// task is still running, which helps find tasks that should be split up or moved to a
+    /// dedicated thread. When the `log` feature is enabled, a warning is logged as well.
+    ///
+    /// The callback is invoked on an internal timer thread, so it should be cheap and must not
+    /// block.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    /// use std::time::Duration;
+    ///
+    /// let builder = PoolBuilder::new().watchdog(Duration::from_secs(10), |task| {
+    ///     eprintln!("a blocking task has been running for {:?}", task.elapsed());
+    /// });
+    /// ```
+    pub fn watchdog(
+        mut self,
+        threshold: Duration,
+        f: impl Fn(&LongRunningTask) + Send + Sync + 'static,
+    ) -> PoolBuilder {
+        self.watchdog = Some((threshold, Arc::new(f)));
+        self
+    }
+
+    /// Sets a callback invoked when a task in the pool panics.
+    ///
+    /// The callback receives the name of the task, if it was spawned with one, and the panic
+    /// payload. It is invoked on the thread that ran the task, before the panic is reported to
+    /// whoever awaits the task, which makes it a good place for logging, counting, or aborting the
+    /// process.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let builder = PoolBuilder::new().panic_handler(|name, _payload| {
+    ///     eprintln!("blocking task {:?} panicked", name);
+    ///     std::process::abort();
+    /// });
+    /// ```
+    pub fn panic_handler(
+        mut self,
+        f: impl Fn(Option<&str>, &(dyn Any + Send)) + Send + Sync + 'static,
+    ) -> PoolBuilder {
+        self.panic_handler = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets whether tasks spawned from threads in the pool run inline.
+    ///
+    /// When enabled, a task spawned onto the pool by code that is already running on one of its
+    /// threads is first run right away on the same thread rather than going through the queue.
+    /// This avoids extra latency for nested blocking calls and the risk of a deadlock when all
+    /// threads are busy waiting on nested tasks. If the task yields, it gets scheduled as usual.
+    /// This is disabled by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let builder = PoolBuilder::new().run_nested_inline(true);
+    /// ```
+    pub fn run_nested_inline(mut self, enabled: bool) -> PoolBuilder {
+        self.run_nested_inline = enabled;
+        self
+    }
+
+    /// Sets whether tasks run on the Windows system thread pool.
+    ///
+    /// When enabled, tasks are submitted to the process-wide thread pool managed by Windows instead
+    /// of threads spawned by this pool. The system pool is shared with other components in the
+    /// process and sizes itself, which reduces thread churn on busy servers. The thread limit and
+    /// settings that apply to threads spawned by this pool, like names, stack sizes, priorities,
+    /// affinity, and start and stop hooks, have no effect on the system pool. If a task cannot be
+    /// submitted, it falls back to a thread spawned by this pool. This setting is ignored on other
+    /// platforms and is disabled by default.
+    ///
+    /// Work submitted to the system pool is counted in [`PoolMetrics::thread_count()`] while it's
+    /// pending or running, and [`Pool::shutdown()`] waits for it to finish.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let builder = PoolBuilder::new().system_thread_pool(true);
+    /// ```
+    pub fn system_thread_pool(mut self, enabled: bool) -> PoolBuilder {
+        self.system_thread_pool = enabled;
+        self
+    }
+
+    /// Sets a backend that runs tasks instead of threads spawned by the pool.
+    ///
+    /// Applications that already have a pool for blocking work, like the one behind
+    /// `tokio::task::spawn_blocking()`, can use this to avoid running two competing pools in one
+    /// process. Tasks are still queued by priority and tracked by the pool, but every time a task
+    /// is scheduled, the backend is asked to run it. The thread limit and settings that apply to
+    /// threads spawned by the pool have no effect. Closures handed to the backend are counted in
+    /// [`PoolMetrics::thread_count()`] until they return, and [`Pool::shutdown()`] waits for them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::{BlockingBackend, PoolBuilder};
+    /// use std::thread;
+    ///
+    /// struct ThreadPerTask;
+    ///
+    /// impl BlockingBackend for ThreadPerTask {
+    ///     fn spawn(&self, f: Box<dyn FnOnce() + Send>) {
+    ///         thread::spawn(f);
+    ///     }
+    /// }
+    ///
+    /// let builder = PoolBuilder::new().backend(ThreadPerTask);
+    /// ```
+    pub fn backend(mut self, backend: impl BlockingBackend) -> PoolBuilder {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Injects faults into the pool for testing.
+    ///
+    /// This makes it possible to test how code built on [`Blocking`] behaves under slow I/O and
+    /// pool saturation. See [`FaultInjection`] for the kinds of faults that can be injected.
+    ///
+    /// This method is only available with the `test-util` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::{FaultInjection, PoolBuilder};
+    /// use std::time::Duration;
+    ///
+    /// let faults = FaultInjection::new().delay(Duration::from_millis(10)).thread_cap(2);
+    /// let pool = PoolBuilder::new().fault_injection(faults).build();
+    /// ```
+    #[cfg(feature = "test-util")]
+    pub fn fault_injection(mut self, faults: FaultInjection) -> PoolBuilder {
+        self.fault_injection = faults;
+        self
+    }
+
+    /// Creates a new pool with this configuration.
+    ///
+    /// Threads configured with [`PoolBuilder::min_threads()`] are spawned right away, and the rest
+    /// are spawned lazily once tasks are spawned onto the pool.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// let pool = PoolBuilder::new().max_threads(4).build();
+    /// ```
+    pub fn build(self) -> Pool {
+        let executor = Arc::new(self.into_executor());
+        executor.spawn_min_threads();
+        Pool { executor }
+    }
+
+    /// Initializes the global pool with this configuration.
+    ///
+    /// This method must be called before the first blocking task is spawned. If the global pool
+    /// has already been initialized, an error of kind [`io::ErrorKind::AlreadyExists`] is
+    /// returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::PoolBuilder;
+    ///
+    /// PoolBuilder::new()
+    ///     .max_threads(8)
+    ///     .build_global()
+    ///     .expect("the global pool has already been initialized");
+    /// ```
+    pub fn build_global(self) -> io::Result<()> {
+        // Spawn threads only after the executor is installed, or else they would never stop.
+        let executor = Arc::new(self.into_executor());
+        EXECUTOR.set(executor.clone()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the global pool has already been initialized",
+            )
+        })?;
+        executor.spawn_min_threads();
+        Ok(())
+    }
+
+    /// Creates an executor with this configuration.
+    fn into_executor(self) -> Executor {
+        Executor {
+            inner: Mutex::new(Inner {
+                thread_count: 0,
+                max_threads: self.max_threads,
+                peak_thread_count: 0,
+                retiring: 0,
+                shutdown_wakers: Vec::new(),
+                idle_wakers: Vec::new(),
+            }),
+            queue: Queue::new(),
+            event: Event::new(),
+            notified: AtomicBool::new(false),
+            at_limit: AtomicBool::new(false),
+            idle_count: AtomicUsize::new(0),
+            active_count: AtomicUsize::new(0),
+            peak_queue_len: AtomicUsize::new(0),
+            completed_count: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+            draining: AtomicUsize::new(0),
+            over_limit: AtomicBool::new(false),
+            config: self,
+            thread_id: AtomicUsize::new(0),
+            handle_count: AtomicUsize::new(1),
+        }
+    }
+}
+
+/// Returns the default limit on the number of threads in a pool.
+fn default_max_threads() -> usize {
+    // The standard library respects cgroup CPU quotas and the cpuset when counting CPUs.
+    #[cfg(target_os = "linux")]
+    if let Ok(cpus) = thread::available_parallelism() {
+        return cpus
+            .get()
+            .saturating_mul(DEFAULT_THREADS_PER_CPU)
+            .min(DEFAULT_MAX_THREADS);
+    }
+
+    DEFAULT_MAX_THREADS
+}
+
+impl fmt::Debug for PoolBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("PoolBuilder");
+        s.field("max_threads", &self.max_threads)
+            .field("min_threads", &self.min_threads)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("thread_name", &self.thread_name)
+            .field("stack_size", &self.stack_size)
+            .field("thread_priority", &self.thread_priority)
+            .field("qos_class", &self.qos_class)
+            .field("cpu_affinity", &self.cpu_affinity)
+            .field("after_start", &self.after_start.is_some())
+            .field("before_stop", &self.before_stop.is_some())
+            .field("observer", &self.observer.is_some())
+            .field(
+                "watchdog",
+                &self.watchdog.as_ref().map(|(threshold, _)| threshold),
+            )
+            .field("panic_handler", &self.panic_handler.is_some())
+            .field("run_nested_inline", &self.run_nested_inline)
+            .field("system_thread_pool", &self.system_thread_pool)
+            .field("backend", &self.backend.is_some())
+            .field("manual", &self.manual);
+        #[cfg(feature = "test-util")]
+        s.field("fault_injection", &self.fault_injection);
+        s.finish()
+    }
+}
+
+impl Default for PoolBuilder {
+    fn default() -> PoolBuilder {
+        PoolBuilder::new()
+    }
+}
+
+/// The scheduling priority of threads in a pool.
+///
+/// See [`PoolBuilder::thread_priority()`] for more information.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ThreadPriority {
+    /// The lowest priority, for work that should only run when nothing else does.
+    Lowest,
+
+    /// Lower than the default priority.
+    BelowNormal,
+
+    /// The default priority.
+    Normal,
+
+    /// Higher than the default priority.
+    AboveNormal,
+
+    /// The highest priority that doesn't require a real-time scheduling policy.
+    Highest,
+}
+
+/// The quality-of-service class of threads in a pool on Apple platforms.
+///
+/// See [`PoolBuilder::qos_class()`] for more information.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QosClass {
+    /// Work the user is interacting with, like UI updates.
+    UserInteractive,
+
+    /// Work the user has started and is waiting for.
+    UserInitiated,
+
+    /// The default class.
+    Default,
+
+    /// Long-running work the user doesn't track actively, like downloads.
+    Utility,
+
+    /// Work the user isn't aware of, like indexing or backups.
+    Background,
+}
+
+/// A pool of threads for running blocking tasks.
+///
+/// Blocking tasks are spawned onto a global pool by default. Applications with different kinds of
+/// blocking workloads can create separate pools so that, for example, slow network file systems
+/// don't hold up fast local disk I/O.
+///
+/// Cloning a [`Pool`] creates another handle to the same pool. Threads in the pool shut down once
+/// they are idle and all handles to the pool have been dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use blocking::Pool;
+/// use std::fs;
+///
+/// # futures::executor::block_on(async {
+/// let pool = Pool::builder().max_threads(4).build();
+/// let contents = pool.spawn(async { fs::read_to_string("file.txt") }).await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub struct Pool {
+    /// The executor running tasks in this pool.
+    executor: Arc<Executor>,
+}
+
+impl Pool {
+    /// Creates a new pool with the default configuration.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Pool;
+    ///
+    /// let pool = Pool::new();
+    /// ```
+    pub fn new() -> Pool {
+        PoolBuilder::new().build()
+    }
+
+    /// Creates a builder for configuring a new pool.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Pool;
+    ///
+    /// let pool = Pool::builder().thread_name("nfs").build();
+    /// ```
+    pub fn builder() -> PoolBuilder {
+        PoolBuilder::new()
+    }
+
+    /// Spawns a future onto this pool.
+    ///
+    /// This is just like [`Blocking::spawn()`], except the future runs on this pool rather than the
+    /// global one.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Pool;
+    /// use std::fs;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let pool = Pool::new();
+    /// let contents = pool.spawn(async { fs::read_to_string("file.txt") }).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn spawn<T: Send + 'static>(
+        &self,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Blocking<T> {
+        Blocking::spawn_on(self, future)
+    }
+
+    /// Spawns a future with the given priority onto this pool.
+    ///
+    /// This is just like [`Blocking::spawn_with_priority()`], except the future runs on this pool
+    /// rather than the global one.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::{Pool, Priority};
+    /// use std::fs;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let pool = Pool::new();
+    /// let task = pool.spawn_with_priority(Priority::Low, async { fs::copy("a.txt", "b.txt") });
+    /// task.await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn spawn_with_priority<T: Send + 'static>(
+        &self,
+        priority: Priority,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Blocking<T> {
+        let info = TaskInfo::new(priority, None);
+        let task = self.executor.spawn_with_info(info, future);
+        Blocking::from_state(State::Task(task), Some(self.clone()))
+    }
+
+    /// Spawns a named future onto this pool.
+    ///
+    /// This is just like [`Blocking::spawn_named()`], except the future runs on this pool rather
+    /// than the global one.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Pool;
+    /// use std::fs;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let pool = Pool::new();
+    /// let task = pool.spawn_named("backup", async { fs::copy("a.txt", "b.txt") });
+    /// task.await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn spawn_named<T: Send + 'static>(
+        &self,
+        name: impl Into<Arc<str>>,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Blocking<T> {
+        let info = TaskInfo::new(Priority::Normal, Some(name.into()));
+        let task = self.executor.spawn_with_info(info, future);
+        Blocking::from_state(State::Task(task), Some(self.clone()))
+    }
+
+    /// Spawns a batch of futures onto this pool.
+    ///
+    /// This is like calling [`Pool::spawn()`] for each future, except that all tasks are queued at
+    /// once and the pool decides whether to grow only once for the whole batch. This is much
+    /// cheaper when spawning lots of small tasks, like a `stat()` call for every file in a
+    /// directory.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Pool;
+    /// use std::fs;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let pool = Pool::new();
+    /// let paths = vec!["a.txt", "b.txt", "c.txt"];
+    ///
+    /// let tasks = pool.spawn_all(paths.into_iter().map(|path| async move { fs::metadata(path) }));
+    /// for task in tasks {
+    ///     println!("{} bytes", task.await?.len());
+    /// }
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn spawn_all<T, F>(&self, futures: impl IntoIterator<Item = F>) -> Vec<Blocking<T>>
+    where
+        T: Send + 'static,
+        F: Future<Output = T> + Send + 'static,
+    {
+        self.executor
+            .spawn_batch(futures)
+            .into_iter()
+            .map(|task| Blocking::from_state(State::Task(task), Some(self.clone())))
+            .collect()
+    }
+
+    /// Shuts down this pool.
+    ///
+    /// The pool stops accepting new tasks and its threads keep running until the queue is drained,
+    /// after which they stop. Returns `true` if all threads have stopped within `timeout`, or
+    /// `false` otherwise.
+    ///
+    /// Tasks spawned after shutdown has begun are canceled, so awaiting them will panic. However,
+    /// tasks that were spawned earlier still get to run, even if they are woken up after shutdown.
+    /// Reading, writing, and seeking through [`Blocking`] handles on this pool fail with an error
+    /// instead, and the I/O handles can still be extracted with
+    /// [`into_inner()`][`Blocking::into_inner()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Pool;
+    /// use std::time::Duration;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let pool = Pool::new();
+    /// pool.spawn(async { println!("Hello from the pool!") }).await;
+    ///
+    /// assert!(pool.shutdown(Duration::from_secs(1)).await);
+    /// # });
+    /// ```
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        self.executor.shutdown(timeout).await
+    }
+
+    /// Returns the maximum number of threads in this pool.
+    ///
+    /// Every pool has its own limit, so one process can run, for example, a small pool for database
+    /// work next to a large pool for network file systems.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Pool;
+    ///
+    /// let pool = Pool::builder().max_threads(16).build();
+    /// assert_eq!(pool.max_threads(), 16);
+    /// ```
+    pub fn max_threads(&self) -> usize {
+        self.executor.inner.lock().unwrap().max_threads
+    }
+
+    /// Changes the maximum number of threads in this pool.
+    ///
+    /// This can be called while tasks are running. Raising the limit lets the pool grow
+    /// immediately if it has more work than threads, while lowering it stops idle threads right
+    /// away and busy threads once they finish their current task.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_threads` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Pool;
+    ///
+    /// let pool = Pool::builder().max_threads(4).build();
+    /// pool.resize(64);
+    /// assert_eq!(pool.max_threads(), 64);
+    /// ```
+    pub fn resize(&self, max_threads: usize) {
+        assert!(
+            max_threads > 0,
+            "maximum number of threads must be positive"
+        );
+        self.executor.resize(max_threads);
+    }
+
+    /// Waits until no tasks in this pool are scheduled or running.
+    ///
+    /// This is useful for making sure all background blocking work has settled, e.g. in tests or
+    /// at the end of a batch job. Note that tasks waiting to be woken up, like tasks blocked on a
+    /// channel, don't keep the pool busy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::{Pool, Task};
+    ///
+    /// # futures::executor::block_on(async {
+    /// let pool = Pool::new();
+    /// Task::spawn_on(&pool, async { println!("flushing logs") }).detach();
+    ///
+    /// pool.wait_idle().await;
+    /// # });
+    /// ```
+    pub async fn wait_idle(&self) {
+        self.executor.wait_idle().await
+    }
+
+    /// Returns a snapshot of metrics for this pool.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Pool;
+    ///
+    /// let pool = Pool::new();
+    /// let metrics = pool.metrics();
+    /// assert_eq!(metrics.thread_count(), 0);
+    /// ```
+    pub fn metrics(&self) -> PoolMetrics {
+        self.executor.metrics()
+    }
+
+    /// Creates a limiter that runs at most `limit` of its tasks on this pool at a time.
+    ///
+    /// This is useful for throttling a group of tasks, like database dumps, without limiting
+    /// everything else running on the pool. Tasks waiting for their turn don't occupy threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Pool;
+    /// use std::fs;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let pool = Pool::new();
+    /// let dumps = pool.limiter(4);
+    ///
+    /// // At most 4 of these copies run at the same time.
+    /// let tasks: Vec<_> = (0..16)
+    ///     .map(|i| dumps.spawn(async move { fs::copy(format!("{}.db", i), format!("{}.bak", i)) }))
+    ///     .collect();
+    /// for task in tasks {
+    ///     task.await?;
+    /// }
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn limiter(&self, limit: usize) -> Limiter {
+        assert!(limit > 0, "concurrency limit must be positive");
+        Limiter {
+            pool: self.clone(),
+            semaphore: Arc::new(Semaphore {
+                permits: AtomicUsize::new(limit),
+                event: Event::new(),
+            }),
+        }
+    }
+}
+
+/// Limits how many tasks from a group run on a pool at a time.
+///
+/// Limiters are created with [`Pool::limiter()`]. Cloning a [`Limiter`] creates another handle to
+/// the same limit.
+#[derive(Clone, Debug)]
+pub struct Limiter {
+    /// The pool tasks run on.
+    pool: Pool,
+
+    /// Permits for running tasks.
+    semaphore: Arc<Semaphore>,
+}
+
+impl Limiter {
+    /// Spawns a future onto the pool once fewer than the limit of tasks from this limiter are
+    /// running.
+    ///
+    /// This is just like [`Pool::spawn()`], except the future waits for its turn without
+    /// occupying a thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Pool;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let limiter = Pool::new().limiter(1);
+    /// assert_eq!(limiter.spawn(async { 1 + 2 }).await, 3);
+    /// # });
+    /// ```
+    pub fn spawn<T: Send + 'static>(
+        &self,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Blocking<T> {
+        let semaphore = self.semaphore.clone();
+        self.pool.spawn(async move {
+            let _permit = semaphore.acquire().await;
+            future.await
+        })
+    }
+}
+
+/// An async semaphore backing a [`Limiter`].
+#[derive(Debug)]
+struct Semaphore {
+    /// Number of available permits.
+    permits: AtomicUsize,
+
+    /// Notified when a permit is released.
+    event: Event,
+}
+
+impl Semaphore {
+    /// Attempts to take a permit without waiting.
+    fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut permits = self.permits.load(Ordering::SeqCst);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return Some(Permit(self)),
+                Err(p) => permits = p,
+            }
+        }
+        None
+    }
+
+    /// Waits until a permit is available and takes it.
+    async fn acquire(&self) -> Permit<'_> {
+        loop {
+            if let Some(permit) = self.try_acquire() {
+                return permit;
+            }
+
+            // Start listening, then try again in case a permit was released in the meantime.
+            let listener = self.event.listen();
+            if let Some(permit) = self.try_acquire() {
+                return permit;
+            }
+            listener.await;
+        }
+    }
+}
+
+/// A permit taken from a [`Semaphore`], released on drop.
+struct Permit<'a>(&'a Semaphore);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.permits.fetch_add(1, Ordering::SeqCst);
+        self.0.event.notify(1);
+    }
+}
+
+/// A snapshot of metrics for a pool.
+///
+/// The pool is saturated when all of its threads are busy and tasks are piling up in the queue.
+///
+/// # Examples
+///
+/// ```
+/// let metrics = blocking::metrics();
+/// println!(
+///     "{} threads, {} idle, {} queued",
+///     metrics.thread_count(),
+///     metrics.idle_count(),
+///     metrics.queue_len(),
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolMetrics {
+    thread_count: usize,
+    idle_count: usize,
+    queue_len: usize,
+    peak_thread_count: usize,
+    peak_queue_len: usize,
+    completed_count: usize,
+}
+
+impl PoolMetrics {
+    /// Returns the total number of threads in the pool.
+    ///
+    /// This is the number of idle threads + the number of active threads.
+    pub fn thread_count(&self) -> usize {
+        self.thread_count
+    }
+
+    /// Returns the number of idle threads in the pool.
+    ///
+    /// Idle threads are sleeping, waiting to get a task to run.
+    pub fn idle_count(&self) -> usize {
+        self.idle_count
+    }
+
+    /// Returns the number of runnable tasks waiting in the queue.
+    pub fn queue_len(&self) -> usize {
+        self.queue_len
+    }
+
+    /// Returns the highest number of threads the pool has had at once.
+    ///
+    /// A peak close to the limit on the number of threads suggests the limit should be raised.
+    pub fn peak_thread_count(&self) -> usize {
+        self.peak_thread_count
+    }
+
+    /// Returns the highest number of runnable tasks that have been waiting in the queue at once.
+    pub fn peak_queue_len(&self) -> usize {
+        self.peak_queue_len
+    }
+
+    /// Returns the number of tasks that have completed since the pool was created.
+    ///
+    /// Tasks that panicked are counted as completed, but canceled tasks are not.
+    pub fn completed_count(&self) -> usize {
+        self.completed_count
+    }
+}
+
+/// An observer of events in a pool.
+///
+/// Observers are installed with [`PoolBuilder::observer()`] and make it possible to hook
+/// monitoring tools into the pool. All methods do nothing by default.
+///
+/// Methods are called synchronously on the thread where the event happens, so they should be
+/// cheap and must not block.
+pub trait ExecutorObserver: Send + Sync {
+    /// Called when a task is spawned onto the pool.
+    fn task_spawned(&self) {}
+
+    /// Called on a thread in the pool right before it starts running a task.
+    ///
+    /// A task that yields gets run again once it's woken up, so this method may be called
+    /// multiple times for the same task.
+    fn task_started(&self) {}
+
+    /// Called on a thread in the pool right after it has finished running a task.
+    ///
+    /// A task that yields gets run again once it's woken up, so this method may be called
+    /// multiple times for the same task.
+    fn task_finished(&self) {}
+
+    /// Called on a new thread in the pool right after it starts.
+    fn thread_spawned(&self) {}
+
+    /// Called on a thread in the pool right before it stops.
+    fn thread_exited(&self) {}
+}
+
+/// A backend that runs tasks on behalf of a pool.
+///
+/// Backends are installed with [`PoolBuilder::backend()`] or [`set_backend()`] and make it
+/// possible to delegate blocking work to another runtime's thread pool.
+///
+/// # Examples
+///
+/// Delegate to tokio's blocking pool:
+///
+/// ```ignore
+/// use blocking::BlockingBackend;
+///
+/// struct Tokio(tokio::runtime::Handle);
+///
+/// impl BlockingBackend for Tokio {
+///     fn spawn(&self, f: Box<dyn FnOnce() + Send>) {
+///         self.0.spawn_blocking(f);
+///     }
+/// }
+///
+/// blocking::set_backend(Tokio(tokio::runtime::Handle::current()))?;
+/// ```
+pub trait BlockingBackend: Send + Sync + 'static {
+    /// Runs a closure on a thread where blocking is allowed.
+    ///
+    /// The closure runs a single task until it completes or yields, and must be called exactly
+    /// once.
+    fn spawn(&self, f: Box<dyn FnOnce() + Send>);
+}
+
+/// A task reported by the watchdog for running too long.
+///
+/// See [`PoolBuilder::watchdog()`] for more information.
+#[derive(Clone, Debug)]
+pub struct LongRunningTask {
+    name: Option<Arc<str>>,
+    thread_name: Option<String>,
+    elapsed: Duration,
+}
+
+impl LongRunningTask {
+    /// Returns the name of the task, if it was spawned with one.
+    ///
+    /// See [`Blocking::spawn_named()`] for more information.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the name of the thread running the task, if it has one.
+    pub fn thread_name(&self) -> Option<&str> {
+        self.thread_name.as_deref()
+    }
+
+    /// Returns how long the task has been running.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+/// Reports a long-running task when woken by its timer.
+struct Watchdog {
+    /// The callback to invoke.
+    callback: WatchdogHook,
+
+    /// The name of the task.
+    name: Option<Arc<str>>,
+
+    /// The name of the thread running the task.
+    thread_name: Option<String>,
+
+    /// When the task started running.
+    start: Instant,
+}
+
+impl Wake for Watchdog {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let task = LongRunningTask {
+            name: self.name.clone(),
+            thread_name: self.thread_name.clone(),
+            elapsed: self.start.elapsed(),
+        };
+
+        #[cfg(feature = "log")]
+        log::warn!(
+            "blocking task {:?} has been running on thread {:?} for {:?}",
+            task.name().unwrap_or("<unnamed>"),
+            task.thread_name().unwrap_or("<unnamed>"),
+            task.elapsed(),
+        );
+
+        (self.callback)(&task);
+    }
+}
+
+impl Default for Pool {
+    fn default() -> Pool {
+        Pool::new()
+    }
+}
+
+impl Clone for Pool {
+    fn clone(&self) -> Pool {
+        self.executor.handle_count.fetch_add(1, Ordering::SeqCst);
+        Pool {
+            executor: self.executor.clone(),
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // If this was the last handle, wake up threads kept alive by the minimum so that they can
+        // time out and stop.
+        if self.executor.handle_count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.executor.event.notify(usize::MAX);
+        }
+    }
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("config", &self.executor.config)
+            .finish()
+    }
+}
+
+/// Faults to inject into a pool for testing.
+///
+/// Faults are installed with [`PoolBuilder::fault_injection()`]. By default, no faults are
+/// injected.
+///
+/// This type is only available with the `test-util` feature.
+///
+/// # Examples
+///
+/// Test behavior on a saturated pool with a single thread and slow tasks:
+///
+/// ```
+/// use blocking::{FaultInjection, PoolBuilder};
+/// use std::time::Duration;
+///
+/// let faults = FaultInjection::new().delay(Duration::from_millis(50)).thread_cap(1);
+/// let pool = PoolBuilder::new().fault_injection(faults).build();
+///
+/// # futures::executor::block_on(async {
+/// let a = pool.spawn(async { 1 });
+/// let b = pool.spawn(async { 2 });
+/// assert_eq!(a.await + b.await, 3);
+/// assert_eq!(pool.metrics().peak_thread_count(), 1);
+/// # });
+/// ```
+#[cfg(feature = "test-util")]
+#[derive(Clone, Debug, Default)]
+pub struct FaultInjection {
+    /// How long to sleep before every poll of a task.
+    delay: Option<Duration>,
+
+    /// Whether tasks get woken right away every time they yield.
+    spurious_wakeups: bool,
+
+    /// The number of threads after which spawning more threads fails.
+    thread_cap: Option<usize>,
+}
+
+#[cfg(feature = "test-util")]
+impl FaultInjection {
+    /// Creates a configuration that injects no faults.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::FaultInjection;
+    ///
+    /// let faults = FaultInjection::new();
+    /// ```
+    pub fn new() -> FaultInjection {
+        FaultInjection::default()
+    }
+
+    /// Sleeps for `delay` before every poll of a task, simulating slow I/O.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::FaultInjection;
+    /// use std::time::Duration;
+    ///
+    /// let faults = FaultInjection::new().delay(Duration::from_millis(100));
+    /// ```
+    pub fn delay(mut self, delay: Duration) -> FaultInjection {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Wakes tasks right away every time they yield, as if they were woken spuriously.
+    ///
+    /// Futures must handle being polled when they're not ready, and this makes sure they do.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::FaultInjection;
+    ///
+    /// let faults = FaultInjection::new().spurious_wakeups(true);
+    /// ```
+    pub fn spurious_wakeups(mut self, enabled: bool) -> FaultInjection {
+        self.spurious_wakeups = enabled;
+        self
+    }
+
+    /// Makes spawning threads fail once the pool has `cap` threads.
+    ///
+    /// The pool behaves as if the OS refused to create more threads, so tasks pile up in the queue
+    /// even though the pool's thread limit hasn't been reached.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::FaultInjection;
+    ///
+    /// let faults = FaultInjection::new().thread_cap(4);
+    /// ```
+    pub fn thread_cap(mut self, cap: usize) -> FaultInjection {
+        self.thread_cap = Some(cap);
+        self
+    }
+
+    /// Wraps a future so that it's affected by the injected faults.
+    fn inject<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+        let faults = self.clone();
+        async move {
+            let mut future = pin::pin!(future);
+            future::poll_fn(|cx| {
+                if let Some(delay) = faults.delay {
+                    thread::sleep(delay);
+                }
+                let poll = future.as_mut().poll(cx);
+                if poll.is_pending() && faults.spurious_wakeups {
+                    cx.waker().wake_by_ref();
+                }
+                poll
+            })
+            .await
+        }
+    }
+}
+
+/// A pool for deterministic tests, where tasks run only when stepped manually.
+///
+/// No threads are ever spawned for this pool. Tasks spawned onto [`TestPool::pool()`] are queued
+/// until [`TestPool::run_one()`] or [`TestPool::run_until_stalled()`] runs them on the current
+/// thread, so tests of code built on [`Blocking`] don't need sleeps and don't suffer from races.
+///
+/// This type is only available with the `test-util` feature.
+///
+/// # Examples
+///
+/// ```
+/// use blocking::{Blocking, TestPool};
+///
+/// let test = TestPool::new();
+/// let task = Blocking::spawn_on(test.pool(), async { 1 + 2 });
+///
+/// assert_eq!(test.pool().metrics().queue_len(), 1);
+/// assert_eq!(test.run_until_stalled(), 1);
+/// assert_eq!(futures::executor::block_on(task), 3);
+/// ```
+#[cfg(feature = "test-util")]
+#[derive(Debug, Default)]
+pub struct TestPool {
+    /// The pool tasks are spawned onto.
+    pool: Pool,
+}
+
+#[cfg(feature = "test-util")]
+impl TestPool {
+    /// Creates a new test pool.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::TestPool;
+    ///
+    /// let test = TestPool::new();
+    /// ```
+    pub fn new() -> TestPool {
+        let builder = PoolBuilder {
+            manual: true,
+            ..PoolBuilder::new()
+        };
+        TestPool {
+            pool: builder.build(),
+        }
+    }
+
+    /// Returns the pool to spawn tasks and create [`Blocking`] handles on.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::{Blocking, TestPool};
+    ///
+    /// let test = TestPool::new();
+    /// let stdout = Blocking::new_on(test.pool(), std::io::stdout());
+    /// ```
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Runs one queued task on the current thread until it completes or yields.
+    ///
+    /// Returns `false` if there were no queued tasks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::TestPool;
+    ///
+    /// let test = TestPool::new();
+    /// let task = test.pool().spawn(async { 7 });
+    ///
+    /// assert!(test.run_one());
+    /// assert!(!test.run_one());
+    /// ```
+    pub fn run_one(&self) -> bool {
+        self.pool.executor.run_one()
+    }
+
+    /// Runs queued tasks on the current thread until there are none left.
+    ///
+    /// Tasks that get woken while running are queued again and run too. Returns the number of
+    /// times a task was run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::TestPool;
+    ///
+    /// let test = TestPool::new();
+    /// let a = test.pool().spawn(async { 1 });
+    /// let b = test.pool().spawn(async { 2 });
+    ///
+    /// assert_eq!(test.run_until_stalled(), 2);
+    /// assert_eq!(test.run_until_stalled(), 0);
+    /// ```
+    pub fn run_until_stalled(&self) -> usize {
+        let mut count = 0;
+        while self.run_one() {
+            count += 1;
+        }
+        count
+    }
+}
+
+/// The blocking executor.
+struct Executor {
+    /// Inner state of the executor.
+    inner: Mutex<Inner>,
+
+    /// The queue of blocking tasks.
+    queue: Queue,
+
+    /// Used to put idle threads to sleep and wake them up when new work comes in.
+    event: Event,
+
+    /// Set to `true` when a sleeping thread has been notified and hasn't woken up yet.
+    ///
+    /// While a notification is in flight, scheduling more tasks doesn't notify more threads.
+    /// Instead, every thread that picks up a task while more are waiting notifies the next one, so
+    /// a burst of scheduled tasks wakes threads in a chain.
+    notified: AtomicBool,
+
+    /// Set to `true` when the pool has as many threads as it may have.
+    ///
+    /// This lets scheduling skip locking the inner state when the pool can't grow anyway.
+    at_limit: AtomicBool,
+
+    /// Number of idle threads in the pool.
+    ///
+    /// Idle threads are sleeping, waiting to get a task to run.
+    idle_count: AtomicUsize,
+
+    /// Number of tasks that are scheduled or running.
+    active_count: AtomicUsize,
+
+    /// The highest number of runnable tasks the queue has held at once.
+    peak_queue_len: AtomicUsize,
+
+    /// Number of tasks that have completed since the pool was created.
+    completed_count: AtomicUsize,
+
+    /// Set to `true` when the pool is shutting down and doesn't accept new tasks.
+    shutdown: AtomicBool,
+
+    /// Number of pending waits for the pool to drain.
+    ///
+    /// While non-zero, threads stop once the queue is drained, but new tasks are still accepted.
+    draining: AtomicUsize,
+
+    /// Set to `true` when the pool has more threads than its limit after it was resized.
+    ///
+    /// Threads check this flag after running a task and before going to sleep.
+    over_limit: AtomicBool,
+
+    /// The configuration this executor was built with.
+    config: PoolBuilder,
+
+    /// Sequence number for the next thread's name.
+    thread_id: AtomicUsize,
+
+    /// Number of [`Pool`] handles to this executor.
+    ///
+    /// Threads kept alive by the minimum are released once this drops to zero. The global
+    /// executor always has one handle.
+    handle_count: AtomicUsize,
+}
+
+/// Inner state of the blocking executor.
+struct Inner {
+    /// Total number of threads in the pool.
+    ///
+    /// This is the number of idle threads + the number of active threads.
+    thread_count: usize,
+
+    /// Maximum number of threads in the pool.
+    max_threads: usize,
+
+    /// The highest number of threads the pool has had at once.
+    peak_thread_count: usize,
+
+    /// Number of threads that are stopping because the pool has shrunk.
+    ///
+    /// These threads are still included in the thread count until they stop.
+    retiring: usize,
+
+    /// Tasks waiting for all threads to stop after shutdown.
+    shutdown_wakers: Vec<Waker>,
+
+    /// Tasks waiting for the pool to become idle.
+    idle_wakers: Vec<Waker>,
+}
+
+/// The queue of blocking tasks, ordered by priority.
+///
+/// The queue is lock-free so that scheduling tasks doesn't contend with threads popping them.
+struct Queue {
+    /// One FIFO queue per priority level, from highest to lowest.
+    levels: [ConcurrentQueue<Runnable>; 3],
+}
+
+impl Queue {
+    /// Creates an empty queue.
+    fn new() -> Queue {
+        Queue {
+            levels: [
+                ConcurrentQueue::unbounded(),
+                ConcurrentQueue::unbounded(),
+                ConcurrentQueue::unbounded(),
+            ],
+        }
+    }
+
+    /// Pushes a runnable task to the back of the queue for its priority level.
+    fn push(&self, runnable: Runnable) {
+        let level = match runnable.tag().priority {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        };
+        self.levels[level]
+            .push(runnable)
+            .expect("the queue is unbounded and never closed");
+    }
+
+    /// Pops the runnable task with the highest priority.
+    fn pop(&self) -> Option<Runnable> {
+        self.levels.iter().find_map(|level| level.pop().ok())
+    }
+
+    /// Returns the total number of runnable tasks.
+    fn len(&self) -> usize {
+        self.levels.iter().map(|level| level.len()).sum()
+    }
+
+    /// Returns `true` if there are no runnable tasks.
+    fn is_empty(&self) -> bool {
+        self.levels.iter().all(|level| level.is_empty())
+    }
+}
+
+/// Sets a backend that runs tasks of the global pool.
+///
+/// This is a shorthand for [`PoolBuilder::backend()`] followed by
+/// [`PoolBuilder::build_global()`], so it must be called before the first blocking task is
+/// spawned. If the global pool has already been initialized, an error of kind
+/// [`io::ErrorKind::AlreadyExists`] is returned.
+///
+/// # Examples
+///
+/// ```
+/// use blocking::BlockingBackend;
+/// use std::thread;
+///
+/// struct ThreadPerTask;
+///
+/// impl BlockingBackend for ThreadPerTask {
+///     fn spawn(&self, f: Box<dyn FnOnce() + Send>) {
+///         thread::spawn(f);
+///     }
+/// }
+///
+/// blocking::set_backend(ThreadPerTask).expect("the global pool has already been initialized");
+/// ```
+pub fn set_backend(backend: impl BlockingBackend) -> io::Result<()> {
+    PoolBuilder::new().backend(backend).build_global()
+}
+
+/// Sets the maximum number of threads in the global pool.
+///
+/// This overrides the limit configured by [`PoolBuilder::max_threads()`] or the
+/// `BLOCKING_MAX_THREADS` environment variable, and can be called at any time. Raising the limit
+/// lets the pool grow immediately if it has more work than threads, while lowering it stops idle
+/// threads right away and busy threads once they finish their current task.
+///
+/// # Panics
+///
+/// Panics if `max_threads` is zero.
+///
+/// # Examples
+///
+/// ```
+/// blocking::set_max_threads(16);
+/// ```
+pub fn set_max_threads(max_threads: usize) {
+    assert!(
+        max_threads > 0,
+        "maximum number of threads must be positive"
+    );
+
+    Executor::global().resize(max_threads);
+}
+
+/// Returns the maximum number of threads in the global pool.
+///
+/// # Examples
+///
+/// ```
+/// blocking::set_max_threads(16);
+/// assert_eq!(blocking::max_threads(), 16);
+/// ```
+pub fn max_threads() -> usize {
+    Executor::global().inner.lock().unwrap().max_threads
+}
+
+/// Drains the global pool.
+///
+/// Threads of the global pool keep running until the queue is drained, after which they stop.
+/// Returns `true` if all threads have stopped within `timeout`, or `false` otherwise.
+///
+/// This is useful for waiting on outstanding blocking work, like flushing logs, before the program
+/// exits. Unlike [`Pool::shutdown()`], the global pool keeps accepting new tasks because libraries
+/// may be using it too, and threads are started again for tasks spawned afterwards. Threads kept
+/// alive by [`PoolBuilder::min_threads()`] are started again once draining is over.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// # futures::executor::block_on(async {
+/// blocking::shutdown(Duration::from_secs(1)).await;
+/// # });
+/// ```
+pub async fn shutdown(timeout: Duration) -> bool {
+    Executor::global().drain(timeout).await
+}
+
+/// Waits until no tasks in the global pool are scheduled or running.
+///
+/// See [`Pool::wait_idle()`] for more information.
+///
+/// # Examples
+///
+/// ```
+/// # futures::executor::block_on(async {
+/// blocking::wait_idle().await;
+/// # });
+/// ```
+pub async fn wait_idle() {
+    Executor::global().wait_idle().await
+}
+
+/// Returns a snapshot of metrics for the global pool.
+///
+/// # Examples
+///
+/// ```
+/// let metrics = blocking::metrics();
+/// println!("{} threads in the pool", metrics.thread_count());
+/// ```
+pub fn metrics() -> PoolMetrics {
+    Executor::global().metrics()
+}
+
+impl Executor {
+    /// Returns the global executor, initializing it with the default configuration if needed.
+    fn global() -> &'static Arc<Executor> {
+        EXECUTOR.get_or_init(|| {
+            let executor = Arc::new(PoolBuilder::new().into_executor());
+            executor.spawn_min_threads();
+            executor
+        })
+    }
+
+    /// Spawns a future onto this executor.
+    ///
+    /// Returns a [`Task`] handle for the spawned task.
+    fn spawn<T: Send + 'static>(
+        self: &Arc<Self>,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Task<T> {
+        let info = TaskInfo::new(Priority::Normal, None);
+        self.spawn_with_info(info, future)
+    }
+
+    /// Spawns a future with the given task information onto this executor.
+    ///
+    /// Returns a [`Task`] handle for the spawned task.
+    fn spawn_with_info<T: Send + 'static>(
+        self: &Arc<Self>,
+        info: TaskInfo,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Task<T> {
+        let (runnable, task) = self.create_task(info, future);
+
+        // If the pool is shutting down, drop the runnable to cancel the task.
+        if !self.shutdown.load(Ordering::SeqCst) {
+            self.start(runnable);
+        }
+        task
+    }
+
+    /// Spawns a future holding an I/O handle onto this executor, even if it is shutting down.
+    ///
+    /// Canceling the task would drop the I/O handle with it, so callers check for shutdown
+    /// beforehand instead, and a task spawned right after shutdown runs like those spawned before.
+    fn spawn_io<T: Send + 'static>(
+        self: &Arc<Self>,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Task<T> {
+        let (runnable, task) = self.create_task(TaskInfo::new(Priority::Normal, None), future);
+        self.start(runnable);
+        task
+    }
+
+    /// Starts running a newly created task.
+    fn start(self: &Arc<Self>, runnable: Runnable) {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(priority = ?runnable.tag().priority, "spawning a blocking task");
+
+        self.observe(|o| o.task_spawned());
+
+        // Run the task right away if it was spawned from a thread in this pool.
+        if self.config.run_nested_inline && self.is_current_worker() {
+            self.active_count.fetch_add(1, Ordering::SeqCst);
+            self.run(runnable);
+        } else {
+            runnable.schedule();
+        }
+    }
+
+    /// Fails if this executor is shutting down, in which case new tasks would be canceled.
+    fn check_running(&self) -> io::Result<()> {
+        if self.shutdown.load(Ordering::SeqCst) {
+            return Err(io::Error::other("pool is shut down"));
+        }
+        Ok(())
+    }
+
+    /// Spawns a batch of futures, scheduling them all at once.
+    fn spawn_batch<T, F>(self: &Arc<Self>, futures: impl IntoIterator<Item = F>) -> Vec<Task<T>>
+    where
+        T: Send + 'static,
+        F: Future<Output = T> + Send + 'static,
+    {
+        let (runnables, tasks): (Vec<_>, Vec<_>) = futures
+            .into_iter()
+            .map(|future| self.create_task(TaskInfo::new(Priority::Normal, None), future))
+            .unzip();
+
+        // If the pool is shutting down, drop the runnables to cancel the tasks.
+        if !self.shutdown.load(Ordering::SeqCst) && !runnables.is_empty() {
+            #[cfg(feature = "tracing")]
+            tracing::trace!(
+                count = runnables.len(),
+                "spawning a batch of blocking tasks"
+            );
+
+            for _ in &runnables {
+                self.observe(|o| o.task_spawned());
+            }
+            self.schedule_batch(runnables);
+        }
+        tasks
+    }
+
+    /// Creates a task for a future without scheduling it.
+    fn create_task<T: Send + 'static>(
+        self: &Arc<Self>,
+        info: TaskInfo,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> (Runnable, Task<T>) {
+        let executor = self.clone();
+
+        // Inject faults into the future when testing.
+        #[cfg(feature = "test-util")]
+        let future = self.config.fault_injection.inject(future);
+
+        // Run the future inside the current span so that blocking work shows up in traces.
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument(future, tracing::Span::current());
+
+        // Catch panics so that they are reported to whoever awaits the task.
+        let name = info.name.clone();
+        let panic_handler = self.config.panic_handler.clone();
+        let stats = self.clone();
+        let future = async move {
+            let res = catch_unwind(future).await;
+            stats.completed_count.fetch_add(1, Ordering::Relaxed);
+            res.map_err(|payload| {
+                #[cfg(feature = "log")]
+                match &name {
+                    Some(name) => log::warn!(
+                        "blocking task {:?} panicked: {}",
+                        name,
+                        panic_message(&*payload),
+                    ),
+                    None => {
+                        log::warn!("a blocking task panicked: {}", panic_message(&*payload))
+                    }
+                }
+                if let Some(panic_handler) = &panic_handler {
+                    panic_handler(name.as_deref(), &*payload);
+                }
+                JoinError::Panicked(payload)
+            })
+        };
+
+        // Create a task and return its runnable and `Task` handle.
+        let (runnable, handle) = async_task::spawn(future, move |r| executor.schedule(r), info);
+        let task = Task {
+            handle: Some(handle),
+            on_error: task_failed,
+            timeout: None,
+        };
+        (runnable, task)
+    }
+
+    /// Runs the main loop on the current thread.
+    ///
+    /// This function runs blocking tasks until it becomes idle and times out. If `keep_alive` is
+    /// `true`, the thread doesn't time out while the pool has handles.
+    ///
+    /// Returns `true` if the thread stopped because the pool has shrunk.
+    fn main_loop(self: &Arc<Self>, keep_alive: bool) -> bool {
+        loop {
+            // This thread is not idle anymore because it's going to run tasks.
+            self.idle_count.fetch_sub(1, Ordering::SeqCst);
+            self.notified.store(false, Ordering::SeqCst);
+
+            // Grow the pool if needed because this thread is now busy.
+            if !self.queue.is_empty() {
+                self.grow_pool(self.inner.lock().unwrap());
+            }
+
+            // Run tasks in the queue, but stop this thread after a task if the pool has shrunk.
+            while let Some(runnable) = self.queue.pop() {
+                // Pass the notification on if more tasks are waiting.
+                if !self.queue.is_empty() {
+                    self.notify();
+                }
+                self.run(runnable);
+
+                if self.try_retire() {
+                    // Make sure another thread picks up the remaining tasks.
+                    if !self.queue.is_empty() {
+                        self.event.notify_additional(1);
+                    }
+                    return true;
+                }
+            }
+
+            // This thread is now becoming idle. Clear the notification flag in case nobody was
+            // listening when it was set.
+            self.idle_count.fetch_add(1, Ordering::SeqCst);
+            self.notified.store(false, Ordering::SeqCst);
+
+            // Start listening for notifications, then check the queue again in case a task was
+            // scheduled in the meantime.
+            let listener = self.event.listen();
+            if !self.queue.is_empty() {
+                continue;
+            }
+
+            // If the pool is shutting down or draining and the queue is drained, stop this thread.
+            if self.shutdown.load(Ordering::SeqCst) || self.draining.load(Ordering::SeqCst) > 0 {
+                self.idle_count.fetch_sub(1, Ordering::SeqCst);
+                return false;
+            }
+
+            // If the pool has shrunk, stop this thread.
+            if self.try_retire() {
+                self.idle_count.fetch_sub(1, Ordering::SeqCst);
+                return true;
+            }
+
+            // Put the thread to sleep until it gets notified of a new task.
+            if keep_alive && self.handle_count.load(Ordering::SeqCst) > 0 {
+                listener.wait();
+            } else if !listener.wait_timeout(self.idle_timeout()) {
+                // There are no tasks after a while, so stop this thread. But check the queue one
+                // more time after leaving the idle state because a task might have been scheduled
+                // right after the timeout when this thread was still counted as idle.
+                self.idle_count.fetch_sub(1, Ordering::SeqCst);
+                if self.queue.is_empty() {
+                    return false;
+                }
+                self.idle_count.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Returns how long an idle thread waits for a task, with random jitter.
+    ///
+    /// The jitter spreads out timeouts of threads that became idle at the same time.
+    fn idle_timeout(&self) -> Duration {
+        let timeout = self.config.idle_timeout;
+        let random = RandomState::new().build_hasher().finish();
+        let fraction = (random >> 11) as f64 / (1u64 << 53) as f64;
+        timeout
+            .checked_add(timeout.mul_f64(fraction / 2.0))
+            .unwrap_or(timeout)
+    }
+
+    /// Returns `true` if the current thread belongs to this executor.
+    fn is_current_worker(&self) -> bool {
+        WORKER.with(|worker| ptr::eq(worker.get(), self))
+    }
+
+    /// Claims a spot for the current thread to stop if the pool has more threads than its limit.
+    fn try_retire(&self) -> bool {
+        if !self.over_limit.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.thread_count - inner.retiring <= inner.max_threads {
+            return false;
+        }
+        inner.retiring += 1;
+        if inner.thread_count - inner.retiring <= inner.max_threads {
+            self.over_limit.store(false, Ordering::SeqCst);
+        }
+        true
+    }
+
+    /// Changes the maximum number of threads.
+    fn resize(self: &Arc<Self>, max_threads: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.max_threads = max_threads;
+
+        if inner.thread_count - inner.retiring > max_threads {
+            // Wake up idle threads so that the extra ones stop.
+            self.over_limit.store(true, Ordering::SeqCst);
+            self.event.notify(usize::MAX);
+        } else {
+            self.over_limit.store(false, Ordering::SeqCst);
+        }
+        self.grow_pool(inner);
+    }
+
+    /// Runs a task on the current thread.
+    fn run(&self, runnable: Runnable) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(
+            "blocking_task",
+            priority = ?runnable.tag().priority,
+            name = runnable.tag().name.as_deref(),
+        )
+        .entered();
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+
+        // Arm the watchdog, which fires unless the timer is dropped before the threshold.
+        let watchdog = self.config.watchdog.as_ref().map(|(threshold, callback)| {
+            let waker = Waker::from(Arc::new(Watchdog {
+                callback: callback.clone(),
+                name: runnable.tag().name.clone(),
+                thread_name: thread::current().name().map(|s| s.to_string()),
+                start: Instant::now(),
+            }));
+            let mut timer = Timer::after(*threshold);
+            let _ = Pin::new(&mut timer).poll(&mut Context::from_waker(&waker));
+            timer
+        });
+
+        self.observe(|o| o.task_started());
+        // Panics in futures are caught inside tasks, but dropping a canceled future may panic too.
+        let _ = panic::catch_unwind(|| run_current(runnable));
+        drop(watchdog);
+        self.observe(|o| o.task_finished());
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(elapsed = ?start.elapsed(), "blocking task finished running");
+
+        // If this was the last active task, wake up tasks waiting for the pool to become idle.
+        if self.active_count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let wakers = mem::take(&mut self.inner.lock().unwrap().idle_wakers);
+            for waker in wakers {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Removes a stopped thread from the pool.
+    ///
+    /// If `retired` is `true`, the thread stopped because the pool has shrunk.
+    fn exit_thread(&self, retired: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.thread_count -= 1;
+        if retired {
+            inner.retiring -= 1;
+        }
+        self.at_limit.store(false, Ordering::SeqCst);
+
+        // If this was the last thread, wake up tasks waiting for shutdown to complete.
+        if inner.thread_count == 0 {
+            for waker in inner.shutdown_wakers.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Returns a snapshot of metrics for this executor.
+    fn metrics(&self) -> PoolMetrics {
+        let inner = self.inner.lock().unwrap();
+        PoolMetrics {
+            thread_count: inner.thread_count,
+            idle_count: self.idle_count.load(Ordering::SeqCst),
+            queue_len: self.queue.len(),
+            peak_thread_count: inner.peak_thread_count,
+            peak_queue_len: self.peak_queue_len.load(Ordering::Relaxed),
+            completed_count: self.completed_count.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Shuts down this executor and waits until all threads stop or the timeout expires.
+    async fn shutdown(&self, timeout: Duration) -> bool {
+        // Stop accepting new tasks.
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.wait_stopped(timeout).await
+    }
+
+    /// Waits until all threads stop after draining the queue or the timeout expires, while still
+    /// accepting new tasks.
+    async fn drain(self: &Arc<Self>, timeout: Duration) -> bool {
+        struct Guard<'a>(&'a Arc<Executor>);
+
+        impl Drop for Guard<'_> {
+            fn drop(&mut self) {
+                // Draining stopped the threads kept alive by the pool, so bring them back.
+                if self.0.draining.fetch_sub(1, Ordering::SeqCst) == 1
+                    && !self.0.shutdown.load(Ordering::SeqCst)
+                {
+                    self.0.spawn_min_threads();
+                }
+            }
+        }
+
+        self.draining.fetch_add(1, Ordering::SeqCst);
+        let _guard = Guard(self);
+        self.wait_stopped(timeout).await
+    }
+
+    /// Wakes all idle threads so that they can stop, and waits until they all do or the timeout
+    /// expires.
+    async fn wait_stopped(&self, timeout: Duration) -> bool {
+        self.event.notify(usize::MAX);
+
+        let mut timer = Timer::after(timeout);
+        future::poll_fn(|cx| {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.thread_count == 0 {
+                return Poll::Ready(true);
+            }
+            if !inner
+                .shutdown_wakers
+                .iter()
+                .any(|w| w.will_wake(cx.waker()))
+            {
+                inner.shutdown_wakers.push(cx.waker().clone());
+            }
+            drop(inner);
+
+            ready!(Pin::new(&mut timer).poll(cx));
+
+            // Don't leave the waker behind for the last thread to wake.
+            self.inner
+                .lock()
+                .unwrap()
+                .shutdown_wakers
+                .retain(|w| !w.will_wake(cx.waker()));
+            Poll::Ready(false)
+        })
+        .await
+    }
+
+    /// Waits until no tasks are scheduled or running.
+    async fn wait_idle(&self) {
+        future::poll_fn(|cx| {
+            let mut inner = self.inner.lock().unwrap();
+            if self.active_count.load(Ordering::SeqCst) == 0 {
+                return Poll::Ready(());
+            }
+            if !inner.idle_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                inner.idle_wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Schedules a runnable task for execution.
+    fn schedule(self: &Arc<Self>, runnable: Runnable) {
+        self.enqueue(runnable);
+        self.dispatch(1);
+    }
+
+    /// Schedules a batch of runnable tasks for execution.
+    fn schedule_batch(self: &Arc<Self>, runnables: Vec<Runnable>) {
+        let count = runnables.len();
+        for runnable in runnables {
+            self.enqueue(runnable);
+        }
+        self.dispatch(count);
+    }
+
+    /// Pushes a runnable task into the queue.
+    fn enqueue(&self, runnable: Runnable) {
+        self.active_count.fetch_add(1, Ordering::SeqCst);
+        self.queue.push(runnable);
+        self.peak_queue_len
+            .fetch_max(self.queue.len(), Ordering::Relaxed);
+    }
+
+    /// Makes sure `count` tasks that were just pushed into the queue get to run.
+    fn dispatch(self: &Arc<Self>, count: usize) {
+        // Tasks in a manually stepped pool wait in the queue until they are run.
+        if self.config.manual {
+            return;
+        }
+
+        // Without threads, run the tasks on the current thread instead.
+        #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+        {
+            let _ = count;
+            self.run_inline();
+        }
+
+        #[cfg(not(all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+        {
+            // Hand the tasks over to the backend if there is one.
+            if let Some(backend) = &self.config.backend {
+                // Count the closures as running threads so that shutting down waits for them.
+                let mut inner = self.inner.lock().unwrap();
+                inner.thread_count += count;
+                inner.peak_thread_count = inner.peak_thread_count.max(inner.thread_count);
+                drop(inner);
+                for _ in 0..count {
+                    let executor = self.clone();
+                    backend.spawn(Box::new(move || {
+                        executor.run_one();
+                        executor.exit_thread(false);
+                    }));
+                }
+                return;
+            }
+
+            // Hand the tasks over to the system thread pool if configured. If a task cannot be
+            // submitted, threads in this pool pick up the rest.
+            #[cfg(windows)]
+            if self.config.system_thread_pool
+                && (0..count).all(|_| submit_system_work(self).is_ok())
+            {
+                return;
+            }
+
+            // Notify a sleeping thread to pick up the tasks. Woken threads notify more threads
+            // while there are tasks left in the queue.
+            self.notify();
+
+            // Spawn more threads if needed, but lock the inner state only if growing looks
+            // necessary and possible.
+            if self.needs_growth() && !self.at_limit.load(Ordering::SeqCst) {
+                self.grow_pool(self.inner.lock().unwrap());
+            }
+        }
+    }
+
+    /// Runs one queued task on a thread that doesn't belong to the pool.
+    ///
+    /// This is how tasks run when they are handed over to threads managed by someone else.
+    ///
+    /// Returns `false` if there were no queued tasks.
+    #[cfg_attr(
+        all(target_arch = "wasm32", not(target_feature = "atomics")),
+        allow(dead_code)
+    )]
+    fn run_one(&self) -> bool {
+        let prev = WORKER.with(|worker| worker.replace(self));
+        let runnable = self.queue.pop();
+        let found = runnable.is_some();
+        if let Some(runnable) = runnable {
+            self.run(runnable);
+        }
+        WORKER.with(|worker| worker.set(prev));
+        found
+    }
+
+    /// Runs queued tasks on the current thread until the queue is empty.
+    ///
+    /// This is how tasks run on targets without threads. Tasks scheduled while the queue is being
+    /// drained are picked up by the outer call rather than run recursively.
+    #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+    fn run_inline(&self) {
+        if self.is_current_worker() {
+            return;
+        }
+
+        let prev = WORKER.with(|worker| worker.replace(self));
+        while let Some(runnable) = self.queue.pop() {
+            self.run(runnable);
+        }
+        WORKER.with(|worker| worker.set(prev));
+    }
+
+    /// Notifies a sleeping thread unless a notification is already in flight.
+    fn notify(&self) {
+        if !self.notified.swap(true, Ordering::SeqCst) {
+            self.event.notify_additional(1);
+        }
+    }
+
+    /// Returns `true` if runnable tasks greatly outnumber idle threads.
+    fn needs_growth(&self) -> bool {
+        self.queue.len() > self.idle_count.load(Ordering::SeqCst) * 5
+    }
+
+    /// Spawns the threads that are kept alive even when idle.
+    fn spawn_min_threads(self: &Arc<Self>) {
+        // Threads of the system thread pool or a backend are not managed by the pool, and a
+        // manually stepped pool has no threads at all.
+        if (cfg!(windows) && self.config.system_thread_pool)
+            || self.config.backend.is_some()
+            || self.config.manual
+        {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let min_threads = self.config.min_threads.min(inner.max_threads);
+
+        while inner.thread_count < min_threads {
+            // The new thread starts in idle state.
+            self.idle_count.fetch_add(1, Ordering::SeqCst);
+            inner.thread_count += 1;
+
+            if self.thread_refused(inner.thread_count) || self.spawn_thread(true).is_err() {
+                self.idle_count.fetch_sub(1, Ordering::SeqCst);
+                inner.thread_count -= 1;
+                break;
+            }
+            inner.peak_thread_count = inner.peak_thread_count.max(inner.thread_count);
+        }
+    }
+
+    /// Spawns more blocking threads if the pool is overloaded with work.
+    fn grow_pool(self: &Arc<Self>, mut inner: MutexGuard<'_, Inner>) {
+        // After shutdown, only tasks spawned earlier can be scheduled, so one thread is enough to
+        // run them.
+        let max_threads = if self.shutdown.load(Ordering::SeqCst) {
+            inner.max_threads.min(1)
+        } else {
+            inner.max_threads
+        };
+
+        // If runnable tasks greatly outnumber idle threads and there aren't too many threads
+        // already, then be aggressive and spawn one more thread.
+        while self.needs_growth() && inner.thread_count < max_threads {
+            // The new thread starts in idle state.
+            self.idle_count.fetch_add(1, Ordering::SeqCst);
+            inner.thread_count += 1;
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                thread_count = inner.thread_count,
+                queue_len = self.queue.len(),
+                "growing the blocking pool",
+            );
+
+            #[cfg(feature = "log")]
+            {
+                log::debug!(
+                    "growing the blocking pool to {} threads ({} tasks in the queue)",
+                    inner.thread_count,
+                    self.queue.len(),
+                );
+                if inner.thread_count == inner.max_threads {
+                    log::warn!(
+                        "the blocking pool has reached its limit of {} threads",
+                        inner.max_threads,
+                    );
+                }
+            }
+
+            // Spawn the new thread.
+            if self.thread_refused(inner.thread_count) || self.spawn_thread(false).is_err() {
+                // The OS refused to give us another thread, so stop growing for now.
+                self.idle_count.fetch_sub(1, Ordering::SeqCst);
+                inner.thread_count -= 1;
+                break;
+            }
+            inner.peak_thread_count = inner.peak_thread_count.max(inner.thread_count);
+        }
+
+        self.at_limit
+            .store(inner.thread_count >= inner.max_threads, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if spawning the thread that brings the pool to `thread_count` threads should
+    /// fail because of an injected fault.
+    fn thread_refused(&self, thread_count: usize) -> bool {
+        #[cfg(feature = "test-util")]
+        if let Some(cap) = self.config.fault_injection.thread_cap {
+            return thread_count > cap;
+        }
+        let _ = thread_count;
+        false
+    }
+
+    /// Spawns a new thread running the main loop.
+    ///
+    /// If `keep_alive` is `true`, the thread doesn't shut down when idle.
+    fn spawn_thread(self: &Arc<Self>, keep_alive: bool) -> io::Result<()> {
+        let mut builder = thread::Builder::new();
+        if let Some(prefix) = &self.config.thread_name {
+            let id = self.thread_id.fetch_add(1, Ordering::Relaxed);
+            builder = builder.name(format!("{}-{}", prefix, id));
+        }
+        if let Some(stack_size) = self.config.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        let executor = self.clone();
+        builder.spawn(move || {
+            WORKER.with(|worker| worker.set(&*executor));
+            executor.observe(|o| o.thread_spawned());
+            executor.configure_thread();
+            if let Some(after_start) = &executor.config.after_start {
+                after_start();
+            }
+            let retired = executor.main_loop(keep_alive);
+            if let Some(before_stop) = &executor.config.before_stop {
+                before_stop();
+            }
+            executor.observe(|o| o.thread_exited());
+            executor.exit_thread(retired);
+        })?;
+        Ok(())
+    }
+
+    /// Applies OS-level thread settings to the current thread.
+    ///
+    /// Failing to apply a setting is not fatal, so errors are only logged.
+    fn configure_thread(&self) {
+        if let Some(priority) = self.config.thread_priority {
+            let _res = set_thread_priority(priority);
+
+            #[cfg(feature = "log")]
+            if let Err(err) = _res {
+                log::warn!("cannot set the priority of a blocking thread: {}", err);
+            }
+        }
+
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        if let Some(qos_class) = self.config.qos_class {
+            let _res = set_thread_qos_class(qos_class);
+
+            #[cfg(feature = "log")]
+            if let Err(err) = _res {
+                log::warn!("cannot set the QoS class of a blocking thread: {}", err);
+            }
+        }
+
+        if let Some(cpus) = &self.config.cpu_affinity {
+            let _res = set_thread_affinity(cpus);
+
+            #[cfg(feature = "log")]
+            if let Err(err) = _res {
+                log::warn!("cannot set the CPU affinity of a blocking thread: {}", err);
+            }
+        }
+    }
+
+    /// Notifies the observer of an event, if there is one.
+    fn observe(&self, f: impl FnOnce(&dyn ExecutorObserver)) {
+        if let Some(observer) = &self.config.observer {
+            f(&**observer);
+        }
+    }
+}
+
+/// Sets the scheduling priority of the current thread.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_thread_priority(priority: ThreadPriority) -> io::Result<()> {
+    // On Linux, the nice value can be set for individual threads.
+    let nice = match priority {
+        ThreadPriority::Lowest => 19,
+        ThreadPriority::BelowNormal => 10,
+        ThreadPriority::Normal => 0,
+        ThreadPriority::AboveNormal => -5,
+        ThreadPriority::Highest => -10,
+    };
+
+    unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        if libc::setpriority(libc::PRIO_PROCESS, tid, nice) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Sets the scheduling priority of the current thread.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn set_thread_priority(priority: ThreadPriority) -> io::Result<()> {
+    unsafe {
+        // Pick a priority within the range of the default scheduling policy.
+        let min = libc::sched_get_priority_min(libc::SCHED_OTHER);
+        let max = libc::sched_get_priority_max(libc::SCHED_OTHER);
+        let step = match priority {
+            ThreadPriority::Lowest => 0,
+            ThreadPriority::BelowNormal => 1,
+            ThreadPriority::Normal => 2,
+            ThreadPriority::AboveNormal => 3,
+            ThreadPriority::Highest => 4,
+        };
+
+        let mut param: libc::sched_param = mem::zeroed();
+        param.sched_priority = min + (max - min) * step / 4;
+
+        match libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_OTHER, &param) {
+            0 => Ok(()),
+            err => Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+/// Sets the scheduling priority of the current thread.
+#[cfg(windows)]
+fn set_thread_priority(priority: ThreadPriority) -> io::Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_ABOVE_NORMAL,
+        THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_LOWEST,
+        THREAD_PRIORITY_NORMAL,
+    };
+
+    let priority = match priority {
+        ThreadPriority::Lowest => THREAD_PRIORITY_LOWEST,
+        ThreadPriority::BelowNormal => THREAD_PRIORITY_BELOW_NORMAL,
+        ThreadPriority::Normal => THREAD_PRIORITY_NORMAL,
+        ThreadPriority::AboveNormal => THREAD_PRIORITY_ABOVE_NORMAL,
+        ThreadPriority::Highest => THREAD_PRIORITY_HIGHEST,
+    };
+
+    unsafe {
+        if SetThreadPriority(GetCurrentThread(), priority) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Sets the scheduling priority of the current thread.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    windows
+)))]
+fn set_thread_priority(_: ThreadPriority) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "thread priorities are not supported on this platform",
+    ))
+}
+
+/// Sets the quality-of-service class of the current thread.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn set_thread_qos_class(qos_class: QosClass) -> io::Result<()> {
+    use libc::qos_class_t::*;
+
+    let qos_class = match qos_class {
+        QosClass::UserInteractive => QOS_CLASS_USER_INTERACTIVE,
+        QosClass::UserInitiated => QOS_CLASS_USER_INITIATED,
+        QosClass::Default => QOS_CLASS_DEFAULT,
+        QosClass::Utility => QOS_CLASS_UTILITY,
+        QosClass::Background => QOS_CLASS_BACKGROUND,
+    };
+
+    unsafe {
+        match libc::pthread_set_qos_class_self_np(qos_class, 0) {
+            0 => Ok(()),
+            err => Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+/// Submits a callback to the Windows system thread pool that runs one queued task.
+///
+/// Each callback holds a reference to the executor and pops a task from the queue when it runs, so
+/// task priorities are respected even though the system decides when callbacks run.
+#[cfg(windows)]
+fn submit_system_work(executor: &Arc<Executor>) -> io::Result<()> {
+    use std::ffi::c_void;
+    use windows_sys::Win32::System::Threading::{
+        TrySubmitThreadpoolCallback, PTP_CALLBACK_INSTANCE,
+    };
+
+    unsafe extern "system" fn callback(_: PTP_CALLBACK_INSTANCE, context: *mut c_void) {
+        let executor = Arc::from_raw(context as *const Executor);
+        executor.run_one();
+        executor.exit_thread(false);
+    }
+
+    // Count the callback as a running thread so that shutting down waits for it.
+    executor.inner.lock().unwrap().thread_count += 1;
+
+    let context = Arc::into_raw(executor.clone()) as *mut c_void;
+    unsafe {
+        if TrySubmitThreadpoolCallback(Some(callback), context, ptr::null()) == 0 {
+            drop(Arc::from_raw(context as *const Executor));
+            executor.exit_thread(false);
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Pins the current thread to a set of CPUs.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_thread_affinity(cpus: &[usize]) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        for &cpu in cpus {
+            if cpu >= mem::size_of::<libc::cpu_set_t>() * 8 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("CPU index {} is out of range", cpu),
+                ));
+            }
+            libc::CPU_SET(cpu, &mut set);
+        }
+
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Pins the current thread to a set of CPUs.
+#[cfg(windows)]
+fn set_thread_affinity(cpus: &[usize]) -> io::Result<()> {
+    use windows_sys::Win32::System::Threading::{GetCurrentThread, SetThreadAffinityMask};
+
+    let mut mask = 0usize;
+    for &cpu in cpus {
+        if cpu >= mem::size_of::<usize>() * 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CPU index {} is out of range", cpu),
+            ));
+        }
+        mask |= 1 << cpu;
+    }
+
+    unsafe {
+        if SetThreadAffinityMask(GetCurrentThread(), mask) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Pins the current thread to a set of CPUs.
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+fn set_thread_affinity(_: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "CPU affinity is not supported on this platform",
+    ))
+}
+
+/// Extracts the message from a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// The global set of timers.
+static TIMERS: Lazy<Timers> = Lazy::new(|| {
+    thread::Builder::new()
+        .name("blocking-timer".to_string())
+        .spawn(|| TIMERS.main_loop())
+        .expect("cannot spawn timer thread");
+
+    Timers {
+        wakers: Mutex::new(BTreeMap::new()),
+        cvar: Condvar::new(),
+        next_id: AtomicUsize::new(1),
+    }
+});
+
+/// Registered timers, processed by a dedicated thread.
+struct Timers {
+    /// Wakers of pending timers, ordered by deadline and ID.
+    wakers: Mutex<BTreeMap<(Instant, usize), Waker>>,
+
+    /// Used to wake up the timer thread when a timer is registered.
+    cvar: Condvar,
+
+    /// The ID for the next registered timer.
+    next_id: AtomicUsize,
+}
+
+impl Timers {
+    /// Runs the timer thread, waking timers as they fire.
+    fn main_loop(&self) {
+        let mut wakers = self.wakers.lock().unwrap();
+        loop {
+            // Take out wakers of fired timers.
+            let now = Instant::now();
+            let pending = wakers.split_off(&(now, usize::MAX));
+            let ready = mem::replace(&mut *wakers, pending);
+
+            // Wake fired timers without holding the lock.
+            if !ready.is_empty() {
+                drop(wakers);
+                for (_, waker) in ready {
+                    waker.wake();
+                }
+                wakers = self.wakers.lock().unwrap();
+                continue;
+            }
+
+            // Sleep until the next timer fires or a new timer is registered.
+            wakers = match wakers.keys().next() {
+                Some(&(deadline, _)) => self.cvar.wait_timeout(wakers, deadline - now).unwrap().0,
+                None => self.cvar.wait(wakers).unwrap(),
+            };
+        }
+    }
+}
+
+/// A future that completes at a point in time.
+struct Timer {
+    /// When this timer fires, or `None` if it never does.
+    deadline: Option<Instant>,
+
+    /// The ID of this timer if it is registered.
+    id: Option<usize>,
+}
+
+impl Timer {
+    /// Creates a timer that fires after a duration of time.
+    fn after(dur: Duration) -> Timer {
+        Timer {
+            deadline: Instant::now().checked_add(dur),
+            id: None,
+        }
+    }
+
+    /// Unregisters this timer if it is registered.
+    fn deregister(&mut self) {
+        if let (Some(deadline), Some(id)) = (self.deadline, self.id.take()) {
+            TIMERS.wakers.lock().unwrap().remove(&(deadline, id));
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.deregister();
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return Poll::Pending,
+        };
+
+        if Instant::now() >= deadline {
+            self.deregister();
+            return Poll::Ready(());
+        }
+
+        // Register the timer or update its waker, then wake up the timer thread.
+        let id = *self
+            .id
+            .get_or_insert_with(|| TIMERS.next_id.fetch_add(1, Ordering::Relaxed));
+        TIMERS
+            .wakers
+            .lock()
+            .unwrap()
+            .insert((deadline, id), cx.waker().clone());
+        TIMERS.cvar.notify_one();
+        Poll::Pending
+    }
+}
+
+/// Fails a pending operation with a [`io::ErrorKind::TimedOut`] error once `timeout` elapses.
+///
+/// The timer starts when the operation is first pending and is reset when it completes.
+fn poll_timeout<R>(
+    cx: &mut Context<'_>,
+    timeout: Option<Duration>,
+    timer: &mut Option<Timer>,
+    poll: Poll<io::Result<R>>,
+) -> Poll<io::Result<R>> {
+    match (poll, timeout) {
+        (Poll::Pending, Some(timeout)) => {
+            let t = timer.get_or_insert_with(|| Timer::after(timeout));
+            ready!(Pin::new(t).poll(cx));
+            *timer = None;
+            Poll::Ready(Err(io::ErrorKind::TimedOut.into()))
+        }
+        (Poll::Pending, None) => Poll::Pending,
+        (Poll::Ready(res), _) => {
+            *timer = None;
+            Poll::Ready(res)
+        }
+    }
+}
+
+/// Runs a blocking closure on the global pool.
+///
+/// This is a shorthand for [`Task::spawn()`] with a future that just calls `f`, which is what most
+/// blocking code needs.
+///
+/// # Examples
+///
+/// ```no_run
+/// use blocking::unblock;
+/// use std::fs;
+///
+/// # futures::executor::block_on(async {
+/// let contents = unblock(|| fs::read("file.txt")).await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub fn unblock<T, F>(f: F) -> Task<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Task::spawn(async move { f() })
+}
+
+/// Returns an async handle to the standard input of the current process.
+///
+/// This is a shorthand for `Blocking::new(std::io::stdin())` tuned for interactive input. The
+/// pipe holds at most 8 KB, the size of the line buffer of [`io::Stdin`], so the reading task
+/// doesn't consume much more input than the program has asked for.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::prelude::*;
+///
+/// # futures::executor::block_on(async {
+/// let mut lines = blocking::stdin().into_lines();
+///
+/// while let Some(line) = lines.next().await {
+///     println!("read: {}", line?);
+/// }
+/// # std::io::Result::Ok(()) });
+/// ```
+pub fn stdin() -> Blocking<io::Stdin> {
+    Blocking::with_capacity(STDIN_PIPE_CAPACITY, io::stdin())
+}
+
+/// Returns an async handle to the standard output of the current process.
+///
+/// This is a shorthand for `Blocking::new(std::io::stdout())` with a pipe of at most 64 KB, which
+/// is plenty for terminal output. The handle
+/// [finishes writing in the background][`Blocking::set_flush_on_drop()`] when dropped, so output
+/// isn't lost if it's not flushed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::prelude::*;
+///
+/// # futures::executor::block_on(async {
+/// let mut stdout = blocking::stdout();
+/// stdout.write_all(b"Hello world!\n").await?;
+/// stdout.flush().await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub fn stdout() -> Blocking<io::Stdout> {
+    let mut stdout = Blocking::with_capacity(STDOUT_PIPE_CAPACITY, io::stdout());
+    stdout.set_flush_on_drop(true);
+    stdout
+}
+
+/// Returns an async handle to the standard error of the current process.
+///
+/// This is a shorthand for `Blocking::new(std::io::stderr())` configured like [`stdout()`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::prelude::*;
+///
+/// # futures::executor::block_on(async {
+/// let mut stderr = blocking::stderr();
+/// stderr.write_all(b"something went wrong\n").await?;
+/// stderr.flush().await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub fn stderr() -> Blocking<io::Stderr> {
+    let mut stderr = Blocking::with_capacity(STDOUT_PIPE_CAPACITY, io::stderr());
+    stderr.set_flush_on_drop(true);
+    stderr
+}
+
+/// A policy for retrying blocking operations that fail transiently.
+///
+/// Operations are retried with exponentially growing delays in between. By default, errors of
+/// kind [`io::ErrorKind::Interrupted`] and [`io::ErrorKind::WouldBlock`] are retried up to 5
+/// times, with delays starting at 10 milliseconds and doubling up to 1 second.
+///
+/// See [`retry()`] for more information.
+///
+/// # Examples
+///
+/// ```
+/// use blocking::Backoff;
+/// use std::io;
+/// use std::time::Duration;
+///
+/// let backoff = Backoff::new()
+///     .initial_delay(Duration::from_millis(50))
+///     .max_delay(Duration::from_secs(5))
+///     .max_retries(10)
+///     .retry_if(|err| err.kind() == io::ErrorKind::TimedOut);
+/// ```
+#[derive(Clone)]
+pub struct Backoff {
+    /// The delay before the first retry.
+    initial_delay: Duration,
+
+    /// The maximum delay between retries.
+    max_delay: Duration,
+
+    /// The maximum number of retries.
+    max_retries: usize,
+
+    /// Decides which errors are retried, or `None` for the default.
+    predicate: Option<RetryPredicate>,
+}
+
+impl Backoff {
+    /// Creates a policy with the default configuration.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Backoff;
+    ///
+    /// let backoff = Backoff::new();
+    /// ```
+    pub fn new() -> Backoff {
+        Backoff {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(1),
+            max_retries: 5,
+            predicate: None,
+        }
+    }
+
+    /// Sets the delay before the first retry.
+    ///
+    /// The delay doubles after every retry, up to [`max_delay()`][`Backoff::max_delay()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Backoff;
+    /// use std::time::Duration;
+    ///
+    /// let backoff = Backoff::new().initial_delay(Duration::from_millis(100));
+    /// ```
+    pub fn initial_delay(mut self, delay: Duration) -> Backoff {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Sets the maximum delay between retries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Backoff;
+    /// use std::time::Duration;
+    ///
+    /// let backoff = Backoff::new().max_delay(Duration::from_secs(10));
+    /// ```
+    pub fn max_delay(mut self, delay: Duration) -> Backoff {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Sets the maximum number of retries.
+    ///
+    /// Once all retries have failed, the last error is returned. Zero disables retrying.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Backoff;
+    ///
+    /// let backoff = Backoff::new().max_retries(3);
+    /// ```
+    pub fn max_retries(mut self, retries: usize) -> Backoff {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Sets which errors are retried.
+    ///
+    /// This replaces the default of retrying [`io::ErrorKind::Interrupted`] and
+    /// [`io::ErrorKind::WouldBlock`] errors.
+    ///
+    /// # Examples
+    ///
+    /// Also retry operations that timed out:
+    ///
+    /// ```
+    /// use blocking::Backoff;
+    /// use std::io::ErrorKind;
+    ///
+    /// let backoff = Backoff::new().retry_if(|err| {
+    ///     matches!(
+    ///         err.kind(),
+    ///         ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+    ///     )
+    /// });
+    /// ```
+    pub fn retry_if<F>(mut self, predicate: F) -> Backoff
+    where
+        F: Fn(&io::Error) -> bool + Send + Sync + 'static,
+    {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Returns `true` if an operation that failed with `err` should be retried.
+    fn should_retry(&self, err: &io::Error) -> bool {
+        match &self.predicate {
+            Some(predicate) => predicate(err),
+            None => matches!(
+                err.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for Backoff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Backoff")
+            .field("initial_delay", &self.initial_delay)
+            .field("max_delay", &self.max_delay)
+            .field("max_retries", &self.max_retries)
+            .field("retry_if", &self.predicate.is_some())
+            .finish()
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff::new()
+    }
+}
+
+/// Runs a blocking operation on a thread, retrying it with backoff if it fails transiently.
+///
+/// Every attempt runs on the global pool, and the current task waits between attempts without
+/// occupying a thread. Which errors are retried and how long to wait is configured by `backoff`.
+///
+/// The [`retry_blocking!`] macro is a shorthand for this function.
+///
+/// # Examples
+///
+/// ```no_run
+/// use blocking::Backoff;
+/// use std::fs;
+///
+/// # futures::executor::block_on(async {
+/// let contents = blocking::retry(&Backoff::new(), || fs::read("/mnt/nfs/file.txt")).await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub async fn retry<T, F>(backoff: &Backoff, mut f: F) -> io::Result<T>
+where
+    F: FnMut() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let mut delay = backoff.initial_delay;
+    let mut retries = 0;
+    loop {
+        // Run the attempt on the pool, getting the closure back for the next attempt.
+        let (res, g) = unblock(move || (f(), f)).await;
+        f = g;
+
+        match res {
+            Err(err) if retries < backoff.max_retries && backoff.should_retry(&err) => {
+                Timer::after(delay).await;
+                delay = delay.saturating_mul(2).min(backoff.max_delay);
+                retries += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Spawns blocking I/O onto a thread.
+///
+/// Note that `blocking!(expr)` is just syntax sugar for
+/// `Blocking::spawn(async move { expr }).await`.
+///
+/// # Examples
+///
+/// Read a file into a string:
+///
+/// ```no_run
+/// use blocking::blocking;
+/// use std::fs;
+///
+/// # futures::executor::block_on(async {
+/// let contents = blocking!(fs::read_to_string("file.txt"))?;
+/// # std::io::Result::Ok(()) });
+/// ```
+///
+/// Spawn a process:
+///
+/// ```no_run
+/// use blocking::blocking;
+/// use std::process::Command;
+///
+/// # futures::executor::block_on(async {
+/// let out = blocking!(Command::new("dir").output())?;
+/// # std::io::Result::Ok(()) });
+/// ```
+///
+/// The future captures every variable it mentions. To make the captured variables explicit, list
+/// them in `move(...)` followed by `=>` before the expression. Only the listed variables are moved
+/// into the future, and mentioning any other local variable is a compile error:
+///
+/// ```no_run
+/// use blocking::blocking;
+/// use std::fs::File;
+/// use std::io::Write;
+///
+/// # futures::executor::block_on(async {
+/// let file = File::create("file.txt")?;
+/// let buf = b"hello".to_vec();
+///
+/// let (res, file) = blocking!(move(file, buf) => (file.write_all(&buf), file));
+/// res?;
+/// # std::io::Result::Ok(()) });
+/// ```
+#[macro_export]
+macro_rules! blocking {
+    (move($($var:ident),* $(,)?) => $($expr:tt)*) => {
+        $crate::Blocking::spawn($crate::__blocking_capture(($($var,)*), |($($var,)*)| {
+            async move {
+                $(
+                    #[allow(unused_mut)]
+                    let mut $var = $var;
+                )*
+                $($expr)*
+            }
+        }))
+        .await
+    };
+    ($($expr:tt)*) => {
+        $crate::Blocking::spawn(async move { $($expr)* }).await
+    };
+}
+
+/// Builds the future of a blocking macro that captures only the listed variables.
+///
+/// A closure coerces into a function pointer only if it doesn't capture anything, so the listed
+/// variables passed in `args` are the only ones moved into the future.
+#[doc(hidden)]
+pub fn __blocking_capture<A, F>(args: A, f: fn(A) -> F) -> F {
+    f(args)
+}
+
+/// Runs blocking I/O on a thread, retrying it with backoff if it fails transiently.
+///
+/// The expression must evaluate to an [`io::Result`], and it's evaluated again for every attempt.
+/// Note that `retry_blocking!(expr)` is just syntax sugar for
+/// `blocking::retry(&Backoff::new(), move || expr).await`. To configure the backoff, put a
+/// [`Backoff`] and `=>` before the expression.
+///
+/// # Examples
+///
+/// Retry reading a file with the default backoff:
+///
+/// ```no_run
+/// use blocking::retry_blocking;
+/// use std::fs;
+///
+/// # futures::executor::block_on(async {
+/// let contents = retry_blocking!(fs::read("/dev/ttyUSB0"))?;
+/// # std::io::Result::Ok(()) });
+/// ```
+///
+/// Retry with a custom backoff:
+///
+/// ```no_run
+/// use blocking::{retry_blocking, Backoff};
+/// use std::fs;
+///
+/// # futures::executor::block_on(async {
+/// let backoff = Backoff::new().max_retries(10);
+/// let contents = retry_blocking!(backoff => fs::read("/dev/ttyUSB0"))?;
+/// # std::io::Result::Ok(()) });
+/// ```
+#[macro_export]
+macro_rules! retry_blocking {
+    ($backoff:expr => $($expr:tt)*) => {
+        $crate::retry(&$backoff, move || { $($expr)* }).await
+    };
+    ($($expr:tt)*) => {
+        $crate::retry(&$crate::Backoff::new(), move || { $($expr)* }).await
+    };
+}
+
+/// Spawns blocking I/O onto a thread, catching panics.
+///
+/// This is just like [`blocking!`], except that it evaluates to a `Result` with a [`JoinError`]
+/// holding the panic payload if the expression panicked, rather than panicking in the awaiting
+/// task. Note that `try_blocking!(expr)` is just syntax sugar for
+/// `Blocking::spawn_catching(async move { expr }).await`.
+///
+/// A list of captured variables in `move(...) =>` before the expression is supported like with
+/// [`blocking!`].
+///
+/// # Examples
+///
+/// Keep serving requests even if handling one of them panics:
+///
+/// ```
+/// use blocking::try_blocking;
+///
+/// # futures::executor::block_on(async {
+/// let request: Vec<u8> = Vec::new();
+///
+/// match try_blocking!(move(request) => request[0]) {
+///     Ok(byte) => println!("first byte: {}", byte),
+///     Err(err) => println!("handling the request failed: {}", err),
+/// }
+/// # });
+/// ```
+#[macro_export]
+macro_rules! try_blocking {
+    (move($($var:ident),* $(,)?) => $($expr:tt)*) => {
+        $crate::Blocking::spawn_catching($crate::__blocking_capture(($($var,)*), |($($var,)*)| {
+            async move {
+                $(
+                    #[allow(unused_mut)]
+                    let mut $var = $var;
+                )*
+                $($expr)*
+            }
+        }))
+        .await
+    };
+    ($($expr:tt)*) => {
+        $crate::Blocking::spawn_catching(async move { $($expr)* }).await
+    };
+}
+
+/// Async I/O that runs on a thread.
+///
+/// This handle represents a future performing some blocking I/O on the special thread pool. The
+/// output of the future can be awaited because [`Blocking`] itself is a future.
+///
+/// It's also possible to interact with [`Blocking`] through [`Stream`], [`AsyncRead`],
+/// [`AsyncWrite`], and [`AsyncSeek`] traits if the inner type implements [`Iterator`], [`Read`],
+/// [`Write`], or [`Seek`].
+/// Reads are buffered internally, so [`Blocking`] also implements [`AsyncBufRead`] and doesn't
+/// need to be wrapped in a buffered reader to read lines.
+///
+/// To spawn a future and start it immediately, use [`Blocking::spawn()`]. To create an I/O handle
+/// that will lazily spawn an I/O future on its own, use [`Blocking::new()`].
+///
+/// If the [`Blocking`] handle is dropped, the future performing I/O will be canceled if it hasn't
+/// completed yet. However, note that it's not possible to forcibly cancel blocking I/O, so if the
+/// future is currently running, it won't be canceled until it yields.
+///
+/// A [`Blocking`] handle runs one task at a time, so switching between reading and writing stops
+/// the running task. Bytes that have already been read ahead from the I/O handle are kept and
+/// served by subsequent reads. To read and write at the same time, use [`Duplex`] or
+/// [`Blocking::split()`].
+///
+/// If writing some data through the [`AsyncWrite`] trait, make sure to flush before dropping the
+/// [`Blocking`] handle or some written data might get lost. Alternatively, await the handle to
+/// complete the pending work and extract the inner blocking I/O handle. To finish writing in the
+/// background instead, enable [`Blocking::set_flush_on_drop()`].
+///
+/// # Examples
+///
+/// ```
+/// use blocking::Blocking;
+/// use futures::prelude::*;
+/// use std::io::stdout;
+///
+/// # futures::executor::block_on(async {
+/// let mut stdout = Blocking::new(stdout());
+/// stdout.write_all(b"Hello world!").await?;
+///
+/// let inner = stdout.await;
+/// # std::io::Result::Ok(()) });
+/// ```
+///
+/// Read a file line by line:
+///
+/// ```no_run
+/// use blocking::Blocking;
+/// use futures::prelude::*;
+/// use std::fs::File;
+///
+/// # futures::executor::block_on(async {
+/// let mut lines = Blocking::new(File::open("file.txt")?).into_lines();
+///
+/// while let Some(line) = lines.next().await {
+///     println!("{}", line?);
+/// }
+/// # std::io::Result::Ok(()) });
+/// ```
+pub struct Blocking<T> {
+    /// The current state of the blocking task.
+    state: State<T>,
+
+    /// The pool running blocking tasks for this handle, or `None` for the global pool.
+    pool: Option<Pool>,
+
+    /// The capacity of the pipe used for reading and writing, or of the channel used for
+    /// streaming, or `None` for the default.
+    capacity: Option<NonZeroUsize>,
+
+    /// Settings that are rarely changed from their defaults, boxed to keep the handle small.
+    settings: Option<Box<Settings>>,
+
+    /// Bytes read from the I/O handle by a reading task that was stopped before they were
+    /// consumed, served by subsequent reads before starting a new reading task.
+    read_ahead: Option<Box<Reader>>,
+}
+
+/// Settings of a [`Blocking`] handle.
+#[derive(Default)]
+struct Settings {
+    /// Whether to finish writing in the background when dropped.
+    flush_on_drop: bool,
+
+    /// How long a read may be pending before it fails.
+    read_timeout: Option<Duration>,
+
+    /// How long a write or flush may be pending before it fails.
+    write_timeout: Option<Duration>,
+
+    /// The deadline of the pending read.
+    read_timer: Option<Timer>,
+
+    /// The deadline of the pending write or flush.
+    write_timer: Option<Timer>,
+
+    /// How many bytes are copied into or out of the pipe in one go.
+    chunk_size: Option<NonZeroUsize>,
+}
+
+impl Settings {
+    /// Copies the configuration for another handle, leaving out deadlines of pending operations.
+    fn copy_config(&self) -> Box<Settings> {
+        Box::new(Settings {
+            flush_on_drop: self.flush_on_drop,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            chunk_size: self.chunk_size,
+            ..Settings::default()
+        })
+    }
+}
+
+impl<T> Blocking<T> {
+    /// Wraps a blocking I/O handle into an async interface.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::io::stdin;
+    ///
+    /// # futures::executor::block_on(async {
+    /// // Create an async handle to standard input.
+    /// let stdin = Blocking::new(stdin());
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn new(io: T) -> Blocking<T> {
+        Blocking::from_state(State::Idle(Some(Box::new(io))), None)
+    }
+
+    /// Wraps a blocking I/O handle into an async interface with the given buffer capacity.
+    ///
+    /// Bytes read from or written into the handle are buffered in a pipe between the async side
+    /// and the blocking task, and so are items of a streamed iterator. The pipe starts at 64 KB
+    /// and grows up to 8 MB while the other side can't keep up, and the stream holds 8192 items by
+    /// default, which works well for most uses. However, memory-constrained systems may want a
+    /// smaller pipe, high-throughput applications a larger one, and streams of large items a lower
+    /// item count.
+    ///
+    /// For handles implementing [`Read`] or [`Write`], `capacity` is the maximum number of bytes
+    /// the pipe grows to, and for those implementing [`Iterator`], it's a number of items.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// // Use a 64 KB pipe.
+    /// let file = Blocking::with_capacity(64 * 1024, File::open("file.txt")?);
+    ///
+    /// // Buffer at most 16 directory entries.
+    /// let dir = Blocking::with_capacity(16, std::fs::read_dir(".")?);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn with_capacity(capacity: usize, io: T) -> Blocking<T> {
+        let capacity = NonZeroUsize::new(capacity).expect("capacity must be positive");
+        let mut blocking = Blocking::new(io);
+        blocking.capacity = Some(capacity);
+        blocking
+    }
+
+    /// Wraps a blocking I/O handle into an async interface that runs on the given pool.
+    ///
+    /// All blocking work for this handle, like reading, writing, or iterating, will be done by
+    /// tasks spawned onto `pool` rather than the global pool.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::{Blocking, Pool};
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let pool = Pool::new();
+    /// let file = Blocking::new_on(&pool, File::open("file.txt")?);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn new_on(pool: &Pool, io: T) -> Blocking<T> {
+        Blocking::from_state(State::Idle(Some(Box::new(io))), Some(pool.clone()))
+    }
+
+    /// Creates a handle in the given state with default settings.
+    fn from_state(state: State<T>, pool: Option<Pool>) -> Blocking<T> {
+        Blocking {
+            state,
+            pool,
+            capacity: None,
+            settings: None,
+            read_ahead: None,
+        }
+    }
+
+    /// Gets a mutable reference to the blocking I/O handle.
+    ///
+    /// This is an async method because the I/O handle might be on a different thread and needs to
+    /// be moved onto the current thread before we can get a reference to it.
+    ///
+    /// Note that bytes already read ahead from the I/O handle are kept and served by subsequent
+    /// reads. Use [`take_buffered()`][`Blocking::take_buffered()`] to take them out, e.g. before
+    /// seeking.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::create("file.txt")?);
+    /// let metadata = file.get_mut().await.metadata()?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn get_mut(&mut self) -> &mut T {
+        // Wait for the running task to stop and ignore I/O errors if there are any.
+        let _ = future::poll_fn(|cx| self.poll_stop(cx)).await;
+
+        // Assume idle state and get a reference to the inner value.
+        match &mut self.state {
+            State::Idle(t) => t.as_mut().expect("inner value was taken out"),
+            State::Streaming(..)
+            | State::Reading(..)
+            | State::Writing(..)
+            | State::Task(..)
+            | State::Seeking(..) => {
+                unreachable!("when stopped, the state machine must be in idle state");
+            }
+        }
+    }
+
+    /// Gets a reference to the blocking I/O handle if it's idle.
+    ///
+    /// Returns `None` if a blocking task is currently using the I/O handle or if the handle has
+    /// been closed. Use [`get_mut()`][`Blocking::get_mut()`] to wait for the task to stop instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    ///
+    /// let v = Blocking::new(vec![1, 2, 3]);
+    /// assert_eq!(v.try_get_ref(), Some(&vec![1, 2, 3]));
+    /// ```
+    pub fn try_get_ref(&self) -> Option<&T> {
+        match &self.state {
+            State::Idle(t) => t.as_deref(),
+            State::Streaming(..)
+            | State::Reading(..)
+            | State::Writing(..)
+            | State::Task(..)
+            | State::Seeking(..) => None,
+        }
+    }
+
+    /// Gets a mutable reference to the blocking I/O handle if it's idle.
+    ///
+    /// Returns `None` if a blocking task is currently using the I/O handle or if the handle has
+    /// been closed. Use [`get_mut()`][`Blocking::get_mut()`] to wait for the task to stop instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    ///
+    /// let mut v = Blocking::new(vec![1, 2, 3]);
+    /// if let Some(v) = v.try_get_mut() {
+    ///     v.push(4);
+    /// }
+    /// ```
+    pub fn try_get_mut(&mut self) -> Option<&mut T> {
+        match &mut self.state {
+            State::Idle(t) => t.as_deref_mut(),
+            State::Streaming(..)
+            | State::Reading(..)
+            | State::Writing(..)
+            | State::Task(..)
+            | State::Seeking(..) => None,
+        }
+    }
+
+    /// Extracts the inner blocking I/O handle.
+    ///
+    /// This is an async method because the I/O handle might be on a different thread and needs to
+    /// be moved onto the current thread before we can extract it.
+    ///
+    /// Note that awaiting this method is equivalent to awaiting the [`Blocking`] handle.
+    ///
+    /// Bytes that have been read from the I/O handle but not consumed yet are discarded. To keep
+    /// them, stop the reading task with [`get_mut()`][`Blocking::get_mut()`] and take them out
+    /// with [`take_buffered()`][`Blocking::take_buffered()`] first.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::create("file.txt")?);
+    /// file.write_all(b"Hello world!").await?;
+    ///
+    /// let file = file.into_inner().await;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn into_inner(self) -> T {
+        // There's a bug in rustdoc causing it to render `mut self` as `__arg0: Self`, so we just
+        // bind `self` to a local mutable variable.
+        let mut this = self;
+
+        // Wait for the running task to stop and ignore I/O errors if there are any.
+        let _ = future::poll_fn(|cx| this.poll_stop(cx)).await;
+
+        // Assume idle state and extract the inner value.
+        match &mut this.state {
+            State::Idle(t) => *t.take().expect("inner value was taken out"),
+            State::Streaming(..)
+            | State::Reading(..)
+            | State::Writing(..)
+            | State::Task(..)
+            | State::Seeking(..) => {
+                unreachable!("when stopped, the state machine must be in idle state");
+            }
+        }
+    }
+
+    /// Returns what this handle is currently doing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::{Blocking, BlockingState};
+    ///
+    /// let v = Blocking::new(vec![1, 2, 3]);
+    /// assert_eq!(v.state(), BlockingState::Idle);
+    /// ```
+    pub fn state(&self) -> BlockingState {
+        match &self.state {
+            State::Idle(Some(_)) => BlockingState::Idle,
+            State::Idle(None) => BlockingState::Closed,
+            State::Task(..) => BlockingState::Running,
+            State::Streaming(..) => BlockingState::Streaming,
+            State::Reading(..) => BlockingState::Reading,
+            State::Writing(..) => BlockingState::Writing,
+            State::Seeking(..) => BlockingState::Seeking,
+        }
+    }
+
+    /// Returns `true` if there is no blocking task and the inner value is readily available.
+    pub fn is_idle(&self) -> bool {
+        self.state() == BlockingState::Idle
+    }
+
+    /// Returns `true` if the inner value is currently being read from in a task.
+    pub fn is_reading(&self) -> bool {
+        self.state() == BlockingState::Reading
+    }
+
+    /// Returns `true` if the inner value is currently being written into in a task.
+    pub fn is_writing(&self) -> bool {
+        self.state() == BlockingState::Writing
+    }
+
+    /// Extracts the inner blocking I/O handle if it's idle.
+    ///
+    /// If a blocking task is currently using the I/O handle, the handle has been closed, or there
+    /// are bytes read from the I/O handle that haven't been consumed yet, the [`Blocking`] handle
+    /// is returned back as an error. Use [`into_inner()`][`Blocking::into_inner()`] to wait for the
+    /// task to stop instead, or [`take_buffered()`][`Blocking::take_buffered()`] to take out the
+    /// buffered bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    ///
+    /// let v = Blocking::new(vec![1, 2, 3]);
+    /// assert_eq!(v.try_into_inner().ok(), Some(vec![1, 2, 3]));
+    /// ```
+    pub fn try_into_inner(mut self) -> Result<T, Blocking<T>> {
+        if self
+            .read_ahead
+            .as_ref()
+            .is_some_and(|reader| !reader.is_empty())
+        {
+            return Err(self);
+        }
+        match &mut self.state {
+            State::Idle(t @ Some(_)) => Ok(*t.take().unwrap()),
+            _ => Err(self),
+        }
+    }
+
+    /// Returns the executor running blocking tasks for this handle.
+    fn executor(&self) -> &Arc<Executor> {
+        match &self.pool {
+            Some(pool) => &pool.executor,
+            None => Executor::global(),
+        }
+    }
+
+    /// Sets how long a read may be pending before it fails.
+    ///
+    /// If a read through [`AsyncRead`] or [`AsyncBufRead`] doesn't complete within `timeout`, it
+    /// fails with an error of kind [`io::ErrorKind::TimedOut`]. The reading task keeps running in
+    /// the background, so bytes it reads later are not lost and can be read by retrying.
+    ///
+    /// This is useful for I/O handles that might block indefinitely, like standard input or files
+    /// on network file systems. By default, there is no timeout.
+    ///
+    /// Timeouts are not supported on `wasm32` targets without threads.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::io::stdin;
+    /// use std::time::Duration;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut stdin = Blocking::new(stdin());
+    /// stdin.set_read_timeout(Some(Duration::from_secs(5)));
+    ///
+    /// let mut line = String::new();
+    /// match stdin.read_line(&mut line).await {
+    ///     Ok(_) => println!("read: {}", line),
+    ///     Err(err) if err.kind() == std::io::ErrorKind::TimedOut => println!("no input"),
+    ///     Err(err) => return Err(err),
+    /// }
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        let settings = self.settings_mut();
+        settings.read_timeout = timeout;
+        settings.read_timer = None;
+    }
+
+    /// Sets how long a write or flush may be pending before it fails.
+    ///
+    /// If a write or flush through [`AsyncWrite`] doesn't complete within `timeout`, it fails with
+    /// an error of kind [`io::ErrorKind::TimedOut`]. The writing task keeps running in the
+    /// background, so bytes that have already been written are not lost.
+    ///
+    /// By default, there is no timeout. Timeouts are not supported on `wasm32` targets without
+    /// threads.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::fs::File;
+    /// use std::time::Duration;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::create("/mnt/nfs/file.txt")?);
+    /// file.set_write_timeout(Some(Duration::from_secs(30)));
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        let settings = self.settings_mut();
+        settings.write_timeout = timeout;
+        settings.write_timer = None;
+    }
+
+    /// Sets how many bytes are copied in one go between the blocking task and the async side.
+    ///
+    /// Reads and writes go through a pipe, and each side wakes the other after copying at most
+    /// `chunk_size` bytes. Smaller chunks lower latency, which suits interactive handles like
+    /// standard input, while larger chunks mean fewer wakeups for bulk copies. The default is
+    /// 128 KB.
+    ///
+    /// This takes effect for the pipe currently in use and those created later.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::open("big.bin")?);
+    /// file.set_chunk_size(1024 * 1024);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        let chunk_size = NonZeroUsize::new(chunk_size).expect("chunk size must be positive");
+        self.settings_mut().chunk_size = Some(chunk_size);
+
+        // Apply the chunk size to the pipes currently in use.
+        if let Some(reader) = &self.read_ahead {
+            reader.set_chunk_size(chunk_size.get());
+        }
+        match &self.state {
+            State::Reading(Some(reader), _) => reader.set_chunk_size(chunk_size.get()),
+            State::Writing(Some(writer), _) => writer.set_chunk_size(chunk_size.get()),
+            _ => {}
+        }
+    }
+
+    /// Returns the settings of this handle for modification.
+    fn settings_mut(&mut self) -> &mut Settings {
+        self.settings.get_or_insert_with(Default::default)
+    }
+
+    /// Fails a pending read if it has been pending for longer than the read timeout.
+    fn poll_read_timeout<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: Poll<io::Result<R>>,
+    ) -> Poll<io::Result<R>> {
+        match &mut self.settings {
+            Some(settings) => {
+                poll_timeout(cx, settings.read_timeout, &mut settings.read_timer, poll)
+            }
+            None => poll,
+        }
+    }
+
+    /// Fails a pending write or flush if it has been pending for longer than the write timeout.
+    fn poll_write_timeout<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: Poll<io::Result<R>>,
+    ) -> Poll<io::Result<R>> {
+        match &mut self.settings {
+            Some(settings) => {
+                poll_timeout(cx, settings.write_timeout, &mut settings.write_timer, poll)
+            }
+            None => poll,
+        }
+    }
+
+    /// Creates a pipe for reading or writing, starting small and growing up to the capacity.
+    ///
+    /// Buffers of these pipes are recycled, so switching between reading and writing doesn't
+    /// allocate a new buffer every time.
+    fn pipe(&self) -> (Reader, Writer) {
+        let cap = self
+            .capacity
+            .map_or(DEFAULT_PIPE_CAPACITY, NonZeroUsize::get);
+        let mut shared = PipeShared {
+            recycle: true,
+            ..PipeShared::default()
+        };
+        if let Some(chunk_size) = self.settings.as_ref().and_then(|s| s.chunk_size) {
+            shared.chunk_size = AtomicUsize::new(chunk_size.get());
+        }
+        new_pipe(INITIAL_PIPE_CAPACITY.min(cap), cap, shared)
+    }
+
+    /// Waits for the running task to stop.
+    ///
+    /// On success, the state machine is moved into the idle state.
+    fn poll_stop(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match &mut self.state {
+                State::Idle(_) => return Poll::Ready(Ok(())),
+
+                State::Streaming(any, task) => {
+                    // Drop the receiver to close the channel. This stops the `send()` operation in
+                    // the task, after which the task returns the iterator back.
+                    any.take();
+
+                    // Poll the task to retrieve the iterator.
+                    let iter = ready!(Pin::new(task).poll(cx));
+                    self.state = State::Idle(Some(iter));
+                }
+
+                State::Reading(reader, task) => {
+                    // Close the pipe. This stops the `futures::io::copy` operation in the task,
+                    // after which the task returns the I/O handle back.
+                    if let Some(reader) = reader {
+                        reader.close();
+                    }
+
+                    // Poll the task to retrieve the I/O handle.
+                    let (res, io) = ready!(Pin::new(task).poll(cx));
+
+                    // Bytes left in the pipe have already been read from the I/O handle, so keep
+                    // them for subsequent reads rather than losing them.
+                    if let Some(reader) = reader.take() {
+                        if !reader.is_empty() {
+                            self.read_ahead = Some(Box::new(reader));
+                        }
+                    }
+
+                    // Make sure to move into the idle state before reporting errors.
+                    self.state = State::Idle(Some(io));
+                    res?;
+                }
+
+                State::Writing(writer, task) => {
+                    // Drop the writer to close the pipe. This stops the `futures::io::copy`
+                    // operation in the task, after which the task flushes the I/O handle and
+                    // returns it back.
+                    writer.take();
+
+                    // Poll the task to retrieve the I/O handle.
+                    let (res, io) = ready!(Pin::new(task).poll(cx));
+                    // Make sure to move into the idle state before reporting errors.
+                    self.state = State::Idle(Some(io));
+                    res?;
+                }
+
+                State::Task(task) => {
+                    // Poll the task to retrieve the inner value.
+                    let t = ready!(Pin::new(task).poll(cx));
+                    self.state = State::Idle(Some(Box::new(t)));
+                }
+
+                State::Seeking(task) => {
+                    // Poll the task to retrieve the I/O handle.
+                    let (res, io) = ready!(Pin::new(task).poll(cx));
+                    // Make sure to move into the idle state before reporting errors.
+                    self.state = State::Idle(Some(io));
+                    res?;
+                }
+            }
+        }
+    }
+}
+
+impl<T: Send + 'static> Blocking<T> {
+    /// Spawns a future that is allowed to do blocking I/O.
+    ///
+    /// If the [`Blocking`] handle is dropped, the future will be canceled if it hasn't completed
+    /// yet. However, note that it's not possible to forcibly cancel blocking I/O, so if the future
+    /// is currently running, it won't be canceled until it yields.
+    ///
+    /// If the future panics, awaiting the handle resumes the panic with its original payload. Use
+    /// [`Blocking::spawn_catching()`] to handle panics as errors instead.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::fs;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let contents = Blocking::spawn(async { fs::read_to_string("file.txt") }).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn spawn(future: impl Future<Output = T> + Send + 'static) -> Blocking<T> {
+        let task = Executor::global().spawn(future);
+        Blocking::from_state(State::Task(task), None)
+    }
+
+    /// Spawns a future that is allowed to do blocking I/O with the given priority.
+    ///
+    /// Tasks with higher priority get picked up by threads first, which keeps latency-sensitive
+    /// work like reading stdin responsive while bulk file copies are running. Any blocking work
+    /// done later through the returned handle runs with normal priority.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::{Blocking, Priority};
+    /// use std::fs;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let task = Blocking::spawn_with_priority(Priority::Low, async { fs::copy("a.txt", "b.txt") });
+    /// task.await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn spawn_with_priority(
+        priority: Priority,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Blocking<T> {
+        let info = TaskInfo::new(priority, None);
+        let task = Executor::global().spawn_with_info(info, future);
+        Blocking::from_state(State::Task(task), None)
+    }
+
+    /// Spawns a future that is allowed to do blocking I/O and lets it run to completion in the
+    /// background.
+    ///
+    /// Unlike [`Blocking::spawn()`], there is no handle that cancels the future when dropped,
+    /// which suits best-effort work like flushing logs. The output of the future is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::fs::File;
+    ///
+    /// # let file = File::create("log.txt")?;
+    /// Blocking::spawn_detached(async move {
+    ///     let _ = file.sync_all();
+    /// });
+    /// # std::io::Result::Ok(())
+    /// ```
+    pub fn spawn_detached(future: impl Future<Output = T> + Send + 'static) {
+        Executor::global().spawn(future).detach();
+    }
+
+    /// Spawns a future that is allowed to do blocking I/O, catching its failures.
+    ///
+    /// This is just like [`Blocking::spawn()`], except that awaiting the handle returns an error
+    /// if the future panicked or was canceled, rather than panicking in the awaiting task.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let res = Blocking::spawn_catching(async { panic!("oops") }).await;
+    /// assert!(res.unwrap_err().is_panic());
+    /// # });
+    /// ```
+    pub fn spawn_catching(
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Blocking<Result<T, JoinError>> {
+        let mut task = Executor::global().spawn(async move { Ok(future.await) });
+        task.on_error = Err;
+        Blocking::from_state(State::Task(task), None)
+    }
+
+    /// Spawns a future that is allowed to do blocking I/O with a time limit.
+    ///
+    /// If the future doesn't complete within `timeout`, awaiting the handle returns an error and
+    /// the task is canceled. Blocking code that is still running at that point keeps running until
+    /// it yields, but it can check [`is_canceled()`] to stop early.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let res = Blocking::spawn_timeout(Duration::from_millis(10), async {
+    ///     while !blocking::is_canceled() {
+    ///         thread::sleep(Duration::from_millis(1));
+    ///     }
+    /// })
+    /// .await;
+    /// assert!(res.is_err());
+    /// # });
+    /// ```
+    pub fn spawn_timeout(
+        timeout: Duration,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Blocking<Result<T, TimedOut>> {
+        let mut task = Executor::global().spawn(async move { Ok(future.await) });
+        task.timeout = Some((Timer::after(timeout), || Err(TimedOut(()))));
+        Blocking::from_state(State::Task(task), None)
+    }
+
+    /// Spawns a named future that is allowed to do blocking I/O.
+    ///
+    /// The name identifies the task in diagnostics: it is reported by the watchdog (see
+    /// [`PoolBuilder::watchdog()`]), included in panic messages when the `log` feature is enabled,
+    /// and recorded on the task's span when the `tracing` feature is enabled. Any blocking work
+    /// done later through the returned handle is unnamed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::fs;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let task = Blocking::spawn_named("reindex", async { fs::read_dir("index") });
+    /// task.await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn spawn_named(
+        name: impl Into<Arc<str>>,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Blocking<T> {
+        let info = TaskInfo::new(Priority::Normal, Some(name.into()));
+        let task = Executor::global().spawn_with_info(info, future);
+        Blocking::from_state(State::Task(task), None)
+    }
+
+    /// Spawns a future that is allowed to do blocking I/O onto the given pool.
+    ///
+    /// Any blocking work done later through the returned handle will also run on `pool`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::{Blocking, Pool};
+    /// use std::fs;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let pool = Pool::new();
+    /// let contents = Blocking::spawn_on(&pool, async { fs::read_to_string("file.txt") }).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn spawn_on(pool: &Pool, future: impl Future<Output = T> + Send + 'static) -> Blocking<T> {
+        let task = pool.executor.spawn(future);
+        Blocking::from_state(State::Task(task), Some(pool.clone()))
+    }
+
+    /// Spawns a future that doesn't implement [`Send`] onto a dedicated thread.
+    ///
+    /// This is useful for blocking work on values that can't leave their thread, like database
+    /// handles holding an [`Rc`](std::rc::Rc). See [`Task::spawn_local()`] for more information.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let len = Blocking::spawn_local(|| async {
+    ///     let log = Rc::new(RefCell::new(Vec::new()));
+    ///     log.borrow_mut().push("hello");
+    ///     let len = log.borrow().len();
+    ///     len
+    /// })
+    /// .await;
+    /// assert_eq!(len, 1);
+    /// # });
+    /// ```
+    pub fn spawn_local<F, Fut>(f: F) -> Blocking<T>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = T> + 'static,
+    {
+        Blocking::from_state(State::Task(Task::spawn_local(f)), None)
+    }
+
+    /// Runs a blocking operation on the I/O handle and returns its result.
+    ///
+    /// The closure runs on this handle's pool and gets a mutable reference to the I/O handle,
+    /// which stays on the pool rather than being moved onto the current thread like with
+    /// [`get_mut()`][`Blocking::get_mut()`].
+    ///
+    /// If the returned future is dropped before completion, the closure still runs and the I/O
+    /// handle is retrieved by the next operation on this handle.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::create("file.txt")?);
+    /// file.with(|file| file.set_len(1024)).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn with<R, F>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        // Wait for the running task to stop and ignore I/O errors if there are any.
+        let _ = future::poll_fn(|cx| self.poll_stop(cx)).await;
+
+        // Assume idle state and take the inner value out.
+        let mut io = match &mut self.state {
+            State::Idle(t) => t.take().expect("inner value was taken out"),
+            State::Streaming(..)
+            | State::Reading(..)
+            | State::Writing(..)
+            | State::Task(..)
+            | State::Seeking(..) => {
+                unreachable!("when stopped, the state machine must be in idle state");
+            }
+        };
+
+        // Run the closure in a task that returns the inner value, so that it can be retrieved even
+        // if this future gets dropped.
+        let slot = Arc::new(Mutex::new(None));
+        let task = self.executor().spawn({
+            let slot = slot.clone();
+            async move {
+                *slot.lock().unwrap() = Some(f(&mut io));
+                *io
+            }
+        });
+        self.state = State::Task(task);
+
+        // Wait for the task to complete and move back into the idle state.
+        let _ = future::poll_fn(|cx| self.poll_stop(cx)).await;
+        let res = slot.lock().unwrap().take();
+        res.expect("blocking operation didn't complete")
+    }
+}
+
+/// An extension trait for wrapping blocking I/O handles into [`Blocking`].
+///
+/// This trait is implemented for all sized `Send` types, so wrapping can be chained fluently.
+///
+/// # Examples
+///
+/// ```no_run
+/// use blocking::BlockingExt;
+/// use futures::prelude::*;
+/// use std::fs::File;
+///
+/// # futures::executor::block_on(async {
+/// let mut file = File::open("file.txt")?.unblock();
+///
+/// let mut contents = String::new();
+/// file.read_to_string(&mut contents).await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub trait BlockingExt: Sized {
+    /// Wraps this blocking I/O handle into an async interface.
+    ///
+    /// This is equivalent to [`Blocking::new()`].
+    fn unblock(self) -> Blocking<Self>;
+}
+
+impl<T: Send + 'static> BlockingExt for T {
+    fn unblock(self) -> Blocking<T> {
+        Blocking::new(self)
+    }
+}
+
+impl<T> Drop for Blocking<T> {
+    fn drop(&mut self) {
+        if self.settings.as_ref().is_some_and(|s| s.flush_on_drop) {
+            if let State::Writing(writer, _) = &mut self.state {
+                // Close the pipe so that the task writes the remaining bytes, flushes, and exits.
+                writer.take();
+
+                if let State::Writing(_, task) = mem::replace(&mut self.state, State::Idle(None)) {
+                    task.detach();
+                }
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Blocking<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The I/O handle is not shown so that handles of any type can be formatted.
+        f.debug_struct("Blocking")
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Wait for the running task to stop and ignore I/O errors if there are any.
+        let _ = ready!(self.poll_stop(cx));
+
+        // Assume idle state and extract the inner value.
+        match &mut self.state {
+            State::Idle(t) => Poll::Ready(*t.take().expect("inner value was taken out")),
+            State::Streaming(..)
+            | State::Reading(..)
+            | State::Writing(..)
+            | State::Task(..)
+            | State::Seeking(..) => {
+                unreachable!("when stopped, the state machine must be in idle state");
+            }
+        }
+    }
+}
+
+/// What a [`Blocking`] handle is currently doing.
+///
+/// Switching between reading, writing, and streaming stops the running task first. Bytes it has
+/// already read ahead are kept and served by subsequent reads, but items buffered by a streaming
+/// task are discarded. This state can be inspected with [`Blocking::state()`] to know whether such
+/// a switch is about to happen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlockingState {
+    /// There is no blocking task and the inner value is readily available.
+    Idle,
+
+    /// A task spawned by [`Blocking::spawn()`] or a similar function is still running.
+    Running,
+
+    /// The inner [`Iterator`] is being iterated in a task.
+    Streaming,
+
+    /// The inner [`Read`] is being read from in a task.
+    Reading,
+
+    /// The inner [`Write`] is being written into in a task.
+    Writing,
+
+    /// The inner [`Seek`] is being seeked in a task.
+    Seeking,
+
+    /// The inner value has been extracted or dropped, e.g. by closing the handle.
+    Closed,
+}
+
+/// Current state of a blocking task.
+enum State<T> {
+    /// There is no blocking task.
+    ///
+    /// The inner value is readily available, unless it has already been extracted. The value is
+    /// extracted out by [`Blocking::into_inner()`], [`AsyncWrite::poll_close()`], or by awaiting
+    /// [`Blocking`].
+    Idle(Option<Box<T>>),
+
+    /// A task was spawned by [`Blocking::spawn()`] and is still running.
+    Task(Task<T>),
+
+    /// The inner value is an [`Iterator`] currently iterating in a task.
+    ///
+    /// The `dyn Any` value here is a `Streamed<<T as Iterator>::Item>`.
+    #[cfg_attr(not(feature = "stream"), allow(dead_code))]
+    Streaming(Option<Box<dyn Any>>, Task<Box<T>>),
+
+    /// The inner value is a [`Read`] currently reading in a task.
+    Reading(Option<Reader>, Task<(io::Result<()>, Box<T>)>),
+
+    /// The inner value is a [`Write`] currently writing in a task.
+    Writing(Option<Writer>, Task<(io::Result<()>, Box<T>)>),
+
+    /// The inner value is a [`Seek`] currently seeking in a task.
+    Seeking(Task<(io::Result<u64>, Box<T>)>),
+}
+
+#[cfg(feature = "stream")]
+impl<T: Iterator + Send + 'static> Stream for Blocking<T>
+where
+    T::Item: Send + 'static,
+{
+    type Item = T::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T::Item>> {
+        loop {
+            match &mut self.state {
+                // If not in idle or active streaming state, stop the running task.
+                State::Task(..)
+                | State::Seeking(..)
+                | State::Streaming(None, _)
+                | State::Reading(..)
+                | State::Writing(..) => {
+                    // Wait for the running task to stop.
+                    let _ = ready!(self.poll_stop(cx));
+                }
+
+                // If idle, start a streaming task.
+                State::Idle(iter) => {
+                    // If idle, take the iterator out to run it on a blocking task.
+                    let mut iter = iter.take().unwrap();
+
+                    let hint = iter.size_hint();
+                    let capacity = self
+                        .capacity
+                        .map_or(DEFAULT_STREAM_CAPACITY, NonZeroUsize::get);
+                    let (mut sender, receiver) = channel(capacity);
+
+                    // Spawn a blocking task that runs the iterator and returns it when done.
+                    let task = self.executor().spawn(async move {
+                        for item in &mut iter {
+                            if sender.send(item).await.is_err() {
+                                break;
+                            }
+                        }
+                        iter
+                    });
+
+                    // Move into the busy state and poll again.
+                    let streamed = Streamed { receiver, hint };
+                    self.state = State::Streaming(Some(Box::new(streamed)), task);
+                }
+
+                // If streaming, receive an item.
+                State::Streaming(Some(any), task) => {
+                    let streamed = any.downcast_mut::<Streamed<T::Item>>().unwrap();
+
+                    // Poll the channel.
+                    let opt = ready!(streamed.receiver.poll_recv(cx));
+
+                    // Account for the received item in the size hint.
+                    if opt.is_some() {
+                        let (lower, upper) = &mut streamed.hint;
+                        *lower = lower.saturating_sub(1);
+                        *upper = upper.map(|n| n.saturating_sub(1));
+                    }
+
+                    // If the channel is closed, retrieve the iterator back from the blocking task.
+                    // This is not really a required step, but it's cleaner to drop the iterator on
+                    // the same thread that created it.
+                    if opt.is_none() {
+                        // Poll the task to retrieve the iterator.
+                        let iter = ready!(Pin::new(task).poll(cx));
+                        self.state = State::Idle(Some(iter));
+                    }
+
+                    return Poll::Ready(opt);
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.state {
+            State::Idle(Some(iter)) => iter.size_hint(),
+            State::Streaming(Some(any), _) => any.downcast_ref::<Streamed<T::Item>>().unwrap().hint,
+
+            // Other states restart iteration or can't be polled at all, so nothing is known.
+            State::Idle(None)
+            | State::Task(..)
+            | State::Seeking(..)
+            | State::Streaming(None, _)
+            | State::Reading(..)
+            | State::Writing(..) => (0, None),
+        }
+    }
+}
+
+/// The receiving side of a streaming task.
+#[cfg(feature = "stream")]
+struct Streamed<I> {
+    /// Receives items yielded by the iterator.
+    receiver: Receiver<I>,
+
+    /// The size hint of the iterator, captured before spawning the task and updated as items are
+    /// received.
+    hint: (usize, Option<usize>),
+}
+
+#[cfg(feature = "stream")]
+impl<I> Blocking<TryIter<I>> {
+    /// Wraps a fallible iterator into a stream that ends after the first error.
+    ///
+    /// Iterators like [`std::fs::ReadDir`] yield [`Result`]s, and usually an error means that
+    /// iteration can't go on. The returned stream yields the error and then ends, and the blocking
+    /// task stops iterating as soon as it encounters the error, rather than treating it as an
+    /// ordinary item.
+    ///
+    /// Use [`TryIter::into_inner()`] to retrieve the iterator after awaiting the handle.
+    ///
+    /// This method is only available with the `stream` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::fs;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut dir = Blocking::from_try_iter(fs::read_dir(".")?);
+    ///
+    /// while let Some(item) = dir.try_next().await? {
+    ///     println!("{:?}", item.file_name());
+    /// }
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn from_try_iter<U, E>(iter: I) -> Blocking<TryIter<I>>
+    where
+        I: Iterator<Item = Result<U, E>>,
+    {
+        Blocking::new(TryIter {
+            iter,
+            failed: false,
+        })
+    }
+}
+
+/// An iterator over [`Result`]s that ends after the first error.
+///
+/// This iterator is created by [`Blocking::from_try_iter()`].
+#[cfg(feature = "stream")]
+#[derive(Debug)]
+pub struct TryIter<I> {
+    /// The inner iterator.
+    iter: I,
+
+    /// Set to `true` once the inner iterator has yielded an error.
+    failed: bool,
+}
+
+#[cfg(feature = "stream")]
+impl<I> TryIter<I> {
+    /// Extracts the inner iterator.
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<I: Iterator<Item = Result<U, E>>, U, E> Iterator for TryIter<I> {
+    type Item = Result<U, E>;
+
+    fn next(&mut self) -> Option<Result<U, E>> {
+        if self.failed {
+            return None;
+        }
+
+        let item = self.iter.next();
+        self.failed = matches!(item, Some(Err(_)));
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            (0, self.iter.size_hint().1)
+        }
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T: Read + Send + 'static> Blocking<T> {
+    /// Converts this handle into a stream of byte chunks.
+    ///
+    /// Each chunk holds at most `chunk_size` bytes and is read directly by the blocking task, so
+    /// this is a good fit for APIs that consume streams of frames, like HTTP bodies. The stream
+    /// ends after the end of the I/O handle or after the first error. Bytes already read ahead by
+    /// this handle are yielded first.
+    ///
+    /// This method is only available with the `stream` feature.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `chunk_size` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut chunks = Blocking::new(File::open("file.txt")?).into_byte_stream(64 * 1024);
+    ///
+    /// while let Some(chunk) = chunks.try_next().await? {
+    ///     println!("read {} bytes", chunk.len());
+    /// }
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn into_byte_stream(self, chunk_size: usize) -> Blocking<Chunks<T>> {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        self.map_reader(move |io| Chunks {
+            io,
+            chunk_size,
+            done: false,
+        })
+    }
+
+    /// Converts this handle into a stream of lines.
+    ///
+    /// Lines are split by the blocking task, and the newline at the end of each line (`\n` or
+    /// `\r\n`) is removed. The stream ends after the end of the I/O handle or after the first
+    /// error, which is also reported for lines that are not valid UTF-8. Bytes already read ahead
+    /// by this handle are yielded first.
+    ///
+    /// This method is only available with the `stream` feature.
+    ///
+    /// # Examples
+    ///
+    /// Process the standard input line by line:
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut lines = Blocking::new(std::io::stdin()).into_lines();
+    ///
+    /// while let Some(line) = lines.try_next().await? {
+    ///     println!("{}", line.to_uppercase());
+    /// }
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn into_lines(self) -> Blocking<Lines<T>> {
+        self.map_reader(|io| Lines {
+            io: BufReader::new(io),
+            done: false,
+        })
+    }
+
+    /// Converts this handle into a handle wrapping another I/O handle built from this one.
+    ///
+    /// The running task is stopped first, ignoring its errors, and bytes read ahead are chained in
+    /// front of the I/O handle so that they are not lost.
+    fn map_reader<U, F>(mut self, f: F) -> Blocking<U>
+    where
+        U: Send + 'static,
+        F: FnOnce(Buffered<T>) -> U + Send + 'static,
+    {
+        let mut read_ahead = self.read_ahead.take();
+        let state = mem::replace(&mut self.state, State::Idle(None));
+
+        // Carry the configuration over to the new handle.
+        let pool = self.pool.clone();
+        let capacity = self.capacity;
+        let settings = self.settings.as_ref().map(|s| s.copy_config());
+        let handle = move |state| {
+            let mut handle = Blocking::from_state(state, pool);
+            handle.capacity = capacity;
+            handle.settings = settings;
+            handle
+        };
+
+        // Stop the running task, keeping the bytes it has already read ahead.
+        let stop: Stopping<T> = match state {
+            // If idle, there's no need to spawn a task.
+            State::Idle(io) => {
+                let io = *io.expect("inner value was taken out");
+                let mut buffered = Vec::new();
+                if let Some(reader) = &mut read_ahead {
+                    reader.drain_into(&mut buffered);
+                }
+                let io = f(Cursor::new(buffered).chain(io));
+                return handle(State::Idle(Some(Box::new(io))));
+            }
+            State::Task(task) => Box::pin(async move { (read_ahead, task.await) }),
+            State::Streaming(_, task) => Box::pin(async move { (read_ahead, *task.await) }),
+            State::Reading(reader, task) => {
+                if let Some(reader) = &reader {
+                    reader.close();
+                }
+                Box::pin(async move {
+                    let (_, io) = task.await;
+                    (reader.map(Box::new).or(read_ahead), *io)
+                })
+            }
+            State::Writing(_, task) => Box::pin(async move { (read_ahead, *task.await.1) }),
+            State::Seeking(task) => Box::pin(async move { (read_ahead, *task.await.1) }),
+        };
+
+        let task = self.executor().spawn(async move {
+            let (read_ahead, io) = stop.await;
+            let mut buffered = Vec::new();
+            if let Some(mut reader) = read_ahead {
+                reader.drain_into(&mut buffered);
+            }
+            f(Cursor::new(buffered).chain(io))
+        });
+        handle(State::Task(task))
+    }
+}
+
+/// An I/O handle with bytes that have already been read from it chained in front.
+#[cfg(feature = "stream")]
+type Buffered<T> = io::Chain<Cursor<Vec<u8>>, T>;
+
+/// A future stopping the running task, returning the bytes read ahead and the I/O handle.
+#[cfg(feature = "stream")]
+type Stopping<T> = Pin<Box<dyn Future<Output = (Option<Box<Reader>>, T)> + Send>>;
+
+/// An iterator over chunks of bytes read from an I/O handle.
+///
+/// This iterator is created by [`Blocking::into_byte_stream()`].
+#[cfg(feature = "stream")]
+pub struct Chunks<T> {
+    /// The I/O handle to read from.
+    io: Buffered<T>,
+
+    /// The maximum number of bytes in a chunk.
+    chunk_size: usize,
+
+    /// Set to `true` at the end of the I/O handle or after an error.
+    done: bool,
+}
+
+#[cfg(feature = "stream")]
+impl<T> Chunks<T> {
+    /// Extracts the inner I/O handle.
+    ///
+    /// Bytes that were read ahead before the conversion into chunks but not yielded yet are
+    /// discarded.
+    pub fn into_inner(self) -> T {
+        self.io.into_inner().1
+    }
+}
+
+/// An iterator over lines read from an I/O handle.
+///
+/// This iterator is created by [`Blocking::into_lines()`].
+#[cfg(feature = "stream")]
+pub struct Lines<T> {
+    /// The buffered I/O handle to read from.
+    io: BufReader<Buffered<T>>,
+
+    /// Set to `true` at the end of the I/O handle or after an error.
+    done: bool,
+}
+
+#[cfg(feature = "stream")]
+impl<T> Lines<T> {
+    /// Extracts the inner I/O handle.
+    ///
+    /// Bytes that were read ahead but not yielded as lines yet are discarded.
+    pub fn into_inner(self) -> T {
+        self.io.into_inner().into_inner().1
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T: Read> Iterator for Lines<T> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        if self.done {
+            return None;
+        }
+
+        let mut line = String::new();
+        match self.io.read_line(&mut line) {
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Some(Ok(line))
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T: Read> Iterator for Chunks<T> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if self.done {
+            return None;
+        }
+
+        let mut buf = vec![0; self.chunk_size];
+        loop {
+            match self.io.read(&mut buf) {
+                Ok(0) => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(n) => {
+                    buf.truncate(n);
+                    return Some(Ok(buf));
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+impl<T: Read + Send + 'static> Blocking<T> {
+    /// Spawns a task that reads from the idle I/O handle into a pipe, moving into the reading
+    /// state.
+    fn start_reading(&mut self) -> io::Result<()> {
+        // Keep the I/O handle if the pool has shut down, or else the task would drop it.
+        self.executor().check_running()?;
+        let mut io = match &mut self.state {
+            State::Idle(io) => io.take().expect("inner value was taken out"),
+            _ => unreachable!("reading must start in idle state"),
+        };
+        let (reader, mut writer) = self.pipe();
+
+        // Read files through io_uring if possible so that the task doesn't occupy a thread while
+        // waiting for the kernel.
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(fd) = uring_fd(&*io) {
+            let task = self.executor().spawn_io(async move {
+                let res = uring::copy_from_file(fd, &mut writer).await;
+                (res, io)
+            });
+            self.state = State::Reading(Some(reader), task);
+            return Ok(());
+        }
+
+        // Spawn a blocking task that reads and returns the I/O handle when done.
+        let task = self.executor().spawn_io(async move {
+            // Copy bytes from the I/O handle into the pipe until the pipe is closed or an error
+            // occurs.
+            loop {
+                match future::poll_fn(|cx| writer.poll_write(cx, ReadSource(&mut io))).await {
+                    Ok(0) => return (Ok(()), io),
+                    Ok(_) => {}
+                    Err(err) => return (Err(err), io),
+                }
+            }
+        });
+        self.state = State::Reading(Some(reader), task);
+        Ok(())
+    }
+}
+
+impl<T: Read + Send + 'static> Blocking<T> {
+    /// Returns the number of bytes read from the I/O handle that haven't been consumed yet.
+    ///
+    /// Reads are buffered, so the I/O handle is usually ahead of what has been read through this
+    /// handle. This is useful for accounting for those bytes, e.g. to find the logical position in
+    /// a file before seeking it through [`get_mut()`][`Blocking::get_mut()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::io::Cursor;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut reader = Blocking::new(Cursor::new(b"hello world".to_vec()));
+    ///
+    /// let mut buf = [0; 5];
+    /// reader.read_exact(&mut buf).await?;
+    ///
+    /// // Stopping the reading task keeps the bytes it has already read.
+    /// let cursor = reader.get_mut().await;
+    /// assert_eq!(cursor.position(), 11);
+    /// assert_eq!(reader.buffered_len(), 6);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn buffered_len(&self) -> usize {
+        let read_ahead = self.read_ahead.as_ref().map_or(0, |reader| reader.len());
+        let reading = match &self.state {
+            State::Reading(Some(reader), _) => reader.len(),
+            _ => 0,
+        };
+        read_ahead + reading
+    }
+
+    /// Takes out the bytes read from the I/O handle that haven't been consumed yet.
+    ///
+    /// Subsequent reads continue after the taken bytes. This is useful for recovering bytes that
+    /// would otherwise be served before the current position of the I/O handle, e.g. before
+    /// seeking a file through [`get_mut()`][`Blocking::get_mut()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::io::{Cursor, Seek, SeekFrom};
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut reader = Blocking::new(Cursor::new(b"hello world".to_vec()));
+    ///
+    /// let mut buf = [0; 5];
+    /// reader.read_exact(&mut buf).await?;
+    ///
+    /// // Rewind without reading stale bytes afterwards.
+    /// reader.get_mut().await.seek(SeekFrom::Start(0))?;
+    /// assert_eq!(reader.take_buffered(), b" world");
+    ///
+    /// let mut s = String::new();
+    /// reader.read_to_string(&mut s).await?;
+    /// assert_eq!(s, "hello world");
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn take_buffered(&mut self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.buffered_len());
+        if let Some(mut reader) = self.read_ahead.take() {
+            reader.drain_into(&mut buf);
+        }
+        if let State::Reading(Some(reader), _) = &mut self.state {
+            reader.drain_into(&mut buf);
+        }
+        buf
+    }
+
+    /// Reads bytes into `buf` without consuming them.
+    ///
+    /// Subsequent reads return the same bytes again, which is useful for detecting a protocol
+    /// before handing the handle over, e.g. telling TLS from plaintext on a socket. This waits
+    /// until some bytes are available and returns as soon as they are, so fewer bytes than
+    /// `buf.len()` may be returned. Zero means the end of the I/O handle has been reached.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::net::TcpStream;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut stream = Blocking::new(TcpStream::connect("127.0.0.1:8080")?);
+    ///
+    /// // A TLS connection starts with a handshake record.
+    /// let mut byte = [0];
+    /// if stream.peek(&mut byte).await? == 1 && byte[0] == 0x16 {
+    ///     println!("TLS");
+    /// }
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        future::poll_fn(|cx| self.poll_peek(cx, buf)).await
+    }
+
+    /// Attempts to read bytes into `buf` without consuming them.
+    ///
+    /// This is the polling version of [`peek()`][`Blocking::peek()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::future;
+    /// use std::io::Cursor;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut reader = Blocking::new(Cursor::new(b"hello".to_vec()));
+    ///
+    /// let mut buf = [0; 5];
+    /// let n = future::poll_fn(|cx| reader.poll_peek(cx, &mut buf)).await?;
+    /// assert_eq!(&buf[..n], b"hello");
+    ///
+    /// let mut s = String::new();
+    /// reader.read_to_string(&mut s).await?;
+    /// assert_eq!(s, "hello");
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn poll_peek(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let res = self.poll_fill(cx);
+        if !ready!(self.poll_read_timeout(cx, res))? {
+            return Poll::Ready(Ok(0));
+        }
+
+        match (&mut self.read_ahead, &mut self.state) {
+            (Some(reader), _) => Poll::Ready(Ok(reader.peek_into(buf))),
+            (None, State::Reading(Some(reader), _)) => Poll::Ready(Ok(reader.peek_into(buf))),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Reads all bytes until the end of the I/O handle and appends them to `buf`.
+    ///
+    /// Unlike [`AsyncReadExt::read_to_end()`][read_to_end], this doesn't go through the pipe, and
+    /// the whole read loop runs in a single blocking task instead. That avoids waking up the
+    /// current task for every chunk of bytes, which makes reading entire files considerably
+    /// faster.
+    ///
+    /// On success, the number of bytes appended to `buf` is returned. If an error occurs, the bytes
+    /// read before it are still appended to `buf`. If the returned future is dropped before
+    /// completion or the [read timeout][`Blocking::set_read_timeout()`] elapses, the read bytes
+    /// are lost, though the I/O handle is retrieved by the next operation on this handle.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::open("file.txt")?);
+    ///
+    /// let mut contents = Vec::new();
+    /// file.read_all(&mut contents).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    ///
+    /// [read_to_end]: https://docs.rs/futures/0.3/futures/io/trait.AsyncReadExt.html#method.read_to_end
+    pub async fn read_all(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let (res, mut bytes) = self
+            .with_buffered(|mut bytes, io| {
+                let res = io.read_to_end(&mut bytes);
+                (res, bytes)
+            })
+            .await?;
+
+        let n = bytes.len();
+        if buf.is_empty() {
+            *buf = bytes;
+        } else {
+            buf.append(&mut bytes);
+        }
+        res.map(|_| n)
+    }
+
+    /// Reads all bytes until the end of the I/O handle and appends them to `buf` as UTF-8.
+    ///
+    /// Like [`read_all()`][`Blocking::read_all()`], the whole read loop runs in a single
+    /// blocking task, which also validates the bytes. If they are not valid UTF-8, an error of
+    /// kind [`io::ErrorKind::InvalidData`] is returned and `buf` is left unchanged.
+    ///
+    /// On success, the number of bytes appended to `buf` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::open("file.txt")?);
+    ///
+    /// let mut contents = String::new();
+    /// file.read_all_to_string(&mut contents).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn read_all_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
+        let s = self
+            .with_buffered(|mut bytes, io| {
+                let res = io.read_to_end(&mut bytes);
+                match String::from_utf8(bytes) {
+                    Ok(s) => res.map(|_| s),
+                    Err(_) => res.and_then(|_| {
+                        Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "stream did not contain valid UTF-8",
+                        ))
+                    }),
+                }
+            })
+            .await??;
+
+        let n = s.len();
+        if buf.is_empty() {
+            *buf = s;
+        } else {
+            buf.push_str(&s);
+        }
+        Ok(n)
+    }
+
+    /// Runs a blocking operation on the I/O handle, passing it the bytes read ahead.
+    ///
+    /// Errors of the stopped task are reported before the operation runs, and the operation fails
+    /// if it takes longer than the read timeout.
+    async fn with_buffered<R, F>(&mut self, f: F) -> io::Result<R>
+    where
+        F: FnOnce(Vec<u8>, &mut T) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let timeout = self.settings.as_ref().and_then(|s| s.read_timeout);
+        let mut timer = None;
+        let mut op = pin::pin!(async {
+            future::poll_fn(|cx| self.poll_stop(cx)).await?;
+            let buffered = self.take_buffered();
+            Ok(self.with(move |io| f(buffered, io)).await)
+        });
+        future::poll_fn(|cx| {
+            let poll = op.as_mut().poll(cx);
+            poll_timeout(cx, timeout, &mut timer, poll)
+        })
+        .await
+    }
+
+    /// Reads bytes from the pipe with `read`, failing if the read timeout elapses.
+    fn poll_read_with(
+        &mut self,
+        cx: &mut Context<'_>,
+        read: impl FnMut(&mut Reader, &mut Context<'_>) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>> {
+        let res = self.poll_read_pipe_with(cx, read);
+        self.poll_read_timeout(cx, res)
+    }
+
+    /// Reads bytes from the pipe with `read`, starting a reading task if needed.
+    fn poll_read_pipe_with(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut read: impl FnMut(&mut Reader, &mut Context<'_>) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>> {
+        // Serve bytes read ahead before the last mode switch first.
+        if let Some(reader) = &mut self.read_ahead {
+            let res = ready!(read(reader, cx));
+            if reader.is_empty() {
+                self.read_ahead = None;
+            }
+            return Poll::Ready(res);
+        }
+
+        loop {
+            match &mut self.state {
+                // If not in idle or active reading state, stop the running task.
+                State::Task(..)
+                | State::Seeking(..)
+                | State::Reading(None, _)
+                | State::Streaming(..)
+                | State::Writing(..) => {
+                    // Wait for the running task to stop.
+                    ready!(self.poll_stop(cx))?;
+                }
+
+                // If idle, start a reading task.
+                State::Idle(_) => {
+                    // Move into the busy state and poll again.
+                    self.start_reading()?;
+                }
+
+                // If reading, read bytes from the pipe.
+                State::Reading(Some(reader), task) => {
+                    // Poll the pipe.
+                    let n = ready!(read(reader, cx))?;
+
+                    // If the pipe is closed, retrieve the I/O handle back from the blocking task.
+                    // This is not really a required step, but it's cleaner to drop the handle on
+                    // the same thread that created it.
+                    if n == 0 {
+                        // Poll the task to retrieve the I/O handle.
+                        let (res, io) = ready!(Pin::new(task).poll(cx));
+                        // Make sure to move into the idle state before reporting errors.
+                        self.state = State::Idle(Some(io));
+                        res?;
+                    }
+
+                    return Poll::Ready(Ok(n));
+                }
+            }
+        }
+    }
+
+    /// Waits until there are bytes to read, returning `false` if the I/O handle has reached its
+    /// end.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        // Serve bytes read ahead before the last mode switch first.
+        if let Some(reader) = &mut self.read_ahead {
+            ready!(reader.poll_available(cx));
+            return Poll::Ready(Ok(true));
+        }
+
+        loop {
+            match &mut self.state {
+                // If not in idle or active reading state, stop the running task.
+                State::Task(..)
+                | State::Seeking(..)
+                | State::Reading(None, _)
+                | State::Streaming(..)
+                | State::Writing(..) => {
+                    // Wait for the running task to stop.
+                    ready!(self.poll_stop(cx))?;
+                }
+
+                // If idle, start a reading task.
+                State::Idle(_) => self.start_reading()?,
+
+                // If reading, wait for bytes in the pipe.
+                State::Reading(Some(reader), task) => {
+                    if ready!(reader.poll_available(cx)) > 0 {
+                        return Poll::Ready(Ok(true));
+                    }
+
+                    // The pipe is closed, so retrieve the I/O handle back from the blocking task.
+                    let (res, io) = ready!(Pin::new(task).poll(cx));
+                    // Make sure to move into the idle state before reporting errors.
+                    self.state = State::Idle(Some(io));
+                    res?;
+                    return Poll::Ready(Ok(false));
+                }
+            }
+        }
+    }
+}
+
+impl<T: Read + Send + 'static> AsyncRead for Blocking<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_read_with(cx, |reader, cx| reader.poll_read(cx, &mut *buf))
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        // Reading into no buffers would look like the end of the pipe.
+        if bufs.iter().all(|buf| buf.is_empty()) {
+            return Poll::Ready(Ok(0));
+        }
+
+        self.get_mut()
+            .poll_read_with(cx, |reader, cx| reader.poll_read_vectored(cx, bufs))
+    }
+}
+
+impl<T: Read + Send + 'static> AsyncBufRead for Blocking<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+
+        // Wait until there are bytes to read. The buffer is borrowed only afterwards because the
+        // state may need to change while waiting.
+        let res = this.poll_fill(cx);
+        if !ready!(this.poll_read_timeout(cx, res))? {
+            return Poll::Ready(Ok(&[]));
+        }
+
+        match (&this.read_ahead, &this.state) {
+            (Some(reader), _) => Poll::Ready(Ok(reader.filled())),
+            (None, State::Reading(Some(reader), _)) => Poll::Ready(Ok(reader.filled())),
+            _ => unreachable!(),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        if let Some(reader) = &mut this.read_ahead {
+            reader.consume(amt);
+            if reader.is_empty() {
+                this.read_ahead = None;
+            }
+        } else if let State::Reading(Some(reader), _) = &mut this.state {
+            reader.consume(amt);
+        }
+    }
+}
+
+impl<T: Write + Send + 'static> Blocking<T> {
+    /// Sets whether to finish writing in the background when this handle is dropped.
+    ///
+    /// By default, dropping a handle cancels the task writing into the I/O handle, so bytes that
+    /// haven't been flushed yet are lost. If this option is enabled, the task is detached instead
+    /// and keeps running until it has written all remaining bytes and flushed the I/O handle.
+    ///
+    /// This is a best-effort measure for callers who forget to flush or close the handle. Errors
+    /// that occur in the background are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::create("file.txt")?);
+    /// file.set_flush_on_drop(true);
+    ///
+    /// // The bytes are written even though the handle is not flushed.
+    /// file.write_all(b"Hello world!").await?;
+    /// drop(file);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn set_flush_on_drop(&mut self, flush_on_drop: bool) {
+        self.settings_mut().flush_on_drop = flush_on_drop;
+    }
+
+    /// Flushes the handle, stops the writing task, and extracts the inner blocking I/O handle.
+    ///
+    /// Unlike [`AsyncWriteExt::close()`][close], which drops the I/O handle, this method hands it
+    /// back so that it can be reused, e.g. to read back a file that has just been written. If
+    /// flushing fails, the error is returned and the I/O handle is dropped.
+    ///
+    /// [close]: https://docs.rs/futures/0.3/futures/io/trait.AsyncWriteExt.html#method.close
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::fs::File;
+    /// use std::io::{Seek, SeekFrom};
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut file = Blocking::new(File::create("file.txt")?);
+    /// file.write_all(b"Hello world!").await?;
+    ///
+    /// let mut file = file.close_into_inner().await?;
+    /// file.seek(SeekFrom::Start(0))?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn close_into_inner(self) -> io::Result<T> {
+        // There's a bug in rustdoc causing it to render `mut self` as `__arg0: Self`, so we just
+        // bind `self` to a local mutable variable.
+        let mut this = self;
+
+        // Wait for the writing task to flush the I/O handle and stop.
+        future::poll_fn(|cx| Pin::new(&mut this).poll_flush(cx)).await?;
+
+        // Assume idle state and extract the inner value.
+        match &mut this.state {
+            State::Idle(t) => Ok(*t.take().expect("inner value was taken out")),
+            State::Streaming(..)
+            | State::Reading(..)
+            | State::Writing(..)
+            | State::Task(..)
+            | State::Seeking(..) => {
+                unreachable!("when flushed, the state machine must be in idle state");
+            }
+        }
+    }
+
+    /// Converts this handle into a [`Sink`] of byte buffers.
+    ///
+    /// Every buffer sent into the sink is written in full into the inner I/O handle, so streams of
+    /// buffers can be forwarded into a blocking writer without looping over
+    /// [`AsyncWriteExt::write_all()`][write_all]. Buffers can be of any type that implements
+    /// `AsRef<[u8]>`, like `Vec<u8>` or `&[u8]`.
+    ///
+    /// This method is only available with the `sink` feature.
+    ///
+    /// [write_all]: https://docs.rs/futures/0.3/futures/io/trait.AsyncWriteExt.html#method.write_all
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::fs::File;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let lines = stream::iter(vec!["first\n", "second\n"]);
+    /// let mut sink = Blocking::new(File::create("file.txt")?).into_sink();
+    ///
+    /// lines.map(|line| Ok(line.as_bytes())).forward(&mut sink).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    #[cfg(feature = "sink")]
+    pub fn into_sink<B: AsRef<[u8]>>(self) -> BlockingSink<T, B> {
+        BlockingSink {
+            io: self,
+            buf: None,
+            pos: 0,
+        }
+    }
+
+    /// Spawns a task that writes from a pipe into the idle I/O handle, moving into the writing
+    /// state.
+    fn start_writing(&mut self) -> io::Result<()> {
+        // Keep the I/O handle if the pool has shut down, or else the task would drop it.
+        self.executor().check_running()?;
+        let mut io = match &mut self.state {
+            State::Idle(io) => io.take().expect("inner value was taken out"),
+            _ => unreachable!("writing must start in idle state"),
+        };
+        let (mut reader, writer) = self.pipe();
+
+        // Write files through io_uring if possible so that the task doesn't occupy a thread while
+        // waiting for the kernel.
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(fd) = uring_fd(&*io) {
+            let task = self.executor().spawn_io(async move {
+                let res = uring::copy_to_file(fd, &mut reader).await;
+                (res, io)
+            });
+            self.state = State::Writing(Some(writer), task);
+            return Ok(());
+        }
+
+        // Spawn a blocking task that writes and returns the I/O handle when done.
+        let task = self.executor().spawn_io(async move {
+            // Copy bytes from the pipe into the I/O handle until the pipe is closed or an error
+            // occurs. Flush the I/O handle at the end.
+            loop {
+                match future::poll_fn(|cx| reader.poll_read(cx, &mut io)).await {
+                    Ok(0) => return (io.flush(), io),
+                    Ok(_) => {}
+                    Err(err) => {
+                        let _ = io.flush();
+                        return (Err(err), io);
+                    }
+                }
+            }
+        });
+        self.state = State::Writing(Some(writer), task);
+        Ok(())
+    }
+
+    /// Writes bytes into the pipe with `write`, failing if the write timeout elapses.
+    fn poll_write_with(
+        &mut self,
+        cx: &mut Context<'_>,
+        write: impl FnOnce(&mut Writer, &mut Context<'_>) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>> {
+        let res = self.poll_write_pipe_with(cx, write);
+        self.poll_write_timeout(cx, res)
+    }
+
+    /// Writes bytes into the pipe with `write`, starting a writing task if needed.
+    fn poll_write_pipe_with(
+        &mut self,
+        cx: &mut Context<'_>,
+        write: impl FnOnce(&mut Writer, &mut Context<'_>) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            match &mut self.state {
+                // If not in idle or active writing state, stop the running task.
+                State::Task(..)
+                | State::Seeking(..)
+                | State::Writing(None, _)
+                | State::Streaming(..)
+                | State::Reading(..) => {
+                    // Wait for the running task to stop.
+                    ready!(self.poll_stop(cx))?;
+                }
+
+                // If idle, start the writing task.
+                State::Idle(_) => {
+                    // Move into the busy state.
+                    self.start_writing()?;
+                }
+
+                // If writing, write more bytes into the pipe.
+                State::Writing(Some(writer), _) => return write(writer, cx),
+            }
+        }
+    }
+}
+
+impl<T: Write + Send + 'static> AsyncWrite for Blocking<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_write_with(cx, |writer, cx| writer.poll_write(cx, buf))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_write_with(cx, |writer, cx| writer.poll_write_vectored(cx, bufs))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        // Stop the running task, which flushes the I/O handle because idle implies flushed.
+        let res = this.poll_stop(cx);
+        this.poll_write_timeout(cx, res)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // First, make sure the I/O handle is flushed.
+        ready!(Pin::new(&mut *self).poll_flush(cx))?;
+
+        // Then move into the idle state with no I/O handle, thus dropping it.
+        self.state = State::Idle(None);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: Seek + Send + 'static> AsyncSeek for Blocking<T> {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        loop {
+            match &mut self.state {
+                // If not in idle or seeking state, stop the running task. This flushes pending
+                // writes and keeps bytes read ahead, which are accounted for below.
+                State::Task(..)
+                | State::Streaming(..)
+                | State::Reading(..)
+                | State::Writing(..) => {
+                    // Wait for the running task to stop.
+                    ready!(self.poll_stop(cx))?;
+                }
+
+                // If idle, start a seeking task.
+                State::Idle(_) => self.start_seeking(pos)?,
+
+                // If seeking, wait for the task to complete.
+                State::Seeking(_) => return self.poll_seeking(cx),
+            }
+        }
+    }
+}
+
+impl<T: Seek + Send + 'static> Blocking<T> {
+    /// Spawns a task that seeks the I/O handle, moving from the idle into the seeking state.
+    fn start_seeking(&mut self, pos: SeekFrom) -> io::Result<()> {
+        // The I/O handle is ahead of the logical position by the number of bytes read ahead, so
+        // translate relative seeks and then discard those bytes.
+        let read_ahead = self.read_ahead.as_ref().map_or(0, |reader| reader.len());
+        let pos = match pos {
+            SeekFrom::Current(n) => match n.checked_sub(read_ahead as i64) {
+                Some(n) => SeekFrom::Current(n),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "seek offset overflowed",
+                    ))
+                }
+            },
+            pos => pos,
+        };
+
+        // Keep the I/O handle if the pool has shut down, or else the task would drop it.
+        self.executor().check_running()?;
+
+        let mut io = match &mut self.state {
+            State::Idle(io) => io.take().expect("inner value was taken out"),
+            _ => unreachable!("seeking must start in idle state"),
+        };
+        self.read_ahead = None;
+
+        // Spawn a blocking task that seeks and returns the I/O handle.
+        let task = self.executor().spawn_io(async move {
+            let res = io.seek(pos);
+            (res, io)
+        });
+        self.state = State::Seeking(task);
+        Ok(())
+    }
+
+    /// Waits for the seeking task to complete, moving back into the idle state.
+    fn poll_seeking(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match &mut self.state {
+            State::Seeking(task) => {
+                let (res, io) = ready!(Pin::new(task).poll(cx));
+                self.state = State::Idle(Some(io));
+                Poll::Ready(res)
+            }
+            _ => unreachable!("not in seeking state"),
+        }
+    }
+}
+
+/// Copies all bytes from one [`Blocking`] handle into another.
+///
+/// Copying with [`futures::io::copy()`][copy] moves bytes from the reading task through a pipe into
+/// the current task, and then through another pipe into the writing task. This function instead
+/// runs [`std::io::copy()`] on both I/O handles in a single blocking task, so bytes never leave the
+/// pool. Bytes already read ahead by `reader` are written first, and `writer` is flushed at the
+/// end.
+///
+/// On success, the number of copied bytes is returned. If the returned future is dropped before
+/// completion, copying still continues in the background, and the I/O handles are retrieved by the
+/// next operations on the two handles.
+///
+/// # Examples
+///
+/// ```no_run
+/// use blocking::Blocking;
+/// use std::fs::File;
+///
+/// # futures::executor::block_on(async {
+/// let mut src = Blocking::new(File::open("input.txt")?);
+/// let mut dst = Blocking::new(File::create("output.txt")?);
+///
+/// let n = blocking::copy(&mut src, &mut dst).await?;
+/// println!("copied {} bytes", n);
+/// # std::io::Result::Ok(()) });
+/// ```
+///
+/// [copy]: https://docs.rs/futures/0.3/futures/io/fn.copy.html
+pub async fn copy<R, W>(reader: &mut Blocking<R>, writer: &mut Blocking<W>) -> io::Result<u64>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    // Wait for the running tasks to stop, making sure pending writes are flushed.
+    future::poll_fn(|cx| reader.poll_stop(cx)).await?;
+    future::poll_fn(|cx| writer.poll_stop(cx)).await?;
+
+    // Assume idle state and take the inner values out.
+    let buffered = reader.take_buffered();
+    let mut src = match &mut reader.state {
+        State::Idle(t) => t.take().expect("inner value was taken out"),
+        State::Streaming(..)
+        | State::Reading(..)
+        | State::Writing(..)
+        | State::Task(..)
+        | State::Seeking(..) => {
+            unreachable!("when stopped, the state machine must be in idle state");
+        }
+    };
+    let dst = match &mut writer.state {
+        State::Idle(t) => t.take().expect("inner value was taken out"),
+        State::Streaming(..)
+        | State::Reading(..)
+        | State::Writing(..)
+        | State::Task(..)
+        | State::Seeking(..) => {
+            unreachable!("when stopped, the state machine must be in idle state");
+        }
+    };
+
+    // The copying task returns the reader, and hands the writer over to a task that waits for it,
+    // so that both can be retrieved even if this future gets dropped.
+    let handoff = Arc::new(Handoff {
+        io: Mutex::new(None),
+        ready: Event::new(),
+    });
+    let slot = Arc::new(Mutex::new(None));
+
+    let mut guard = HandoffGuard {
+        io: Some(dst),
+        handoff: handoff.clone(),
+    };
+    let task = reader.executor().spawn({
+        let slot = slot.clone();
+        async move {
+            let dst = guard.io.as_mut().unwrap();
+            let res = copy_buffered(&buffered, &mut src, dst);
+            *slot.lock().unwrap() = Some(res);
+            drop(guard);
+            *src
+        }
+    });
+    reader.state = State::Task(task);
+
+    let task = writer
+        .executor()
+        .spawn(async move { *handoff.take().await });
+    writer.state = State::Task(task);
+
+    // Wait for both tasks to complete and move back into the idle state.
+    let _ = future::poll_fn(|cx| reader.poll_stop(cx)).await;
+    let _ = future::poll_fn(|cx| writer.poll_stop(cx)).await;
+    let res = slot.lock().unwrap().take();
+    res.expect("copying didn't complete")
+}
+
+/// Writes `buffered` and then all bytes from `src` into `dst`, flushing it at the end.
+fn copy_buffered<R: Read, W: Write>(buffered: &[u8], src: &mut R, dst: &mut W) -> io::Result<u64> {
+    dst.write_all(buffered)?;
+    let n = io::copy(src, dst)?;
+    dst.flush()?;
+    Ok(buffered.len() as u64 + n)
+}
+
+/// An I/O handle handed over from one task to another.
+struct Handoff<T> {
+    /// The I/O handle, once handed over.
+    io: Mutex<Option<Box<T>>>,
+
+    /// Notified when the I/O handle is handed over.
+    ready: Event,
+}
+
+impl<T> Handoff<T> {
+    /// Waits until the I/O handle is handed over and takes it.
+    async fn take(&self) -> Box<T> {
+        loop {
+            if let Some(io) = self.io.lock().unwrap().take() {
+                return io;
+            }
+
+            // Start listening, then try again in case the handle was handed over in the meantime.
+            let listener = self.ready.listen();
+            if let Some(io) = self.io.lock().unwrap().take() {
+                return io;
+            }
+            listener.await;
+        }
+    }
+}
+
+/// Hands an I/O handle over on drop, even if the task holding it panics or gets canceled.
+struct HandoffGuard<T> {
+    /// The I/O handle to hand over.
+    io: Option<Box<T>>,
+
+    /// Where to hand the I/O handle over.
+    handoff: Arc<Handoff<T>>,
+}
+
+impl<T> Drop for HandoffGuard<T> {
+    fn drop(&mut self) {
+        *self.handoff.io.lock().unwrap() = self.io.take();
+        self.handoff.ready.notify(usize::MAX);
+    }
+}
+
+/// A [`Sink`] of byte buffers that writes them into a [`Blocking`] handle.
+///
+/// This sink is created by [`Blocking::into_sink()`]. Sent buffers are written lazily, so make sure
+/// to flush or close the sink to write everything into the inner I/O handle. Just like closing the
+/// [`Blocking`] handle itself, closing the sink drops the inner I/O handle.
+#[cfg(feature = "sink")]
+pub struct BlockingSink<T, B> {
+    /// The handle buffers are written into.
+    io: Blocking<T>,
+
+    /// The buffer that is currently being written.
+    buf: Option<B>,
+
+    /// The number of bytes of the current buffer that have been written.
+    pos: usize,
+}
+
+// Buffers are never pinned, so the sink can be moved around freely.
+#[cfg(feature = "sink")]
+impl<T, B> Unpin for BlockingSink<T, B> {}
+
+#[cfg(feature = "sink")]
+impl<T: Write + Send + 'static, B: AsRef<[u8]>> BlockingSink<T, B> {
+    /// Converts the sink back into the [`Blocking`] handle.
+    ///
+    /// The part of a sent buffer that hasn't been written yet is discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut sink = Blocking::new(Vec::new()).into_sink();
+    /// sink.send(b"hello").await?;
+    ///
+    /// let writer = sink.into_inner();
+    /// assert_eq!(writer.await, b"hello");
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn into_inner(self) -> Blocking<T> {
+        self.io
+    }
+
+    /// Writes the rest of the current buffer into the handle.
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(buf) = &self.buf {
+            let data = &buf.as_ref()[self.pos..];
+            if data.is_empty() {
+                self.buf = None;
+                break;
+            }
+
+            match ready!(Pin::new(&mut self.io).poll_write(cx, data))? {
+                0 => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                n => self.pos += n,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "sink")]
+impl<T: Write + Send + 'static, B: AsRef<[u8]>> Sink<B> for BlockingSink<T, B> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_write_buf(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: B) -> io::Result<()> {
+        let this = self.get_mut();
+        this.buf = Some(item);
+        this.pos = 0;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.io).poll_close(cx)
+    }
+}
+
+impl<T: Send + Sync + 'static> Blocking<T>
+where
+    for<'a> &'a T: Read + Write,
+{
+    /// Splits this handle into separate read and write halves.
+    ///
+    /// The two halves share the inner I/O handle and can be used independently from different
+    /// tasks, so reading doesn't stop writing and vice versa. This works for I/O handles that can
+    /// be read from and written into through a shared reference, like [`TcpStream`] or [`File`].
+    ///
+    /// The halves run on the same pool as this handle. Use [`ReadHalf::reunite()`] to put them
+    /// back together.
+    ///
+    /// This is an async method because the I/O handle might be on a different thread and needs to
+    /// be moved onto the current thread before we can share it.
+    ///
+    /// [`TcpStream`]: std::net::TcpStream
+    /// [`File`]: std::fs::File
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use futures::prelude::*;
+    /// use std::net::TcpStream;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let stream = Blocking::new(TcpStream::connect("example.com:80")?);
+    /// let (mut reader, mut writer) = stream.split().await;
+    ///
+    /// let request = async {
+    ///     writer.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+    ///     writer.flush().await
+    /// };
+    /// let mut response = Vec::new();
+    /// future::try_join(request, reader.read_to_end(&mut response)).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn split(self) -> (ReadHalf<T>, WriteHalf<T>) {
+        // There's a bug in rustdoc causing it to render `mut self` as `__arg0: Self`, so we just
+        // bind `self` to a local mutable variable.
+        let mut this = self;
+
+        // Wait for the running task to stop and hand bytes read ahead over to the read half.
+        let _ = future::poll_fn(|cx| this.poll_stop(cx)).await;
+        let read_ahead = this.read_ahead.take();
+
+        let pool = this.pool.clone();
+        let capacity = this.capacity;
+        let settings = this.settings.take();
+        let (mut reader, writer) = split_io(this.into_inner().await, pool, capacity, settings);
+        reader.inner.read_ahead = read_ahead;
+        (reader, writer)
+    }
+}
+
+/// Splits an I/O handle into read and write halves running on `pool` with the given configuration.
+fn split_io<T>(
+    io: T,
+    pool: Option<Pool>,
+    capacity: Option<NonZeroUsize>,
+    settings: Option<Box<Settings>>,
+) -> (ReadHalf<T>, WriteHalf<T>) {
+    let io = Arc::new(io);
+    let half = |io: &Arc<T>| {
+        let state = State::Idle(Some(Box::new(Shared(io.clone()))));
+        let mut half = Blocking::from_state(state, pool.clone());
+        half.capacity = capacity;
+        half.settings = settings.as_ref().map(|s| s.copy_config());
+        half
+    };
+    let reader = ReadHalf {
+        inner: half(&io),
+        io: io.clone(),
+    };
+    let writer = WriteHalf {
+        inner: half(&io),
+        io,
+    };
+    (reader, writer)
+}
+
+/// An I/O handle shared between the halves of a split [`Blocking`] handle.
+struct Shared<T>(Arc<T>);
+
+impl<T> Read for Shared<T>
+where
+    for<'a> &'a T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self.0).read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        (&*self.0).read_vectored(bufs)
+    }
+}
+
+impl<T> Write for Shared<T>
+where
+    for<'a> &'a T: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self.0).write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        (&*self.0).write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self.0).flush()
+    }
+}
+
+/// The read half of a [`Blocking`] handle, created by [`Blocking::split()`].
+pub struct ReadHalf<T> {
+    /// The handle reading from the shared I/O handle.
+    inner: Blocking<Shared<T>>,
+
+    /// The shared I/O handle, used to check that halves belong together.
+    io: Arc<T>,
+}
+
+/// The write half of a [`Blocking`] handle, created by [`Blocking::split()`].
+pub struct WriteHalf<T> {
+    /// The handle writing into the shared I/O handle.
+    inner: Blocking<Shared<T>>,
+
+    /// The shared I/O handle, used to check that halves belong together.
+    io: Arc<T>,
+}
+
+impl<T> ReadHalf<T> {
+    /// Returns `true` if this half and `other` were created by the same [`Blocking::split()`]
+    /// call.
+    pub fn is_pair_of(&self, other: &WriteHalf<T>) -> bool {
+        Arc::ptr_eq(&self.io, &other.io)
+    }
+
+    /// Puts the two halves back together into a [`Blocking`] handle.
+    ///
+    /// Bytes that have been read from the I/O handle but not consumed yet are kept for subsequent
+    /// reads, while bytes written into the write half are flushed first. If the halves weren't
+    /// created by the same [`Blocking::split()`] call, they are returned back as an error.
+    ///
+    /// Note that the read half might be blocked waiting for more bytes, in which case this method
+    /// waits until that read completes.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Blocking;
+    /// use std::net::TcpStream;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let stream = Blocking::new(TcpStream::connect("example.com:80")?);
+    /// let (reader, writer) = stream.split().await;
+    ///
+    /// let stream = reader.reunite(writer).await.unwrap();
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn reunite(self, other: WriteHalf<T>) -> Result<Blocking<T>, ReuniteError<T>> {
+        if !self.is_pair_of(&other) {
+            return Err(ReuniteError(self, other));
+        }
+
+        // Stop both tasks so that they give up their references to the I/O handle.
+        let ReadHalf { mut inner, io } = self;
+        let mut writer = other.inner;
+        let _ = future::poll_fn(|cx| inner.poll_stop(cx)).await;
+        let _ = future::poll_fn(|cx| writer.poll_stop(cx)).await;
+
+        let pool = inner.pool.take();
+        let capacity = inner.capacity;
+        let settings = inner.settings.as_ref().map(|s| s.copy_config());
+        let read_ahead = inner.read_ahead.take();
+        drop((inner, writer, other.io));
+
+        match Arc::try_unwrap(io) {
+            Ok(io) => {
+                let mut blocking = Blocking::from_state(State::Idle(Some(Box::new(io))), pool);
+                blocking.capacity = capacity;
+                blocking.settings = settings;
+                blocking.read_ahead = read_ahead;
+                Ok(blocking)
+            }
+            Err(_) => unreachable!("stopped halves must not hold the I/O handle"),
+        }
+    }
+}
+
+impl<T> WriteHalf<T> {
+    /// Returns `true` if this half and `other` were created by the same [`Blocking::split()`]
+    /// call.
+    pub fn is_pair_of(&self, other: &ReadHalf<T>) -> bool {
+        other.is_pair_of(self)
+    }
+
+    /// Puts the two halves back together into a [`Blocking`] handle.
+    ///
+    /// This is equivalent to calling [`ReadHalf::reunite()`] with this half.
+    pub async fn reunite(self, other: ReadHalf<T>) -> Result<Blocking<T>, ReuniteError<T>> {
+        other.reunite(self).await
+    }
+}
+
+impl<T> AsyncRead for ReadHalf<T>
+where
+    T: Send + Sync + 'static,
+    for<'a> &'a T: Read,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read_vectored(cx, bufs)
+    }
+}
+
+impl<T> AsyncBufRead for ReadHalf<T>
+where
+    T: Send + Sync + 'static,
+    for<'a> &'a T: Read,
+{
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.inner).consume(amt)
+    }
+}
+
+impl<T> AsyncWrite for WriteHalf<T>
+where
+    T: Send + Sync + 'static,
+    for<'a> &'a T: Write,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Async I/O that reads and writes at the same time.
+///
+/// A [`Blocking`] handle runs a single task at a time, so writing into it stops the task reading
+/// from it and vice versa. That makes it unsuitable for protocols that read and write concurrently
+/// on a single connection. A [`Duplex`] handle instead runs separate tasks for reading and
+/// writing, so [`AsyncRead`] and [`AsyncWrite`] operations can be interleaved freely.
+///
+/// This works for I/O handles that can be read from and written into through a shared reference,
+/// like [`TcpStream`] or [`File`].
+///
+/// [`TcpStream`]: std::net::TcpStream
+/// [`File`]: std::fs::File
+///
+/// # Examples
+///
+/// ```no_run
+/// use blocking::Duplex;
+/// use futures::prelude::*;
+/// use std::net::TcpStream;
+///
+/// # futures::executor::block_on(async {
+/// let mut stream = Duplex::new(TcpStream::connect("example.com:80")?);
+///
+/// // Writing doesn't interrupt bytes already being read in the background.
+/// stream.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+/// stream.flush().await?;
+///
+/// let mut response = Vec::new();
+/// stream.read_to_end(&mut response).await?;
+/// # std::io::Result::Ok(()) });
+/// ```
+pub struct Duplex<T> {
+    /// The half reading from the I/O handle.
+    reader: ReadHalf<T>,
+
+    /// The half writing into the I/O handle.
+    writer: WriteHalf<T>,
+}
+
+impl<T> Duplex<T> {
+    /// Wraps a blocking I/O handle into an async interface for reading and writing at the same
+    /// time.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Duplex;
+    /// use std::net::TcpStream;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let stream = Duplex::new(TcpStream::connect("example.com:80")?);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn new(io: T) -> Duplex<T> {
+        let (reader, writer) = split_io(io, None, None, None);
+        Duplex { reader, writer }
+    }
+
+    /// Wraps a blocking I/O handle into an async interface for reading and writing at the same
+    /// time, running on the given pool.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::{Duplex, Pool};
+    /// use std::net::TcpStream;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let pool = Pool::new();
+    /// let stream = Duplex::new_on(&pool, TcpStream::connect("example.com:80")?);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn new_on(pool: &Pool, io: T) -> Duplex<T> {
+        let (reader, writer) = split_io(io, Some(pool.clone()), None, None);
+        Duplex { reader, writer }
+    }
+
+    /// Splits this handle into its read and write halves.
+    pub fn into_split(self) -> (ReadHalf<T>, WriteHalf<T>) {
+        (self.reader, self.writer)
+    }
+
+    /// Extracts the inner blocking I/O handle.
+    ///
+    /// Bytes that have been read from the I/O handle but not consumed yet are discarded, while
+    /// bytes written into the handle are flushed first. To keep the buffered bytes, put the halves
+    /// back together with [`into_split()`][`Duplex::into_split()`] and [`ReadHalf::reunite()`],
+    /// and take them out with [`Blocking::take_buffered()`] before extracting the I/O handle.
+    ///
+    /// Note that if the reading task is blocked waiting for more bytes, this method waits until
+    /// that read completes.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use blocking::Duplex;
+    /// use std::net::TcpStream;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let stream = Duplex::new(TcpStream::connect("example.com:80")?);
+    /// let stream = stream.into_inner().await;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn into_inner(self) -> T {
+        match self.reader.reunite(self.writer).await {
+            Ok(io) => io.into_inner().await,
+            Err(_) => unreachable!("halves of a duplex handle must belong together"),
+        }
+    }
+}
+
+impl<T> AsyncRead for Duplex<T>
+where
+    T: Send + Sync + 'static,
+    for<'a> &'a T: Read,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.reader).poll_read_vectored(cx, bufs)
+    }
+}
+
+impl<T> AsyncBufRead for Duplex<T>
+where
+    T: Send + Sync + 'static,
+    for<'a> &'a T: Read,
+{
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().reader).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.reader).consume(amt)
+    }
+}
+
+impl<T> AsyncWrite for Duplex<T>
+where
+    T: Send + Sync + 'static,
+    for<'a> &'a T: Write,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_close(cx)
+    }
+}
+
+/// An error returned when reuniting halves that weren't created by the same
+/// [`Blocking::split()`] call.
+///
+/// The error contains both halves, which can be extracted by destructuring it.
+pub struct ReuniteError<T>(pub ReadHalf<T>, pub WriteHalf<T>);
+
+impl<T> fmt::Debug for ReuniteError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReuniteError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for ReuniteError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("tried to reunite halves that are not from the same split")
+    }
+}
+
+impl<T> std::error::Error for ReuniteError<T> {}
+
+/// Returns the file descriptor of an I/O handle if it's a file that can be accessed through
+/// io_uring.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn uring_fd<T: 'static>(io: &T) -> Option<std::os::unix::io::RawFd> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = (io as &dyn Any).downcast_ref::<std::fs::File>()?;
+    if uring::is_available() {
+        Some(file.as_raw_fd())
+    } else {
+        None
+    }
+}
+
+/// Creates a bounded single-producer single-consumer pipe.
+///
+/// A pipe is a ring buffer of `cap` bytes, with a [`Writer`] implementing [`AsyncWrite`] on one
+/// end and a [`Reader`] implementing [`AsyncRead`] and [`AsyncBufRead`] on the other. The two
+/// halves can be moved to different tasks to bridge a producer and a consumer of bytes.
+///
+/// When the writer is dropped or closed, remaining bytes in the pipe can still be read. After
+/// that, attempts to read will result in `Ok(0)`, i.e. they will always 'successfully' read 0
+/// bytes.
+///
+/// When the reader is dropped, the pipe is closed and no more bytes can be written into it.
+/// Further writes will result in `Ok(0)`, i.e. they will always 'successfully' write 0 bytes.
+///
+/// The halves also implement [`Read`] and [`Write`] by blocking the current thread, so a pipe can
+/// bridge a synchronous thread and an async task in either direction.
+///
+/// # Panics
+///
+/// This function panics if `cap` is zero or too large.
+///
+/// # Examples
+///
+/// ```
+/// use futures::prelude::*;
+///
+/// # futures::executor::block_on(async {
+/// let (mut reader, mut writer) = blocking::pipe(1024);
+///
+/// let producer = async move {
+///     writer.write_all(b"hello").await?;
+///     writer.close();
+///     Ok(())
+/// };
+/// let mut s = String::new();
+/// future::try_join(producer, reader.read_to_string(&mut s)).await?;
+/// assert_eq!(s, "hello");
+/// # std::io::Result::Ok(()) });
+/// ```
+///
+/// Writing from a synchronous thread:
+///
+/// ```
+/// use futures::io::AsyncReadExt;
+/// use std::io::Write;
+/// use std::thread;
+///
+/// # futures::executor::block_on(async {
+/// let (mut reader, mut writer) = blocking::pipe(4);
+///
+/// thread::spawn(move || writer.write_all(b"hello world"));
+///
+/// let mut s = String::new();
+/// reader.read_to_string(&mut s).await?;
+/// assert_eq!(s, "hello world");
+/// # std::io::Result::Ok(()) });
+/// ```
+pub fn pipe(cap: usize) -> (Reader, Writer) {
+    growable_pipe(cap, cap)
+}
+
+/// Creates a bounded single-producer single-consumer pipe that grows when it's full.
+///
+/// This is just like [`pipe()`], except that the pipe starts with a buffer of `initial_cap` bytes.
+/// Whenever the writer finds it full, the writer moves on to a buffer twice as large, up to
+/// `max_cap` bytes, and the reader follows once it has read the bytes in the smaller buffer. This
+/// way, a pipe that is read as fast as it's written doesn't allocate a large buffer up front.
+///
+/// # Panics
+///
+/// This function panics if `initial_cap` is zero, if it's larger than `max_cap`, or if `max_cap`
+/// is too large.
+///
+/// # Examples
+///
+/// ```
+/// use futures::prelude::*;
+///
+/// # futures::executor::block_on(async {
+/// let (mut reader, mut writer) = blocking::growable_pipe(4, 64);
+/// assert_eq!(writer.capacity(), 4);
+///
+/// writer.write_all(b"hello world").await?;
+/// assert_eq!(writer.capacity(), 8);
+/// writer.close();
+///
+/// let mut s = String::new();
+/// reader.read_to_string(&mut s).await?;
+/// assert_eq!(s, "hello world");
+/// # std::io::Result::Ok(()) });
+/// ```
+pub fn growable_pipe(initial_cap: usize, max_cap: usize) -> (Reader, Writer) {
+    assert!(initial_cap > 0, "capacity must be positive");
+    assert!(
+        initial_cap <= max_cap,
+        "initial capacity must not exceed the maximum capacity"
+    );
+    assert!(max_cap.checked_mul(2).is_some(), "capacity is too large");
+
+    new_pipe(initial_cap, max_cap, PipeShared::default())
+}
+
+/// Creates a growable pipe with the given shared state.
+fn new_pipe(initial_cap: usize, max_cap: usize, shared: PipeShared) -> (Reader, Writer) {
+    let inner = Pipe::new(initial_cap, max_cap, Arc::new(shared), None);
+
+    let r = Reader {
+        inner: inner.clone(),
+        head: 0,
+        tail: 0,
+    };
+
+    let w = Writer {
+        inner,
+        head: 0,
+        tail: 0,
+        initialized: 0,
+    };
+
+    (r, w)
+}
+
+/// Creates a bounded single-producer single-consumer pipe backed by the given buffer.
+///
+/// This is just like [`pipe()`] with a capacity of `buffer.len()`, except that the pipe uses
+/// `buffer` as its ring buffer instead of allocating one. The contents of the buffer don't matter,
+/// and since it's already initialized, the writer doesn't zero it before first use. This is useful
+/// for reusing memory that is already paged in, e.g. from a pool of buffers. The buffer is
+/// deallocated when both halves are dropped.
+///
+/// # Panics
+///
+/// This function panics if `buffer` is empty or too large.
+///
+/// # Examples
+///
+/// ```
+/// use futures::prelude::*;
+///
+/// # futures::executor::block_on(async {
+/// let buffer = vec![0; 1024].into_boxed_slice();
+/// let (mut reader, mut writer) = blocking::pipe_with_buffer(buffer);
+/// assert_eq!(writer.capacity(), 1024);
+///
+/// writer.write_all(b"hello").await?;
+/// writer.close();
+///
+/// let mut s = String::new();
+/// reader.read_to_string(&mut s).await?;
+/// assert_eq!(s, "hello");
+/// # std::io::Result::Ok(()) });
+/// ```
+pub fn pipe_with_buffer(buffer: Box<[u8]>) -> (Reader, Writer) {
+    let cap = buffer.len();
+    assert!(cap > 0, "capacity must be positive");
+    assert!(cap.checked_mul(2).is_some(), "capacity is too large");
+
+    // SAFETY: `UnsafeCell<MaybeUninit<u8>>` has the same layout as `u8`.
+    let buffer =
+        unsafe { Box::from_raw(Box::into_raw(buffer) as *mut [UnsafeCell<MaybeUninit<u8>>]) };
+    let inner = Pipe::with_buffer(buffer, cap, Default::default(), None);
+
+    let r = Reader {
+        inner: inner.clone(),
+        head: 0,
+        tail: 0,
+    };
+
+    let w = Writer {
+        inner,
+        head: 0,
+        tail: 0,
+        // The whole buffer is already initialized.
+        initialized: cap,
+    };
+
+    (r, w)
+}
+
+/// The reading side of a pipe.
+///
+/// This type is created by [`pipe()`]. Besides [`AsyncRead`] and [`AsyncBufRead`], it implements
+/// [`Read`] by blocking the current thread until bytes are available.
+#[derive(Debug)]
+pub struct Reader {
+    /// The inner ring buffer.
+    inner: Arc<Pipe>,
+
+    /// The head index, moved by the reader, in the range `0..2*cap`.
+    ///
+    /// This index always matches `inner.head`.
+    head: usize,
+
+    /// The tail index, moved by the writer, in the range `0..2*cap`.
+    ///
+    /// This index is a snapshot of `index.tail` that might become stale at any point.
+    tail: usize,
+}
+
+/// The writing side of a pipe.
+///
+/// This type is created by [`pipe()`]. Besides [`AsyncWrite`], it implements [`Write`] by
+/// blocking the current thread until there's room in the pipe. To write into the pipe from
+/// multiple producers, convert it with [`Writer::into_shared()`].
+#[derive(Debug)]
+pub struct Writer {
+    /// The inner ring buffer.
+    inner: Arc<Pipe>,
+
+    /// The head index, moved by the reader, in the range `0..2*cap`.
+    ///
+    /// This index is a snapshot of `index.head` that might become stale at any point.
+    head: usize,
+
+    /// The tail index, moved by the writer, in the range `0..2*cap`.
+    ///
+    /// This index always matches `inner.tail`.
+    tail: usize,
+
+    /// How many bytes at the beginning of the buffer have been initialized.
+    ///
+    /// The pipe allocates an uninitialized buffer, and we must be careful about passing
+    /// uninitialized data to user code. Bytes copied from slices initialize the buffer as they
+    /// go, and only the part handed to a [`Read`] handle gets zeroed first. Since the writer
+    /// moves through the buffer in order, the initialized part is always a prefix of it.
+    initialized: usize,
+}
+
+/// The inner ring buffer.
+///
+/// Head and tail indices are in the range `0..2*cap`, even though they really map onto the
+/// `0..cap` range. The distance between head and tail indices is never more than `cap`.
+///
+/// The reason why indices are not in the range `0..cap` is because we need to distinguish between
+/// the pipe being empty and being full. If head and tail were in `0..cap`, then `head == tail`
+/// could mean the pipe is either empty or full, but we don't know which!
+///
+/// The fields touched on every read or write are padded to cache lines of their own, so the
+/// reader and writer threads don't contend for them.
+#[derive(Debug)]
+struct Pipe {
+    /// The head index, moved by the reader, in the range `0..2*cap`.
+    head: CachePadded<AtomicUsize>,
+
+    /// The tail index, moved by the writer, in the range `0..2*cap`.
+    tail: CachePadded<AtomicUsize>,
+
+    /// A waker representing the blocked reader.
+    reader: CachePadded<AtomicWaker>,
+
+    /// A waker representing the blocked writer.
+    writer: CachePadded<AtomicWaker>,
+
+    /// Set to `true` if the reader or writer was dropped.
+    closed: AtomicBool,
+
+    /// The byte buffer, initialized between the head and the tail.
+    buffer: Buffer,
+
+    /// The buffer capacity.
+    cap: usize,
+
+    /// The capacity the pipe can grow to.
+    max_cap: usize,
+
+    /// The larger buffer the writer has moved on to, once this one has been outgrown.
+    ///
+    /// When the writer moves on, it closes this buffer, so the reader switches to the next buffer
+    /// after reading all bytes in this one.
+    next: Mutex<Option<Arc<Pipe>>>,
+
+    /// The smaller buffer this one has replaced, as long as the reader hasn't moved on from it.
+    prev: Option<Weak<Pipe>>,
+
+    /// State shared by all buffers of the pipe.
+    shared: Arc<PipeShared>,
+}
+
+/// State shared by all buffers of a pipe, like counters tracking its throughput.
+///
+/// Each counter is only incremented by one side, and the two sides' counters live on separate
+/// cache lines.
+#[derive(Debug)]
+struct PipeShared {
+    /// The maximum number of bytes copied in one go before waking the other side.
+    chunk_size: AtomicUsize,
+
+    /// Whether buffers are kept in [`SPARE_BUFFERS`] for reuse once dropped.
+    recycle: bool,
+
+    /// Bytes written by the writer.
+    bytes_written: CachePadded<AtomicUsize>,
+
+    /// Wakeups of the reader issued by the writer.
+    reader_wakeups: AtomicUsize,
+
+    /// Bytes read by the reader.
+    bytes_read: CachePadded<AtomicUsize>,
+
+    /// Wakeups of the writer issued by the reader.
+    writer_wakeups: AtomicUsize,
+}
+
+impl Default for PipeShared {
+    fn default() -> PipeShared {
+        PipeShared {
+            chunk_size: AtomicUsize::new(DEFAULT_CHUNK_SIZE),
+            recycle: false,
+            bytes_written: Default::default(),
+            reader_wakeups: Default::default(),
+            bytes_read: Default::default(),
+            writer_wakeups: Default::default(),
+        }
+    }
+}
+
+impl PipeShared {
+    /// Sets how many bytes are copied in one go before waking the other side.
+    fn set_chunk_size(&self, chunk_size: usize) {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        self.chunk_size.store(chunk_size, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the counters.
+    fn metrics(&self) -> PipeMetrics {
+        PipeMetrics {
+            bytes_written: self.bytes_written.load(Ordering::Relaxed) as u64,
+            bytes_read: self.bytes_read.load(Ordering::Relaxed) as u64,
+            reader_wakeups: self.reader_wakeups.load(Ordering::Relaxed) as u64,
+            writer_wakeups: self.writer_wakeups.load(Ordering::Relaxed) as u64,
+        }
+    }
+}
+
+/// A snapshot of metrics for a pipe.
+///
+/// Comparing the counters over time shows where a pipeline is slow: if the writer keeps waking the
+/// reader but the pipe stays nearly empty, the source is the bottleneck, and if the pipe is mostly
+/// full and the reader keeps waking the writer, the consumer is.
+///
+/// Counters wrap around on overflow, which can only happen on 32-bit platforms.
+///
+/// # Examples
+///
+/// ```
+/// use futures::prelude::*;
+///
+/// # futures::executor::block_on(async {
+/// let (mut reader, mut writer) = blocking::pipe(1024);
+/// writer.write_all(b"hello").await?;
+///
+/// let mut buf = [0; 2];
+/// reader.read_exact(&mut buf).await?;
+///
+/// let metrics = reader.metrics();
+/// assert_eq!(metrics.bytes_written(), 5);
+/// assert_eq!(metrics.bytes_read(), 2);
+/// # std::io::Result::Ok(()) });
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PipeMetrics {
+    bytes_written: u64,
+    bytes_read: u64,
+    reader_wakeups: u64,
+    writer_wakeups: u64,
+}
+
+impl PipeMetrics {
+    /// Returns the total number of bytes written into the pipe.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Returns the total number of bytes read or skipped from the pipe.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the number of times the writer woke up the reader waiting for bytes.
+    pub fn reader_wakeups(&self) -> u64 {
+        self.reader_wakeups
+    }
+
+    /// Returns the number of times the reader woke up the writer waiting for room.
+    pub fn writer_wakeups(&self) -> u64 {
+        self.writer_wakeups
+    }
+}
+
+unsafe impl Sync for Pipe {}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        if !self.shared.recycle {
+            return;
+        }
+
+        // Keep the buffer for reuse if there's room among the spare buffers.
+        let mut spares = SPARE_BUFFERS.lock().unwrap();
+        let total: usize = spares.iter().map(|b| b.len()).sum();
+        if total + self.cap <= SPARE_BUFFERS_CAPACITY {
+            spares.push(mem::take(&mut self.buffer));
+        }
+    }
+}
+
+/// The byte buffer of a pipe.
+type Buffer = Box<[UnsafeCell<MaybeUninit<u8>>]>;
+
+/// Buffers of dropped pipes created by [`Blocking`] handles, kept for reuse.
+///
+/// Handles that alternate between reading and writing create a new pipe on every switch, so this
+/// saves them from allocating a new buffer each time.
+static SPARE_BUFFERS: Mutex<Vec<Buffer>> = Mutex::new(Vec::new());
+
+/// Pads and aligns a value to the length of a cache line.
+///
+/// On x86-64 and aarch64, the prefetcher pulls in pairs of 64-byte cache lines, so values are
+/// padded to 128 bytes there.
+#[cfg_attr(any(target_arch = "x86_64", target_arch = "aarch64"), repr(align(128)))]
+#[cfg_attr(
+    not(any(target_arch = "x86_64", target_arch = "aarch64")),
+    repr(align(64))
+)]
+#[derive(Debug, Default)]
+struct CachePadded<T>(T);
+
+impl<T> std::ops::Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl Pipe {
+    /// Allocates a ring buffer, reusing a spare buffer if the pipe recycles them.
+    fn new(
+        cap: usize,
+        max_cap: usize,
+        shared: Arc<PipeShared>,
+        prev: Option<Weak<Pipe>>,
+    ) -> Arc<Pipe> {
+        let spare = if shared.recycle {
+            let mut spares = SPARE_BUFFERS.lock().unwrap();
+            spares
+                .iter()
+                .position(|b| b.len() == cap)
+                .map(|i| spares.swap_remove(i))
+        } else {
+            None
+        };
+
+        let buffer = spare.unwrap_or_else(|| {
+            // Allocate the buffer without touching it.
+            let mut v = Vec::with_capacity(cap);
+            unsafe {
+                v.set_len(cap);
+            }
+            v.into_boxed_slice()
+        });
+
+        Pipe::with_buffer(buffer, max_cap, shared, prev)
+    }
+
+    /// Creates a ring buffer from the given buffer.
+    ///
+    /// If `prev` is given, the new buffer replaces it.
+    fn with_buffer(
+        buffer: Buffer,
+        max_cap: usize,
+        shared: Arc<PipeShared>,
+        prev: Option<Weak<Pipe>>,
+    ) -> Arc<Pipe> {
+        let cap = buffer.len();
+        Arc::new(Pipe {
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+            reader: CachePadded(AtomicWaker::new()),
+            writer: CachePadded(AtomicWaker::new()),
+            closed: AtomicBool::new(false),
+            buffer,
+            cap,
+            max_cap,
+            next: Mutex::new(None),
+            prev,
+            shared,
+        })
+    }
+
+    /// Returns a pointer to the start of the buffer.
+    fn ptr(&self) -> *mut u8 {
+        UnsafeCell::raw_get(self.buffer.as_ptr()).cast()
+    }
+
+    /// Wakes the blocked reader, if any.
+    fn wake_reader(&self) {
+        if let Some(waker) = self.reader.take() {
+            self.shared.reader_wakeups.fetch_add(1, Ordering::Relaxed);
+            waker.wake();
+        }
+    }
+
+    /// Wakes the blocked writer, if any.
+    fn wake_writer(&self) {
+        if let Some(waker) = self.writer.take() {
+            self.shared.writer_wakeups.fetch_add(1, Ordering::Relaxed);
+            waker.wake();
+        }
+    }
+
+    /// Returns the number of bytes in the buffer.
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head <= tail {
+            tail - head
+        } else {
+            2 * self.cap - (head - tail)
+        }
+    }
+
+    /// Returns the larger buffer the writer has moved on to.
+    fn next(&self) -> Option<Arc<Pipe>> {
+        self.next.lock().unwrap().clone()
+    }
+
+    /// Returns the buffer the writer is currently writing into.
+    fn last(self: &Arc<Self>) -> Arc<Pipe> {
+        let mut pipe = self.clone();
+        while let Some(next) = pipe.next() {
+            pipe = next;
+        }
+        pipe
+    }
+
+    /// Returns `true` if the writer has to wait for room in this buffer.
+    fn is_full(&self) -> bool {
+        self.len() == self.cap && self.cap == self.max_cap
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        // Dropping closes the pipe.
+        self.close();
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        // Dropping closes the pipe.
+        self.close();
+    }
+}
+
+impl Reader {
+    /// Closes the pipe and then wakes the writer.
+    ///
+    /// Further writes into the pipe will result in `Ok(0)`, but bytes remaining in the pipe can
+    /// still be read afterwards. Unlike dropping the reader, this keeps it around, e.g. to drain
+    /// the pipe or inspect it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (mut reader, mut writer) = blocking::pipe(1024);
+    /// writer.write_all(b"hello").await?;
+    ///
+    /// reader.close();
+    /// assert_eq!(writer.write(b"world").await?, 0);
+    ///
+    /// let mut s = String::new();
+    /// reader.read_to_string(&mut s).await?;
+    /// assert_eq!(s, "hello");
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn close(&self) {
+        // Close all buffers, including the one the writer is currently writing into.
+        let mut pipe = self.inner.clone();
+        loop {
+            pipe.closed.store(true, Ordering::SeqCst);
+            pipe.wake_writer();
+            match pipe.next() {
+                Some(next) => pipe = next,
+                None => break,
+            }
+        }
+    }
+
+    /// Moves on to the larger buffer the writer has moved on to, returning `false` if there's none.
+    fn advance(&mut self) -> bool {
+        match self.inner.next() {
+            Some(next) => {
+                self.inner = next;
+                self.head = 0;
+                self.tail = 0;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the number of bytes in the pipe.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (reader, mut writer) = blocking::pipe(1024);
+    /// writer.write_all(b"hello").await?;
+    /// assert_eq!(reader.len(), 5);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn len(&self) -> usize {
+        let cap = self.inner.cap;
+        let tail = self.inner.tail.load(Ordering::Acquire);
+        let mut len = if self.head <= tail {
+            tail - self.head
+        } else {
+            2 * cap - (self.head - tail)
+        };
+
+        // Count bytes in larger buffers the writer has moved on to.
+        let mut next = self.inner.next();
+        while let Some(pipe) = next {
+            len += pipe.len();
+            next = pipe.next();
+        }
+        len
+    }
+
+    /// Returns the capacity of the pipe.
+    ///
+    /// For a [growable pipe][`growable_pipe()`], this is the capacity of the buffer the writer is
+    /// currently writing into.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (reader, writer) = blocking::pipe(1024);
+    /// assert_eq!(reader.capacity(), 1024);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.inner.last().cap
+    }
+
+    /// Returns `true` if the pipe is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (reader, writer) = blocking::pipe(1024);
+    /// assert!(reader.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the pipe is full.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (reader, mut writer) = blocking::pipe(5);
+    /// writer.write_all(b"hello").await?;
+    /// assert!(reader.is_full());
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn is_full(&self) -> bool {
+        self.inner.last().is_full()
+    }
+
+    /// Returns a snapshot of metrics for the pipe.
+    ///
+    /// See [`PipeMetrics`] for more information.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (reader, writer) = blocking::pipe(1024);
+    /// assert_eq!(reader.metrics().bytes_read(), 0);
+    /// ```
+    pub fn metrics(&self) -> PipeMetrics {
+        self.inner.shared.metrics()
+    }
+
+    /// Sets how many bytes are copied in one go before waking the other side.
+    ///
+    /// This applies to both halves of the pipe. Smaller chunks get bytes to the other side
+    /// sooner, which suits interactive streams, while larger chunks mean fewer wakeups for bulk
+    /// transfers. The default is 128 KB.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (reader, writer) = blocking::pipe(1024 * 1024);
+    /// reader.set_chunk_size(4096);
+    /// assert_eq!(writer.chunk_size(), 4096);
+    /// ```
+    pub fn set_chunk_size(&self, chunk_size: usize) {
+        self.inner.shared.set_chunk_size(chunk_size);
+    }
+
+    /// Returns how many bytes are copied in one go before waking the other side.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (reader, writer) = blocking::pipe(1024);
+    /// assert_eq!(reader.chunk_size(), 128 * 1024);
+    /// ```
+    pub fn chunk_size(&self) -> usize {
+        self.inner.shared.chunk_size.load(Ordering::Relaxed)
+    }
+
+    /// Reads bytes into `buf` without consuming them.
+    ///
+    /// Subsequent reads return the same bytes again, so a parser can look ahead before deciding
+    /// how to read. This waits until some bytes are available and returns as soon as they are, so
+    /// fewer bytes than `buf.len()` may be returned. Zero means the pipe is empty and closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (mut reader, mut writer) = blocking::pipe(1024);
+    /// writer.write_all(b"hello").await?;
+    ///
+    /// let mut buf = [0; 2];
+    /// assert_eq!(reader.peek(&mut buf).await?, 2);
+    /// assert_eq!(&buf, b"he");
+    ///
+    /// let mut buf = [0; 5];
+    /// reader.read_exact(&mut buf).await?;
+    /// assert_eq!(&buf, b"hello");
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        future::poll_fn(|cx| self.poll_peek(cx, buf)).await
+    }
+
+    /// Attempts to read bytes into `buf` without consuming them.
+    ///
+    /// This is the polling version of [`peek()`][`Reader::peek()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    /// use std::future;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (mut reader, mut writer) = blocking::pipe(1024);
+    /// writer.write_all(b"hello").await?;
+    ///
+    /// let mut buf = [0; 5];
+    /// let n = future::poll_fn(|cx| reader.poll_peek(cx, &mut buf)).await?;
+    /// assert_eq!(&buf[..n], b"hello");
+    /// assert_eq!(reader.len(), 5);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn poll_peek(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        // Wait for bytes in the pipe, or return if it's closed.
+        if ready!(self.poll_available(cx)) == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        Poll::Ready(Ok(self.peek_into(buf)))
+    }
+
+    /// Discards the next `n` bytes without copying them out.
+    ///
+    /// This waits until `n` bytes have been discarded, and returns how many were. That is fewer
+    /// than `n` only if the pipe got closed first. If the returned future is dropped before
+    /// completion, the bytes discarded so far stay discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (mut reader, mut writer) = blocking::pipe(1024);
+    /// writer.write_all(b"\x00\x05hello").await?;
+    /// writer.close();
+    ///
+    /// // Skip the length prefix.
+    /// assert_eq!(reader.skip(2).await, 2);
+    ///
+    /// let mut s = String::new();
+    /// reader.read_to_string(&mut s).await?;
+    /// assert_eq!(s, "hello");
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn skip(&mut self, n: usize) -> usize {
+        let mut skipped = 0;
+        while skipped < n {
+            // Wait for bytes in the pipe, or stop if it's closed.
+            if future::poll_fn(|cx| self.poll_available(cx)).await == 0 {
+                break;
+            }
+
+            // Discard the contiguous bytes at the head.
+            let k = self.filled().len().min(n - skipped);
+            self.consume(k);
+            skipped += k;
+        }
+        skipped
+    }
+
+    fn poll_read(&mut self, cx: &mut Context<'_>, mut dest: impl Write) -> Poll<io::Result<usize>> {
+        // Wait for bytes in the pipe, or return if it's closed.
+        if ready!(self.poll_available(cx)) == 0 {
+            return Poll::Ready(Ok(0));
+        }
+
+        let cap = self.inner.cap;
+
+        // Calculates the distance between two indices.
+        let distance = |a: usize, b: usize| {
+            if a <= b {
+                b - a
+            } else {
+                2 * cap - (a - b)
+            }
+        };
+
+        // Given an index in `0..2*cap`, returns the real index in `0..cap`.
+        let real_index = |i: usize| {
+            if i < cap {
+                i
+            } else {
+                i - cap
+            }
+        };
+
+        // The maximum number of bytes to read before waking the writer.
+        let chunk_size = self.inner.shared.chunk_size.load(Ordering::Relaxed);
+
+        // Number of bytes read so far.
+        let mut count = 0;
+
+        loop {
+            // Calculate how many bytes to read in this iteration.
+            let n = chunk_size // Not too many bytes in one go - better to wake the writer soon!
+                .min(distance(self.head, self.tail)) // No more than bytes in the pipe.
+                .min(cap - real_index(self.head)); // Don't go past the buffer boundary.
+
+            // Create a slice of data in the pipe buffer.
+            let pipe_slice =
+                unsafe { slice::from_raw_parts(self.inner.ptr().add(real_index(self.head)), n) };
+
+            // Copy bytes from the pipe buffer into `dest`.
+            let n = dest
+                .write(pipe_slice)
+                .expect("shouldn't fail because `dest` is a slice");
+            count += n;
+
+            // If pipe is empty or `dest` is full, return.
+            if n == 0 {
+                return Poll::Ready(Ok(count));
+            }
+
+            // Move the head forward.
+            self.consume(n);
+        }
+    }
+
+    fn poll_read_vectored(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_read(cx, VectoredWriter::new(bufs))
+    }
+
+    /// Waits until the pipe has bytes in it, returning how many.
+    ///
+    /// Returns zero if the pipe is empty and closed.
+    fn poll_available(&mut self, cx: &mut Context<'_>) -> Poll<usize> {
+        let cap = self.inner.cap;
+
+        // Calculates the distance between two indices.
+        let distance = |a: usize, b: usize| {
+            if a <= b {
+                b - a
+            } else {
+                2 * cap - (a - b)
+            }
+        };
+
+        // If the pipe appears to be empty...
+        if distance(self.head, self.tail) == 0 {
+            // Reload the tail in case it's become stale.
+            self.tail = self.inner.tail.load(Ordering::Acquire);
+
+            // If the pipe is now really empty...
+            if distance(self.head, self.tail) == 0 {
+                // Register the waker.
+                self.inner.reader.register(cx.waker());
+                atomic::fence(Ordering::SeqCst);
+
+                // Reload the tail after registering the waker.
+                self.tail = self.inner.tail.load(Ordering::Acquire);
+
+                // If the pipe is still empty...
+                if distance(self.head, self.tail) == 0 {
+                    // Check whether the pipe is closed or just empty.
+                    if !self.inner.closed.load(Ordering::Acquire) {
+                        return Poll::Pending;
+                    }
+
+                    // Reload the tail in case bytes were written right before closing.
+                    self.tail = self.inner.tail.load(Ordering::Acquire);
+                    if distance(self.head, self.tail) == 0 {
+                        // If the writer has moved on to a larger buffer, continue there.
+                        if self.advance() {
+                            return self.poll_available(cx);
+                        }
+                        return Poll::Ready(0);
+                    }
+                }
+            }
+        }
+
+        // The pipe is not empty so remove the waker.
+        self.inner.reader.take();
+
+        Poll::Ready(distance(self.head, self.tail))
+    }
+
+    /// Returns the contiguous bytes at the head of the pipe.
+    ///
+    /// This must be called after [`Reader::poll_available()`] has returned a non-zero count.
+    fn filled(&self) -> &[u8] {
+        let cap = self.inner.cap;
+
+        // Calculates the distance between two indices.
+        let distance = |a: usize, b: usize| {
+            if a <= b {
+                b - a
+            } else {
+                2 * cap - (a - b)
+            }
+        };
+
+        // Given an index in `0..2*cap`, returns the real index in `0..cap`.
+        let real_index = |i: usize| {
+            if i < cap {
+                i
+            } else {
+                i - cap
+            }
+        };
+
+        let n = distance(self.head, self.tail) // No more than bytes in the pipe.
+            .min(cap - real_index(self.head)); // Don't go past the buffer boundary.
+        unsafe { slice::from_raw_parts(self.inner.ptr().add(real_index(self.head)), n) }
+    }
+
+    /// Copies bytes at the head of the pipe into `dest` without consuming them.
+    fn peek_into(&mut self, dest: &mut [u8]) -> usize {
+        // Reload the tail so that all bytes written so far are visible.
+        self.tail = self.inner.tail.load(Ordering::Acquire);
+        let cap = self.inner.cap;
+        let len = if self.head <= self.tail {
+            self.tail - self.head
+        } else {
+            2 * cap - (self.head - self.tail)
+        };
+
+        let filled = self.filled();
+        let n = filled.len().min(dest.len());
+        dest[..n].copy_from_slice(&filled[..n]);
+
+        // Bytes past the buffer boundary continue at its start.
+        let m = (len - filled.len()).min(dest.len() - n);
+        let wrapped = unsafe { slice::from_raw_parts(self.inner.ptr(), m) };
+        dest[n..n + m].copy_from_slice(wrapped);
+        n + m
+    }
+
+    /// Moves all bytes currently in the pipe into `dest` without waiting for more.
+    fn drain_into(&mut self, dest: &mut Vec<u8>) {
+        loop {
+            // Reload the tail so that all bytes written so far are visible.
+            self.tail = self.inner.tail.load(Ordering::Acquire);
+
+            loop {
+                let filled = self.filled();
+                let n = filled.len();
+                if n == 0 {
+                    break;
+                }
+                dest.extend_from_slice(filled);
+                self.consume(n);
+            }
+
+            // If the writer has moved on to a larger buffer, drain that one too.
+            // Once a buffer is closed, its tail doesn't move anymore, so reload it one last time.
+            if !self.inner.closed.load(Ordering::Acquire) {
+                break;
+            }
+            if self.inner.tail.load(Ordering::Acquire) == self.tail && !self.advance() {
+                break;
+            }
+        }
+    }
+
+    /// Removes `n` bytes from the head of the pipe.
+    fn consume(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+
+        // Move the head forward.
+        let cap = self.inner.cap;
+        if self.head + n < 2 * cap {
+            self.head += n;
+        } else {
+            self.head = 0;
+        }
+
+        // Store the current head index.
+        self.inner.head.store(self.head, Ordering::Release);
+        self.inner.shared.bytes_read.fetch_add(n, Ordering::Relaxed);
+
+        // Wake the writer because the pipe is not full.
+        self.inner.wake_writer();
+    }
+}
+
+/// Writes bytes into a sequence of buffers, filling them in order.
+struct VectoredWriter<'a, 'b> {
+    /// The buffers to fill.
+    bufs: &'a mut [IoSliceMut<'b>],
+
+    /// The index of the buffer being filled.
+    index: usize,
+
+    /// The number of bytes written into the buffer being filled.
+    offset: usize,
+}
+
+impl<'a, 'b> VectoredWriter<'a, 'b> {
+    fn new(bufs: &'a mut [IoSliceMut<'b>]) -> VectoredWriter<'a, 'b> {
+        VectoredWriter {
+            bufs,
+            index: 0,
+            offset: 0,
+        }
+    }
+}
+
+impl Write for VectoredWriter<'_, '_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut count = 0;
+
+        while count < data.len() && self.index < self.bufs.len() {
+            // Copy as many bytes as fit into the current buffer.
+            let buf = &mut self.bufs[self.index][self.offset..];
+            let n = buf.len().min(data.len() - count);
+            buf[..n].copy_from_slice(&data[count..count + n]);
+            count += n;
+            self.offset += n;
+
+            // Move on to the next buffer once this one is full.
+            if self.offset == self.bufs[self.index].len() {
+                self.index += 1;
+                self.offset = 0;
+            }
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads bytes from a sequence of buffers, draining them in order.
+struct VectoredReader<'a, 'b> {
+    /// The buffers to drain.
+    bufs: &'a [IoSlice<'b>],
+
+    /// The number of bytes read from the first buffer.
+    offset: usize,
+}
+
+impl<'a, 'b> VectoredReader<'a, 'b> {
+    fn new(bufs: &'a [IoSlice<'b>]) -> VectoredReader<'a, 'b> {
+        VectoredReader { bufs, offset: 0 }
+    }
+}
+
+impl Source for VectoredReader<'_, '_> {
+    fn fill(&mut self, dest: &mut [MaybeUninit<u8>], init: &mut usize) -> io::Result<usize> {
+        let mut count = 0;
+
+        while count < dest.len() && !self.bufs.is_empty() {
+            // Copy as many bytes as fit from the first buffer.
+            let n = copy_uninit(&self.bufs[0][self.offset..], &mut dest[count..]);
+            count += n;
+            self.offset += n;
+
+            // Move on to the next buffer once this one is drained.
+            if self.offset == self.bufs[0].len() {
+                self.bufs = &self.bufs[1..];
+                self.offset = 0;
+            }
+        }
+        *init = (*init).max(count);
+        Ok(count)
+    }
+}
+
+/// A source of bytes written into a pipe.
+pub(crate) trait Source {
+    /// Copies bytes into `dest`, returning how many were copied.
+    ///
+    /// Only the first `init` bytes of `dest` are initialized on entry. Implementations update
+    /// `init` to the number of bytes of `dest` that are initialized on return.
+    fn fill(&mut self, dest: &mut [MaybeUninit<u8>], init: &mut usize) -> io::Result<usize>;
+}
+
+impl<S: Source + ?Sized> Source for &mut S {
+    fn fill(&mut self, dest: &mut [MaybeUninit<u8>], init: &mut usize) -> io::Result<usize> {
+        (**self).fill(dest, init)
+    }
+}
+
+impl Source for &[u8] {
+    fn fill(&mut self, dest: &mut [MaybeUninit<u8>], init: &mut usize) -> io::Result<usize> {
+        let n = copy_uninit(self, dest);
+        *self = &self[n..];
+        *init = (*init).max(n);
+        Ok(n)
+    }
+}
+
+/// A source of bytes read from an arbitrary [`Read`] handle.
+///
+/// Since a [`Read`] implementation may look at the buffer it reads into, the uninitialized part
+/// of the buffer gets zeroed first.
+pub(crate) struct ReadSource<R>(pub(crate) R);
+
+impl<R: Read> Source for ReadSource<R> {
+    fn fill(&mut self, dest: &mut [MaybeUninit<u8>], init: &mut usize) -> io::Result<usize> {
+        for b in &mut dest[*init..] {
+            *b = MaybeUninit::new(0);
+        }
+        *init = dest.len();
+
+        // SAFETY: All bytes in `dest` have just been initialized.
+        let dest = unsafe { &mut *(dest as *mut [MaybeUninit<u8>] as *mut [u8]) };
+        self.0.read(dest)
+    }
+}
+
+/// Copies as many bytes from `src` as fit into `dest`, returning how many were copied.
+pub(crate) fn copy_uninit(src: &[u8], dest: &mut [MaybeUninit<u8>]) -> usize {
+    let n = src.len().min(dest.len());
+    // SAFETY: Both slices are valid for `n` bytes and can't overlap since `dest` is borrowed
+    // mutably.
+    unsafe {
+        ptr::copy_nonoverlapping(src.as_ptr(), dest.as_mut_ptr().cast::<u8>(), n);
+    }
+    n
+}
+
+impl Writer {
+    fn poll_write(&mut self, cx: &mut Context<'_>, src: impl Source) -> Poll<io::Result<usize>> {
+        if ready!(self.poll_vacant(cx)) == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        Poll::Ready(self.fill_from(src))
+    }
+
+    /// Waits until there's room in the pipe, returning how many bytes fit, or zero if the pipe is
+    /// closed.
+    ///
+    /// Only the writer fills the pipe, so the returned number of bytes can be written with
+    /// [`Writer::fill_from()`] afterwards, even if the reader closes the pipe in the meantime.
+    pub(crate) fn poll_vacant(&mut self, cx: &mut Context<'_>) -> Poll<usize> {
+        // Just a quick check if the pipe is closed, which is why a relaxed load is okay.
+        if self.inner.closed.load(Ordering::Relaxed) {
+            return Poll::Ready(0);
+        }
+
+        // Calculates the distance between two indices.
+        let cap = self.inner.cap;
+        let distance = |a: usize, b: usize| {
+            if a <= b {
+                b - a
+            } else {
+                2 * cap - (a - b)
+            }
+        };
+
+        // If the pipe appears to be full...
+        if distance(self.head, self.tail) == cap {
+            // Reload the head in case it's become stale.
+            self.head = self.inner.head.load(Ordering::Acquire);
+
+            // If the pipe is now really empty...
+            if distance(self.head, self.tail) == cap {
+                // Register the waker.
+                self.inner.writer.register(cx.waker());
+                atomic::fence(Ordering::SeqCst);
+
+                // Reload the head after registering the waker.
+                self.head = self.inner.head.load(Ordering::Acquire);
+
+                // If the pipe is still full...
+                if distance(self.head, self.tail) == cap {
+                    // Check whether the pipe is closed or just full.
+                    if self.inner.closed.load(Ordering::Relaxed) {
+                        return Poll::Ready(0);
+                    }
+
+                    // If the buffer can grow, move on to a larger one instead of waiting.
+                    if cap < self.inner.max_cap {
+                        self.grow();
+                        return self.poll_vacant(cx);
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+
+        // The pipe is not full so remove the waker.
+        self.inner.writer.take();
+        Poll::Ready(cap - distance(self.head, self.tail))
+    }
+
+    /// Copies bytes from `src` into the free space in the pipe, returning how many were copied.
+    ///
+    /// Bytes are copied even if the pipe is closed, since the reader may still take them out.
+    pub(crate) fn fill_from(&mut self, mut src: impl Source) -> io::Result<usize> {
+        // Calculates the distance between two indices.
+        let cap = self.inner.cap;
+        let distance = |a: usize, b: usize| {
+            if a <= b {
+                b - a
+            } else {
+                2 * cap - (a - b)
+            }
+        };
+
+        // Given an index in `0..2*cap`, returns the real index in `0..cap`.
+        let real_index = |i: usize| {
+            if i < cap {
+                i
+            } else {
+                i - cap
+            }
+        };
+
+        // The maximum number of bytes to write before waking the reader.
+        let chunk_size = self.inner.shared.chunk_size.load(Ordering::Relaxed);
+
+        // Number of bytes written so far.
+        let mut count = 0;
+
+        loop {
+            // Calculate how many bytes to write in this iteration.
+            let n = chunk_size // Not too many bytes in one go - better to wake the reader soon!
+                .min(self.initialized * 2 + 4096) // Don't zero too many bytes when starting.
+                .min(cap - distance(self.head, self.tail)) // No more than space in the pipe.
+                .min(cap - real_index(self.tail)); // Don't go past the buffer boundary.
+
+            // Create a slice of available space in the pipe buffer.
+            let from = real_index(self.tail);
+            let pipe_slice_mut = unsafe {
+                slice::from_raw_parts_mut(self.inner.ptr().add(from).cast::<MaybeUninit<u8>>(), n)
+            };
+
+            // Copy bytes from `src` into the pipe buffer, keeping track of initialized bytes.
+            let mut init = self.initialized.saturating_sub(from).min(n);
+            let n = src.fill(pipe_slice_mut, &mut init)?;
+            self.initialized = self.initialized.max(from + init);
+            count += n;
+
+            // If the pipe is full or `src` is empty, return.
+            if n == 0 {
+                return Ok(count);
+            }
+
+            // Move the tail forward.
+            if self.tail + n < 2 * cap {
+                self.tail += n;
+            } else {
+                self.tail = 0;
+            }
+
+            // Store the current tail index.
+            self.inner.tail.store(self.tail, Ordering::Release);
+            self.inner
+                .shared
+                .bytes_written
+                .fetch_add(n, Ordering::Relaxed);
+
+            // Wake the reader because the pipe is not empty.
+            self.inner.wake_reader();
+        }
+    }
+
+    fn poll_write_vectored(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write(cx, VectoredReader::new(bufs))
+    }
+
+    /// Moves on to a buffer twice as large, leaving the bytes in the current one to the reader.
+    fn grow(&mut self) {
+        let cap = (self.inner.cap * 2).min(self.inner.max_cap);
+        let next = Pipe::new(
+            cap,
+            self.inner.max_cap,
+            self.inner.shared.clone(),
+            Some(Arc::downgrade(&self.inner)),
+        );
+        *self.inner.next.lock().unwrap() = Some(next.clone());
+
+        // If the reader closed the pipe before it could see the new buffer, close that one too.
+        if self.inner.closed.load(Ordering::SeqCst) {
+            next.closed.store(true, Ordering::SeqCst);
+        }
+
+        // Close the current buffer so that the reader moves on after reading the rest of it.
+        let prev = mem::replace(&mut self.inner, next);
+        prev.closed.store(true, Ordering::SeqCst);
+        prev.wake_reader();
+
+        self.head = 0;
+        self.tail = 0;
+        self.initialized = 0;
+    }
+}
+
+impl AsyncRead for Reader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_read(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_read_vectored(cx, bufs)
+    }
+}
+
+impl AsyncBufRead for Reader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if ready!(this.poll_available(cx)) == 0 {
+            return Poll::Ready(Ok(&[]));
+        }
+        Poll::Ready(Ok(this.filled()))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().consume(amt);
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        block_on(future::poll_fn(|cx| self.poll_read(cx, &mut *buf)))
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        block_on(future::poll_fn(|cx| self.poll_read_vectored(cx, bufs)))
+    }
+}
+
+impl AsyncWrite for Writer {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Bytes are readable as soon as they are in the pipe, so there's nothing to flush.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        block_on(future::poll_fn(|cx| self.poll_write(cx, buf)))
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        block_on(future::poll_fn(|cx| self.poll_write_vectored(cx, bufs)))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Writer {
+    /// Returns the number of bytes in the pipe.
     ///
-    /// This index always matches `inner.head`.
-    head: usize,
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (reader, mut writer) = blocking::pipe(1024);
+    /// writer.write_all(b"hello").await?;
+    /// assert_eq!(writer.len(), 5);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn len(&self) -> usize {
+        let cap = self.inner.cap;
+        let head = self.inner.head.load(Ordering::Acquire);
+        let mut len = if head <= self.tail {
+            self.tail - head
+        } else {
+            2 * cap - (head - self.tail)
+        };
+
+        // Count bytes the reader hasn't read yet from smaller buffers this writer has outgrown.
+        let mut prev = self.inner.prev.as_ref().and_then(Weak::upgrade);
+        while let Some(pipe) = prev {
+            len += pipe.len();
+            prev = pipe.prev.as_ref().and_then(Weak::upgrade);
+        }
+        len
+    }
+
+    /// Returns the capacity of the pipe.
+    ///
+    /// For a [growable pipe][`growable_pipe()`], this is the capacity of the buffer currently
+    /// being written into.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (reader, writer) = blocking::pipe(1024);
+    /// assert_eq!(writer.capacity(), 1024);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.inner.cap
+    }
+
+    /// Returns `true` if the pipe is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (reader, writer) = blocking::pipe(1024);
+    /// assert!(writer.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the pipe is full.
+    ///
+    /// A full pipe has no room for more bytes until the reader makes progress.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (reader, mut writer) = blocking::pipe(5);
+    /// writer.write_all(b"hello").await?;
+    /// assert!(writer.is_full());
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+
+    /// Returns a snapshot of metrics for the pipe.
+    ///
+    /// See [`PipeMetrics`] for more information.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (reader, writer) = blocking::pipe(1024);
+    /// assert_eq!(writer.metrics().bytes_written(), 0);
+    /// ```
+    pub fn metrics(&self) -> PipeMetrics {
+        self.inner.shared.metrics()
+    }
+
+    /// Sets how many bytes are copied in one go before waking the other side.
+    ///
+    /// See [`Reader::set_chunk_size()`] for more information.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (reader, writer) = blocking::pipe(1024 * 1024);
+    /// writer.set_chunk_size(4096);
+    /// assert_eq!(reader.chunk_size(), 4096);
+    /// ```
+    pub fn set_chunk_size(&self, chunk_size: usize) {
+        self.inner.shared.set_chunk_size(chunk_size);
+    }
+
+    /// Returns how many bytes are copied in one go before waking the other side.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (reader, writer) = blocking::pipe(1024);
+    /// assert_eq!(writer.chunk_size(), 128 * 1024);
+    /// ```
+    pub fn chunk_size(&self) -> usize {
+        self.inner.shared.chunk_size.load(Ordering::Relaxed)
+    }
+
+    /// Closes the pipe and then wakes the reader.
+    ///
+    /// The reader can still read the bytes remaining in the pipe, after which it reaches the end.
+    /// Further writes will result in `Ok(0)`. Unlike dropping the writer, this keeps it around,
+    /// e.g. to inspect the pipe.
+    ///
+    /// Note that this method shadows [`AsyncWriteExt::close()`][close], which does the same
+    /// asynchronously.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (mut reader, mut writer) = blocking::pipe(1024);
+    /// writer.write_all(b"hello").await?;
+    /// writer.close();
+    ///
+    /// let mut s = String::new();
+    /// reader.read_to_string(&mut s).await?;
+    /// assert_eq!(s, "hello");
+    /// assert!(writer.is_empty());
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    ///
+    /// [close]: https://docs.rs/futures/0.3/futures/io/trait.AsyncWriteExt.html#method.close
+    pub fn close(&self) {
+        self.inner.closed.store(true, Ordering::SeqCst);
+        self.inner.wake_reader();
+    }
+
+    /// Converts this writer into one that can be cloned to write from multiple producers.
+    ///
+    /// See [`SharedWriter`] for more information.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blocking::Blocking;
+    /// use futures::io::AsyncReadExt;
+    /// use std::io::Write;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (mut reader, writer) = blocking::pipe(1024);
+    /// let writer = writer.into_shared();
+    ///
+    /// // Funnel bytes from several blocking tasks into one async consumer.
+    /// for i in 0..4 {
+    ///     let mut writer = writer.clone();
+    ///     Blocking::spawn_detached(async move {
+    ///         writer.write_all(format!("{}", i).as_bytes()).unwrap();
+    ///     });
+    /// }
+    /// drop(writer);
+    ///
+    /// let mut s = String::new();
+    /// reader.read_to_string(&mut s).await?;
+    /// assert_eq!(s.len(), 4);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn into_shared(self) -> SharedWriter {
+        SharedWriter {
+            inner: Arc::new(SharedInner {
+                writer: Mutex::new(self),
+                waiters: Arc::new(Waiters(Mutex::new(Vec::new()))),
+            }),
+        }
+    }
+}
+
+/// The writing side of a pipe that can be shared by multiple producers.
+///
+/// This type is created by [`Writer::into_shared()`]. Clones write into the same pipe, and the pipe
+/// gets closed once all of them have been dropped. Each write puts its bytes into the pipe
+/// contiguously, but bytes from `write_all()` calls of different producers may interleave if they
+/// take multiple writes.
+///
+/// Besides [`AsyncWrite`], this type implements [`Write`] by blocking the current thread, so it
+/// can be used from blocking tasks. Note that closing it through [`AsyncWrite`] doesn't close the
+/// pipe for the other producers.
+#[derive(Clone, Debug)]
+pub struct SharedWriter {
+    /// The writer shared by all clones.
+    inner: Arc<SharedInner>,
+}
+
+/// The state shared by clones of a [`SharedWriter`].
+#[derive(Debug)]
+struct SharedInner {
+    /// The writing side of the pipe.
+    writer: Mutex<Writer>,
+
+    /// Producers waiting for room in the pipe.
+    waiters: Arc<Waiters>,
+}
+
+/// Wakers of producers waiting for room in a pipe, all woken at once.
+///
+/// The pipe holds a single waker for its writing side, so producers register this set instead.
+#[derive(Debug)]
+struct Waiters(Mutex<Vec<Waker>>);
+
+impl Wake for Waiters {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = mem::take(&mut *self.0.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl SharedWriter {
+    /// Writes bytes into the pipe with `write`, waiting for room along with other producers.
+    fn poll_write_with(
+        &self,
+        cx: &mut Context<'_>,
+        write: impl FnOnce(&mut Writer, &mut Context<'_>) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>> {
+        let mut writer = self.inner.writer.lock().unwrap();
+
+        // Register before writing so that a reader making room in the meantime wakes this
+        // producer.
+        {
+            let mut wakers = self.inner.waiters.0.lock().unwrap();
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+
+        let waker = Waker::from(self.inner.waiters.clone());
+        let res = write(&mut writer, &mut Context::from_waker(&waker));
+
+        if res.is_ready() {
+            self.inner
+                .waiters
+                .0
+                .lock()
+                .unwrap()
+                .retain(|w| !w.will_wake(cx.waker()));
+        }
+        res
+    }
+}
+
+impl AsyncWrite for SharedWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_with(cx, |writer, cx| writer.poll_write(cx, buf))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_with(cx, |writer, cx| writer.poll_write_vectored(cx, bufs))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Bytes are readable as soon as they are in the pipe, so there's nothing to flush.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Other producers may still be writing, so the pipe gets closed only when all of them
+        // have been dropped.
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        block_on(future::poll_fn(|cx| {
+            self.poll_write_with(cx, |writer, cx| writer.poll_write(cx, buf))
+        }))
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        block_on(future::poll_fn(|cx| {
+            self.poll_write_with(cx, |writer, cx| writer.poll_write_vectored(cx, bufs))
+        }))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates a bounded pipe with a single writer and multiple readers.
+///
+/// Every reader gets all bytes written into the pipe from the point it was created, reading at its
+/// own pace. The pipe holds up to `cap` bytes that haven't been read by all readers yet, so the
+/// writer waits for the slowest reader. More readers are created by cloning a
+/// [`BroadcastReader`], starting at the same position as the original one.
+///
+/// When the writer is dropped or closed, readers can still read the remaining bytes. After that,
+/// attempts to read will result in `Ok(0)`. When all readers are dropped, further writes will
+/// result in `Ok(0)`.
+///
+/// # Panics
+///
+/// This function panics if `cap` is zero.
+///
+/// # Examples
+///
+/// Read a file once and serve it to multiple consumers:
+///
+/// ```no_run
+/// use blocking::Blocking;
+/// use futures::io;
+/// use futures::prelude::*;
+/// use std::fs::File;
+///
+/// # futures::executor::block_on(async {
+/// let (mut reader1, mut writer) = blocking::broadcast_pipe(64 * 1024);
+/// let mut reader2 = reader1.clone();
+/// let mut file = Blocking::new(File::open("file.txt")?);
+///
+/// let producer = async move {
+///     io::copy(&mut file, &mut writer).await?;
+///     writer.close().await
+/// };
+/// let mut a = Vec::new();
+/// let mut b = Vec::new();
+/// future::try_join3(
+///     producer,
+///     reader1.read_to_end(&mut a),
+///     reader2.read_to_end(&mut b),
+/// )
+/// .await?;
+/// assert_eq!(a, b);
+/// # std::io::Result::Ok(()) });
+/// ```
+pub fn broadcast_pipe(cap: usize) -> (BroadcastReader, BroadcastWriter) {
+    assert!(cap > 0, "capacity must be positive");
 
-    /// The tail index, moved by the writer, in the range `0..2*cap`.
-    ///
-    /// This index is a snapshot of `index.tail` that might become stale at any point.
-    tail: usize,
+    let mut readers = BTreeMap::new();
+    readers.insert(
+        0,
+        BroadcastCursor {
+            pos: 0,
+            waker: None,
+        },
+    );
+
+    let inner = Arc::new(Broadcast {
+        state: Mutex::new(BroadcastState {
+            buffer: VecDeque::new(),
+            start: 0,
+            cap,
+            readers,
+            next_id: 1,
+            writer: None,
+            closed: false,
+        }),
+    });
+
+    let w = BroadcastWriter {
+        inner: inner.clone(),
+    };
+    let r = BroadcastReader { inner, id: 0 };
+    (r, w)
 }
 
-/// The writing side of a pipe.
+/// The writing side of a broadcast pipe.
+///
+/// This type is created by [`broadcast_pipe()`].
+#[derive(Debug)]
+pub struct BroadcastWriter {
+    /// The shared ring buffer.
+    inner: Arc<Broadcast>,
+}
+
+/// A reading side of a broadcast pipe.
+///
+/// This type is created by [`broadcast_pipe()`]. Cloning it creates another reader that starts at
+/// the same position.
+#[derive(Debug)]
+pub struct BroadcastReader {
+    /// The shared ring buffer.
+    inner: Arc<Broadcast>,
+
+    /// The ID of this reader's cursor.
+    id: usize,
+}
+
+/// The shared ring buffer of a broadcast pipe.
+#[derive(Debug)]
+struct Broadcast {
+    /// The state of the pipe.
+    state: Mutex<BroadcastState>,
+}
+
+/// The state of a broadcast pipe.
+#[derive(Debug)]
+struct BroadcastState {
+    /// Bytes that haven't been read by all readers yet.
+    buffer: VecDeque<u8>,
+
+    /// The position of the first byte in the buffer, counting from the start of the pipe.
+    start: u64,
+
+    /// The buffer capacity.
+    cap: usize,
+
+    /// Cursors of the readers, keyed by reader IDs.
+    readers: BTreeMap<usize, BroadcastCursor>,
+
+    /// The ID for the next reader.
+    next_id: usize,
+
+    /// A waker representing the blocked writer.
+    writer: Option<Waker>,
+
+    /// Set to `true` if the writer was closed or dropped.
+    closed: bool,
+}
+
+/// The position of a reader in a broadcast pipe.
 #[derive(Debug)]
-struct Writer {
+struct BroadcastCursor {
+    /// The position of the next byte to read, counting from the start of the pipe.
+    pos: u64,
+
+    /// A waker representing the blocked reader.
+    waker: Option<Waker>,
+}
+
+impl BroadcastState {
+    /// Drops bytes that have been read by all readers and wakes the writer if there's room now.
+    fn trim(&mut self) {
+        let end = self.start + self.buffer.len() as u64;
+        let min = self.readers.values().map(|c| c.pos).min().unwrap_or(end);
+
+        let n = (min - self.start) as usize;
+        if n > 0 {
+            self.buffer.drain(..n);
+            self.start = min;
+            if let Some(waker) = self.writer.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Closes the writing side and wakes all readers.
+    fn close(&mut self) {
+        self.closed = true;
+        for cursor in self.readers.values_mut() {
+            if let Some(waker) = cursor.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Clone for BroadcastReader {
+    fn clone(&self) -> BroadcastReader {
+        let mut state = self.inner.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+
+        let pos = state.readers[&self.id].pos;
+        state
+            .readers
+            .insert(id, BroadcastCursor { pos, waker: None });
+
+        BroadcastReader {
+            inner: self.inner.clone(),
+            id,
+        }
+    }
+}
+
+impl Drop for BroadcastReader {
+    fn drop(&mut self) {
+        // Remove the cursor so that the writer doesn't wait for this reader anymore.
+        let mut state = self.inner.state.lock().unwrap();
+        state.readers.remove(&self.id);
+        state.trim();
+
+        if state.readers.is_empty() {
+            if let Some(waker) = state.writer.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Drop for BroadcastWriter {
+    fn drop(&mut self) {
+        // Dropping closes the pipe and then wakes the readers.
+        self.inner.state.lock().unwrap().close();
+    }
+}
+
+impl AsyncRead for BroadcastReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.inner.state.lock().unwrap();
+        let state = &mut *state;
+        let cursor = state.readers.get_mut(&self.id).unwrap();
+
+        // If there are no bytes to read, wait for the writer or return if it's closed.
+        let offset = (cursor.pos - state.start) as usize;
+        if offset == state.buffer.len() {
+            if state.closed {
+                return Poll::Ready(Ok(0));
+            }
+            cursor.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        // Copy the contiguous bytes at the cursor into `buf`.
+        let (a, b) = state.buffer.as_slices();
+        let filled = if offset < a.len() {
+            &a[offset..]
+        } else {
+            &b[offset - a.len()..]
+        };
+        let n = filled.len().min(buf.len());
+        buf[..n].copy_from_slice(&filled[..n]);
+        cursor.pos += n as u64;
+
+        state.trim();
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for BroadcastWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.inner.state.lock().unwrap();
+
+        // If there are no readers, no more bytes can be written.
+        if state.closed || state.readers.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        // If the pipe is full, wait for the slowest reader.
+        let n = (state.cap - state.buffer.len()).min(buf.len());
+        if n == 0 && !buf.is_empty() {
+            state.writer = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        // Copy bytes into the ring buffer and wake the readers.
+        state.buffer.extend(&buf[..n]);
+        for cursor in state.readers.values_mut() {
+            if let Some(waker) = cursor.waker.take() {
+                waker.wake();
+            }
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Bytes are readable as soon as they are in the pipe, so there's nothing to flush.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.state.lock().unwrap().close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Creates a bounded single-producer single-consumer channel.
+///
+/// This is the typed counterpart of [`pipe()`]: a ring buffer of `cap` slots, with a [`Sender`]
+/// on one end and a [`Receiver`] on the other. Sending waits while the channel is full, and
+/// receiving waits while it's empty. Slots are only touched once items are sent into them, so a
+/// large channel that is never filled doesn't cost much memory.
+///
+/// When the sender is dropped or closed, remaining items can still be received, after which
+/// receiving returns `None`. When the receiver is dropped or closed, sending fails and gives the
+/// item back.
+///
+/// # Panics
+///
+/// This function panics if `cap` is zero or too large.
+///
+/// # Examples
+///
+/// ```
+/// use futures::prelude::*;
+///
+/// # futures::executor::block_on(async {
+/// let (mut sender, mut receiver) = blocking::channel(2);
+///
+/// let producer = async move {
+///     for i in 0..5 {
+///         sender.send(i).await.unwrap();
+///     }
+/// };
+/// let (_, items) = future::join(producer, receiver.collect::<Vec<_>>()).await;
+/// assert_eq!(items, [0, 1, 2, 3, 4]);
+/// # });
+/// ```
+pub fn channel<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(cap > 0, "capacity must be positive");
+    assert!(cap.checked_mul(2).is_some(), "capacity is too large");
+
+    // Allocate the slots without touching them.
+    let mut slots = Vec::with_capacity(cap);
+    unsafe {
+        slots.set_len(cap);
+    }
+
+    let inner = Arc::new(Channel {
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        receiver: AtomicWaker::new(),
+        sender: AtomicWaker::new(),
+        closed: AtomicBool::new(false),
+        slots: slots.into_boxed_slice(),
+    });
+
+    let s = Sender {
+        inner: inner.clone(),
+        head: 0,
+        tail: 0,
+    };
+
+    let r = Receiver {
+        inner,
+        head: 0,
+        tail: 0,
+    };
+
+    (s, r)
+}
+
+/// The sending side of a channel.
+///
+/// This type is created by [`channel()`].
+pub struct Sender<T> {
     /// The inner ring buffer.
-    inner: Arc<Pipe>,
+    inner: Arc<Channel<T>>,
 
-    /// The head index, moved by the reader, in the range `0..2*cap`.
+    /// The head index, moved by the receiver, in the range `0..2*cap`.
     ///
     /// This index is a snapshot of `index.head` that might become stale at any point.
     head: usize,
 
-    /// The tail index, moved by the writer, in the range `0..2*cap`.
+    /// The tail index, moved by the sender, in the range `0..2*cap`.
     ///
     /// This index always matches `inner.tail`.
     tail: usize,
+}
+
+/// The receiving side of a channel.
+///
+/// This type is created by [`channel()`]. With the `stream` feature, it implements [`Stream`].
+pub struct Receiver<T> {
+    /// The inner ring buffer.
+    inner: Arc<Channel<T>>,
 
-    /// How many bytes at the beginning of the buffer have been zeroed.
+    /// The head index, moved by the receiver, in the range `0..2*cap`.
     ///
-    /// The pipe allocates an uninitialized buffer, and we must be careful about passing
-    /// uninitialized data to user code. Zeroing the buffer right after allocation would be too
-    /// expensive, so we zero it in smaller chunks as the writer makes progress.
-    zeroed_until: usize,
-}
+    /// This index always matches `inner.head`.
+    head: usize,
 
-unsafe impl Send for Reader {}
-unsafe impl Send for Writer {}
+    /// The tail index, moved by the sender, in the range `0..2*cap`.
+    ///
+    /// This index is a snapshot of `index.tail` that might become stale at any point.
+    tail: usize,
+}
 
-/// The inner ring buffer.
+/// The inner ring buffer of a channel.
 ///
 /// Head and tail indices are in the range `0..2*cap`, even though they really map onto the
 /// `0..cap` range. The distance between head and tail indices is never more than `cap`.
 ///
 /// The reason why indices are not in the range `0..cap` is because we need to distinguish between
-/// the pipe being empty and being full. If head and tail were in `0..cap`, then `head == tail`
-/// could mean the pipe is either empty or full, but we don't know which!
-#[derive(Debug)]
-struct Pipe {
-    /// The head index, moved by the reader, in the range `0..2*cap`.
+/// the channel being empty and being full. If head and tail were in `0..cap`, then `head == tail`
+/// could mean the channel is either empty or full, but we don't know which!
+struct Channel<T> {
+    /// The head index, moved by the receiver, in the range `0..2*cap`.
     head: AtomicUsize,
 
-    /// The tail index, moved by the writer, in the range `0..2*cap`.
+    /// The tail index, moved by the sender, in the range `0..2*cap`.
     tail: AtomicUsize,
 
-    /// A waker representing the blocked reader.
-    reader: AtomicWaker,
+    /// A waker representing the blocked receiver.
+    receiver: AtomicWaker,
 
-    /// A waker representing the blocked writer.
-    writer: AtomicWaker,
+    /// A waker representing the blocked sender.
+    sender: AtomicWaker,
 
-    /// Set to `true` if the reader or writer was dropped.
+    /// Set to `true` if the receiver or sender was closed.
     closed: AtomicBool,
 
-    /// The byte buffer.
-    buffer: *mut u8,
+    /// The slots, initialized between the head and the tail.
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+unsafe impl<T: Send> Send for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
 
-    /// The buffer capacity.
-    cap: usize,
+impl<T> Channel<T> {
+    /// Returns the capacity of the channel.
+    fn cap(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Calculates the distance between two indices.
+    fn distance(&self, a: usize, b: usize) -> usize {
+        if a <= b {
+            b - a
+        } else {
+            2 * self.cap() - (a - b)
+        }
+    }
+
+    /// Returns the slot at an index in the range `0..2*cap`.
+    fn slot(&self, i: usize) -> *mut MaybeUninit<T> {
+        let cap = self.cap();
+        self.slots[if i < cap { i } else { i - cap }].get()
+    }
+
+    /// Returns the index following `i`.
+    fn next_index(&self, i: usize) -> usize {
+        if i + 1 < 2 * self.cap() {
+            i + 1
+        } else {
+            0
+        }
+    }
 }
 
-impl Drop for Pipe {
+impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        // Deallocate the byte buffer.
-        unsafe {
-            Vec::from_raw_parts(self.buffer, 0, self.cap);
+        // Drop the items that were never received.
+        let mut i = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        while i != tail {
+            unsafe {
+                ptr::drop_in_place((*self.slot(i)).as_mut_ptr());
+            }
+            i = self.next_index(i);
         }
     }
 }
 
-impl Drop for Reader {
+impl<T> Drop for Sender<T> {
     fn drop(&mut self) {
-        // Dropping closes the pipe and then wakes the writer.
-        self.inner.closed.store(true, Ordering::SeqCst);
-        self.inner.writer.wake();
+        // Dropping closes the channel.
+        self.close();
     }
 }
 
-impl Drop for Writer {
+impl<T> Drop for Receiver<T> {
     fn drop(&mut self) {
-        // Dropping closes the pipe and then wakes the reader.
+        // Dropping closes the channel.
+        self.close();
+    }
+}
+
+impl<T> Sender<T> {
+    /// Sends an item into the channel, waiting while it's full.
+    ///
+    /// If the channel is closed, the item is given back as an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// let (mut sender, receiver) = blocking::channel(1);
+    /// assert_eq!(sender.send(1).await, Ok(()));
+    ///
+    /// drop(receiver);
+    /// assert_eq!(sender.send(2).await, Err(2));
+    /// # });
+    /// ```
+    pub async fn send(&mut self, item: T) -> Result<(), T> {
+        if !future::poll_fn(|cx| self.poll_reserve(cx)).await {
+            return Err(item);
+        }
+
+        // Write the item into the slot at the tail.
+        unsafe {
+            (*self.inner.slot(self.tail)).as_mut_ptr().write(item);
+        }
+
+        // Move the tail forward and wake the receiver.
+        self.tail = self.inner.next_index(self.tail);
+        self.inner.tail.store(self.tail, Ordering::Release);
+        self.inner.receiver.wake();
+        Ok(())
+    }
+
+    /// Waits until there's a free slot, returning `false` if the channel is closed.
+    fn poll_reserve(&mut self, cx: &mut Context<'_>) -> Poll<bool> {
+        // Just a quick check if the channel is closed, which is why a relaxed load is okay.
+        if self.inner.closed.load(Ordering::Relaxed) {
+            return Poll::Ready(false);
+        }
+
+        let cap = self.inner.cap();
+
+        // If the channel appears to be full...
+        if self.inner.distance(self.head, self.tail) == cap {
+            // Reload the head in case it's become stale.
+            self.head = self.inner.head.load(Ordering::Acquire);
+
+            // If the channel is now really full...
+            if self.inner.distance(self.head, self.tail) == cap {
+                // Register the waker.
+                self.inner.sender.register(cx.waker());
+                atomic::fence(Ordering::SeqCst);
+
+                // Reload the head after registering the waker.
+                self.head = self.inner.head.load(Ordering::Acquire);
+
+                // If the channel is still full...
+                if self.inner.distance(self.head, self.tail) == cap {
+                    // Check whether the channel is closed or just full.
+                    if self.inner.closed.load(Ordering::Relaxed) {
+                        return Poll::Ready(false);
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+
+        // The channel is not full so remove the waker.
+        self.inner.sender.take();
+        Poll::Ready(true)
+    }
+
+    /// Closes the channel and then wakes the receiver.
+    ///
+    /// The receiver can still receive the items remaining in the channel, after which it reaches
+    /// the end. Further sends will fail.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// let (mut sender, mut receiver) = blocking::channel(4);
+    /// sender.send(1).await.unwrap();
+    /// sender.close();
+    ///
+    /// assert_eq!(receiver.recv().await, Some(1));
+    /// assert_eq!(receiver.recv().await, None);
+    /// # });
+    /// ```
+    pub fn close(&self) {
         self.inner.closed.store(true, Ordering::SeqCst);
-        self.inner.reader.wake();
+        self.inner.receiver.wake();
+    }
+
+    /// Returns the number of items in the channel.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// let (mut sender, receiver) = blocking::channel(4);
+    /// sender.send(1).await.unwrap();
+    /// assert_eq!(sender.len(), 1);
+    /// # });
+    /// ```
+    pub fn len(&self) -> usize {
+        let head = self.inner.head.load(Ordering::Acquire);
+        self.inner.distance(head, self.tail)
+    }
+
+    /// Returns `true` if the channel is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (sender, receiver) = blocking::channel::<i32>(4);
+    /// assert!(sender.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the capacity of the channel.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (sender, receiver) = blocking::channel::<i32>(4);
+    /// assert_eq!(sender.capacity(), 4);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.inner.cap()
     }
 }
 
-impl Reader {
-    fn poll_read(&mut self, cx: &mut Context<'_>, mut dest: impl Write) -> Poll<io::Result<usize>> {
-        let cap = self.inner.cap;
-
-        // Calculates the distance between two indices.
-        let distance = |a: usize, b: usize| {
-            if a <= b {
-                b - a
-            } else {
-                2 * cap - (a - b)
-            }
-        };
+impl<T> Receiver<T> {
+    /// Receives an item from the channel, waiting while it's empty.
+    ///
+    /// Returns `None` once the channel is empty and closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// let (mut sender, mut receiver) = blocking::channel(4);
+    /// sender.send("hello").await.unwrap();
+    /// drop(sender);
+    ///
+    /// assert_eq!(receiver.recv().await, Some("hello"));
+    /// assert_eq!(receiver.recv().await, None);
+    /// # });
+    /// ```
+    pub async fn recv(&mut self) -> Option<T> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
 
-        // If the pipe appears to be empty...
-        if distance(self.head, self.tail) == 0 {
+    /// Attempts to receive an item from the channel.
+    ///
+    /// Returns `Poll::Ready(None)` once the channel is empty and closed.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        // If the channel appears to be empty...
+        if self.inner.distance(self.head, self.tail) == 0 {
             // Reload the tail in case it's become stale.
             self.tail = self.inner.tail.load(Ordering::Acquire);
 
-            // If the pipe is now really empty...
-            if distance(self.head, self.tail) == 0 {
+            // If the channel is now really empty...
+            if self.inner.distance(self.head, self.tail) == 0 {
                 // Register the waker.
-                self.inner.reader.register(cx.waker());
+                self.inner.receiver.register(cx.waker());
                 atomic::fence(Ordering::SeqCst);
 
                 // Reload the tail after registering the waker.
                 self.tail = self.inner.tail.load(Ordering::Acquire);
 
-                // If the pipe is still empty...
-                if distance(self.head, self.tail) == 0 {
-                    // Check whether the pipe is closed or just empty.
-                    if self.inner.closed.load(Ordering::Relaxed) {
-                        return Poll::Ready(Ok(0));
-                    } else {
+                // If the channel is still empty...
+                if self.inner.distance(self.head, self.tail) == 0 {
+                    // Check whether the channel is closed or just empty.
+                    if !self.inner.closed.load(Ordering::Acquire) {
                         return Poll::Pending;
                     }
+
+                    // Reload the tail in case items were sent right before closing.
+                    self.tail = self.inner.tail.load(Ordering::Acquire);
+                    if self.inner.distance(self.head, self.tail) == 0 {
+                        return Poll::Ready(None);
+                    }
                 }
             }
         }
 
-        // The pipe is not empty so remove the waker.
-        self.inner.reader.take();
+        // The channel is not empty so remove the waker.
+        self.inner.receiver.take();
 
-        // Given an index in `0..2*cap`, returns the real index in `0..cap`.
-        let real_index = |i: usize| {
-            if i < cap {
-                i
-            } else {
-                i - cap
-            }
-        };
+        // Read the item out of the slot at the head.
+        let item = unsafe { (*self.inner.slot(self.head)).as_ptr().read() };
 
-        // Number of bytes read so far.
-        let mut count = 0;
+        // Move the head forward and wake the sender.
+        self.head = self.inner.next_index(self.head);
+        self.inner.head.store(self.head, Ordering::Release);
+        self.inner.sender.wake();
+        Poll::Ready(Some(item))
+    }
 
-        loop {
-            // Calculate how many bytes to read in this iteration.
-            let n = (128 * 1024) // Not too many bytes in one go - better to wake the writer soon!
-                .min(distance(self.head, self.tail)) // No more than bytes in the pipe.
-                .min(cap - real_index(self.head)); // Don't go past the buffer boundary.
+    /// Closes the channel and then wakes the sender.
+    ///
+    /// Items remaining in the channel can still be received, but further sends will fail.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// let (mut sender, receiver) = blocking::channel(4);
+    /// receiver.close();
+    /// assert_eq!(sender.send(1).await, Err(1));
+    /// # });
+    /// ```
+    pub fn close(&self) {
+        self.inner.closed.store(true, Ordering::SeqCst);
+        self.inner.sender.wake();
+    }
 
-            // Create a slice of data in the pipe buffer.
-            let pipe_slice =
-                unsafe { slice::from_raw_parts(self.inner.buffer.add(real_index(self.head)), n) };
+    /// Returns the number of items in the channel.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// let (mut sender, receiver) = blocking::channel(4);
+    /// sender.send(1).await.unwrap();
+    /// assert_eq!(receiver.len(), 1);
+    /// # });
+    /// ```
+    pub fn len(&self) -> usize {
+        let tail = self.inner.tail.load(Ordering::Acquire);
+        self.inner.distance(self.head, tail)
+    }
 
-            // Copy bytes from the pipe buffer into `dest`.
-            let n = dest
-                .write(pipe_slice)
-                .expect("shouldn't fail because `dest` is a slice");
-            count += n;
+    /// Returns `true` if the channel is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (sender, receiver) = blocking::channel::<i32>(4);
+    /// assert!(receiver.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-            // If pipe is empty or `dest` is full, return.
-            if n == 0 {
-                return Poll::Ready(Ok(count));
-            }
+    /// Returns the capacity of the channel.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (sender, receiver) = blocking::channel::<i32>(4);
+    /// assert_eq!(receiver.capacity(), 4);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.inner.cap()
+    }
+}
 
-            // Move the head forward.
-            if self.head + n < 2 * cap {
-                self.head += n;
-            } else {
-                self.head = 0;
-            }
+#[cfg(feature = "stream")]
+impl<T> Stream for Receiver<T> {
+    type Item = T;
 
-            // Store the current head index.
-            self.inner.head.store(self.head, Ordering::Release);
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(cx)
+    }
 
-            // Wake the writer because the pipe is not full.
-            self.inner.writer.wake();
-        }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len(), None)
     }
 }
 
-impl Writer {
-    fn poll_write(&mut self, cx: &mut Context<'_>, mut src: impl Read) -> Poll<io::Result<usize>> {
-        // Just a quick check if the pipe is closed, which is why a relaxed load is okay.
-        if self.inner.closed.load(Ordering::Relaxed) {
-            return Poll::Ready(Ok(0));
-        }
-
-        // Calculates the distance between two indices.
-        let cap = self.inner.cap;
-        let distance = |a: usize, b: usize| {
-            if a <= b {
-                b - a
-            } else {
-                2 * cap - (a - b)
-            }
-        };
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
 
-        // If the pipe appears to be full...
-        if distance(self.head, self.tail) == cap {
-            // Reload the head in case it's become stale.
-            self.head = self.inner.head.load(Ordering::Acquire);
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
 
-            // If the pipe is now really empty...
-            if distance(self.head, self.tail) == cap {
-                // Register the waker.
-                self.inner.writer.register(cx.waker());
-                atomic::fence(Ordering::SeqCst);
+/// Creates a pipe that passes ownership of byte segments instead of copying them.
+///
+/// Where [`pipe()`] copies bytes into a ring buffer, this pipe moves whole segments like
+/// [`Vec<u8>`] or [`Bytes`](https://docs.rs/bytes) from a [`SegmentWriter`] to a
+/// [`SegmentReader`], holding up to `cap` segments at a time. The reader implements
+/// [`AsyncBufRead`], which hands out slices of the segments themselves, so large buffers produced
+/// by a blocking task reach the async side without a memcpy.
+///
+/// When the writer is dropped or closed, remaining segments can still be read, after which reads
+/// return `Ok(0)`. When the reader is dropped, sending fails and gives the segment back.
+///
+/// # Panics
+///
+/// This function panics if `cap` is zero or too large.
+///
+/// # Examples
+///
+/// ```
+/// use blocking::Blocking;
+/// use futures::prelude::*;
+///
+/// # futures::executor::block_on(async {
+/// let (mut reader, mut writer) = blocking::segment_pipe(4);
+///
+/// // Produce large buffers on a blocking task and move them over.
+/// Blocking::spawn_detached(async move {
+///     for i in 0..3 {
+///         writer.send_blocking(vec![b'a' + i; 1024 * 1024]).unwrap();
+///     }
+/// });
+///
+/// let mut buf = Vec::new();
+/// reader.read_to_end(&mut buf).await?;
+/// assert_eq!(buf.len(), 3 * 1024 * 1024);
+/// # std::io::Result::Ok(()) });
+/// ```
+pub fn segment_pipe<B: AsRef<[u8]>>(cap: usize) -> (SegmentReader<B>, SegmentWriter<B>) {
+    let (sender, receiver) = channel(cap);
+    let w = SegmentWriter { sender };
+    let r = SegmentReader {
+        receiver,
+        segment: None,
+        pos: 0,
+    };
+    (r, w)
+}
 
-                // Reload the head after registering the waker.
-                self.head = self.inner.head.load(Ordering::Acquire);
+/// The writing side of a segment pipe.
+///
+/// This type is created by [`segment_pipe()`].
+#[derive(Debug)]
+pub struct SegmentWriter<B> {
+    /// Sends segments to the reader.
+    sender: Sender<B>,
+}
 
-                // If the pipe is still full...
-                if distance(self.head, self.tail) == cap {
-                    // Check whether the pipe is closed or just full.
-                    if self.inner.closed.load(Ordering::Relaxed) {
-                        return Poll::Ready(Ok(0));
-                    } else {
-                        return Poll::Pending;
-                    }
-                }
-            }
-        }
+/// The reading side of a segment pipe.
+///
+/// This type is created by [`segment_pipe()`]. Besides [`AsyncRead`] and [`AsyncBufRead`], it
+/// implements [`Read`] by blocking the current thread until bytes are available.
+#[derive(Debug)]
+pub struct SegmentReader<B> {
+    /// Receives segments from the writer.
+    receiver: Receiver<B>,
 
-        // The pipe is not full so remove the waker.
-        self.inner.writer.take();
+    /// The segment being read.
+    segment: Option<B>,
 
-        // Given an index in `0..2*cap`, returns the real index in `0..cap`.
-        let real_index = |i: usize| {
-            if i < cap {
-                i
-            } else {
-                i - cap
-            }
-        };
+    /// The position of the next unread byte in the segment.
+    pos: usize,
+}
 
-        // Number of bytes written so far.
-        let mut count = 0;
+impl<B> Unpin for SegmentReader<B> {}
 
-        loop {
-            // Calculate how many bytes to write in this iteration.
-            let n = (128 * 1024) // Not too many bytes in one go - better to wake the reader soon!
-                .min(self.zeroed_until * 2 + 4096) // Don't zero too many bytes when starting.
-                .min(cap - distance(self.head, self.tail)) // No more than space in the pipe.
-                .min(cap - real_index(self.tail)); // Don't go past the buffer boundary.
+impl<B: AsRef<[u8]>> SegmentWriter<B> {
+    /// Sends a segment into the pipe, waiting while it's full.
+    ///
+    /// If the pipe is closed, the segment is given back as an error. Empty segments are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # futures::executor::block_on(async {
+    /// let (mut reader, mut writer) = blocking::segment_pipe(4);
+    /// writer.send(b"hello".to_vec()).await.unwrap();
+    /// writer.close();
+    ///
+    /// let mut s = String::new();
+    /// reader.read_to_string(&mut s).await?;
+    /// assert_eq!(s, "hello");
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn send(&mut self, segment: B) -> Result<(), B> {
+        if segment.as_ref().is_empty() {
+            return Ok(());
+        }
+        self.sender.send(segment).await
+    }
 
-            // Create a slice of available space in the pipe buffer.
-            let pipe_slice_mut = unsafe {
-                let from = real_index(self.tail);
-                let to = from + n;
-
-                // Make sure all bytes in the slice are initialized.
-                if self.zeroed_until < to {
-                    self.inner
-                        .buffer
-                        .add(self.zeroed_until)
-                        .write_bytes(0u8, to - self.zeroed_until);
-                    self.zeroed_until = to;
-                }
+    /// Sends a segment into the pipe, blocking the current thread while it's full.
+    ///
+    /// This is the blocking version of [`send()`][`SegmentWriter::send()`], to be used from
+    /// blocking tasks and other synchronous code.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Read;
+    ///
+    /// let (mut reader, mut writer) = blocking::segment_pipe(4);
+    /// writer.send_blocking(b"hello".to_vec()).unwrap();
+    /// drop(writer);
+    ///
+    /// let mut s = String::new();
+    /// reader.read_to_string(&mut s)?;
+    /// assert_eq!(s, "hello");
+    /// # std::io::Result::Ok(())
+    /// ```
+    pub fn send_blocking(&mut self, segment: B) -> Result<(), B> {
+        block_on(self.send(segment))
+    }
 
-                slice::from_raw_parts_mut(self.inner.buffer.add(from), n)
-            };
+    /// Closes the pipe and then wakes the reader.
+    ///
+    /// The reader can still read the segments remaining in the pipe, after which it reaches the
+    /// end. Further sends will fail.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// let (reader, mut writer) = blocking::segment_pipe(4);
+    /// writer.close();
+    /// assert_eq!(writer.send(vec![1]).await, Err(vec![1]));
+    /// # });
+    /// ```
+    pub fn close(&self) {
+        self.sender.close();
+    }
+}
 
-            // Copy bytes from `src` into the piper buffer.
-            let n = src
-                .read(pipe_slice_mut)
-                .expect("shouldn't fail because `src` is a slice");
-            count += n;
+impl<B: AsRef<[u8]>> SegmentReader<B> {
+    /// Closes the pipe and then wakes the writer.
+    ///
+    /// Segments remaining in the pipe can still be read, but further sends will fail.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// let (reader, mut writer) = blocking::segment_pipe(4);
+    /// reader.close();
+    /// assert_eq!(writer.send(vec![1]).await, Err(vec![1]));
+    /// # });
+    /// ```
+    pub fn close(&self) {
+        self.receiver.close();
+    }
 
-            // If the pipe is full or `src` is empty, return.
-            if n == 0 {
-                return Poll::Ready(Ok(count));
+    /// Waits until there's an unread byte in the current segment.
+    ///
+    /// Returns `false` if the pipe is empty and closed.
+    fn poll_segment(&mut self, cx: &mut Context<'_>) -> Poll<bool> {
+        loop {
+            if let Some(segment) = &self.segment {
+                if self.pos < segment.as_ref().len() {
+                    return Poll::Ready(true);
+                }
             }
 
-            // Move the tail forward.
-            if self.tail + n < 2 * cap {
-                self.tail += n;
-            } else {
-                self.tail = 0;
+            // The current segment has been read, so move on to the next one.
+            self.segment = ready!(self.receiver.poll_recv(cx));
+            self.pos = 0;
+            if self.segment.is_none() {
+                return Poll::Ready(false);
             }
+        }
+    }
 
-            // Store the current tail index.
-            self.inner.tail.store(self.tail, Ordering::Release);
+    /// Returns the unread bytes in the current segment.
+    fn filled(&self) -> &[u8] {
+        match &self.segment {
+            Some(segment) => &segment.as_ref()[self.pos..],
+            None => &[],
+        }
+    }
+}
 
-            // Wake the reader because the pipe is not empty.
-            self.inner.reader.wake();
+impl<B: AsRef<[u8]>> AsyncRead for SegmentReader<B> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() || !ready!(this.poll_segment(cx)) {
+            return Poll::Ready(Ok(0));
         }
+
+        let filled = this.filled();
+        let n = filled.len().min(buf.len());
+        buf[..n].copy_from_slice(&filled[..n]);
+        this.pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<B: AsRef<[u8]>> AsyncBufRead for SegmentReader<B> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        ready!(this.poll_segment(cx));
+        Poll::Ready(Ok(this.filled()))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.pos += amt.min(this.filled().len());
+    }
+}
+
+impl<B: AsRef<[u8]>> Read for SegmentReader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        block_on(future::poll_fn(|cx| {
+            Pin::new(&mut *self).poll_read(cx, buf)
+        }))
     }
 }
@@ -0,0 +1,125 @@
+//! Implementations of the tokio I/O traits.
+//!
+//! These mirror the implementations of the `futures-io` traits, so a [`Blocking`] handle can be
+//! used with tokio directly.
+
+use std::io::{self, IoSlice, Read, Seek, SeekFrom, Write};
+use std::mem::{self, MaybeUninit};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::{copy_uninit, Blocking, State};
+
+impl<T: Read + Send + 'static> AsyncRead for Blocking<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // SAFETY: Only initialized bytes are written into the unfilled part of the buffer.
+        let mut dest = UninitWriter(unsafe { buf.unfilled_mut() });
+        let n = ready!(self
+            .get_mut()
+            .poll_read_with(cx, |reader, cx| reader.poll_read(cx, &mut dest)))?;
+
+        // SAFETY: The first `n` unfilled bytes have just been copied from the pipe.
+        unsafe { buf.assume_init(n) };
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A writer copying bytes into an uninitialized buffer, moving forward as it gets filled.
+struct UninitWriter<'a>(&'a mut [MaybeUninit<u8>]);
+
+impl Write for UninitWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = copy_uninit(buf, self.0);
+        self.0 = &mut mem::take(&mut self.0)[n..];
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: Read + Send + 'static> AsyncBufRead for Blocking<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        futures_io::AsyncBufRead::poll_fill_buf(self, cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        futures_io::AsyncBufRead::consume(self, amt)
+    }
+}
+
+impl<T: Write + Send + 'static> AsyncWrite for Blocking<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        futures_io::AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        futures_io::AsyncWrite::poll_write_vectored(self, cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures_io::AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures_io::AsyncWrite::poll_close(self, cx)
+    }
+}
+
+impl<T: Seek + Send + 'static> AsyncSeek for Blocking<T> {
+    fn start_seek(self: Pin<&mut Self>, pos: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        match &this.state {
+            State::Idle(_) => this.start_seeking(pos),
+
+            // Seeking can't wait for the running task to stop, so it must be stopped beforehand by
+            // calling `poll_complete()`.
+            State::Task(..)
+            | State::Streaming(..)
+            | State::Reading(..)
+            | State::Writing(..)
+            | State::Seeking(..) => Err(io::Error::other(
+                "other operation is pending, call poll_complete before start_seek",
+            )),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        match &this.state {
+            State::Seeking(_) => this.poll_seeking(cx),
+
+            // Without a seek in progress, stop the running task so that a seek can be started. The
+            // position is not known without seeking, so zero is reported instead, which tokio
+            // ignores when it calls this method before `start_seek()`.
+            State::Idle(_)
+            | State::Task(..)
+            | State::Streaming(..)
+            | State::Reading(..)
+            | State::Writing(..) => {
+                ready!(this.poll_stop(cx))?;
+                Poll::Ready(Ok(0))
+            }
+        }
+    }
+}
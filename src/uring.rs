@@ -0,0 +1,360 @@
+//! File reads and writes through io_uring.
+//!
+//! A single ring is shared by the whole process. Operations are submitted by whoever polls them,
+//! and a dedicated thread waits for completions and wakes the tasks waiting on them. This way,
+//! a task copying a file doesn't occupy a thread while the kernel performs the I/O.
+
+use std::collections::HashMap;
+use std::future::{self, Future};
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use io_uring::{opcode, squeue, types, IoUring, Probe};
+use once_cell::sync::Lazy;
+
+use crate::{Reader, Writer};
+
+/// Number of entries in the submission queue.
+const RING_ENTRIES: u32 = 256;
+
+/// Number of bytes read or written by a single operation.
+const CHUNK_SIZE: usize = 128 * 1024;
+
+/// The global ring, or `None` if io_uring is not available on this system.
+static RING: Lazy<Option<&'static Ring>> = Lazy::new(|| {
+    // If the kernel is too old or io_uring is blocked by a seccomp filter, fall back to threads.
+    let ring = IoUring::new(RING_ENTRIES).ok()?;
+
+    // Kernels before 5.6 create rings but don't support plain reads and writes.
+    let mut probe = Probe::new();
+    ring.submitter().register_probe(&mut probe).ok()?;
+    if !probe.is_supported(opcode::Read::CODE) || !probe.is_supported(opcode::Write::CODE) {
+        return None;
+    }
+
+    let ring: &'static Ring = Box::leak(Box::new(Ring {
+        ring,
+        submit_lock: Mutex::new(()),
+        ops: Mutex::new(HashMap::new()),
+        next_id: AtomicU64::new(1),
+        failed: AtomicBool::new(false),
+    }));
+
+    thread::Builder::new()
+        .name("blocking-uring".to_string())
+        .spawn(move || ring.main_loop())
+        .ok()?;
+
+    Some(ring)
+});
+
+/// Returns `true` if file I/O can go through io_uring.
+pub(crate) fn is_available() -> bool {
+    RING.is_some_and(|ring| !ring.failed.load(Ordering::SeqCst))
+}
+
+/// Reads from a file at its current position into `buf`, returning the buffer back.
+pub(crate) fn read(fd: RawFd, mut buf: Vec<u8>) -> Op {
+    let len = buf.len().min(u32::MAX as usize) as u32;
+    let entry = opcode::Read::new(types::Fd(fd), buf.as_mut_ptr(), len)
+        .offset(u64::MAX)
+        .build();
+    Op::new(entry, buf)
+}
+
+/// Writes `buf` into a file at its current position, returning the buffer back.
+pub(crate) fn write(fd: RawFd, buf: Vec<u8>) -> Op {
+    let len = buf.len().min(u32::MAX as usize) as u32;
+    let entry = opcode::Write::new(types::Fd(fd), buf.as_ptr(), len)
+        .offset(u64::MAX)
+        .build();
+    Op::new(entry, buf)
+}
+
+/// The state of a submitted operation.
+enum State {
+    /// The operation is in flight, and the waker is notified when it completes.
+    Waiting(Option<Waker>),
+
+    /// The operation completed with the given result.
+    Done(i32),
+
+    /// The completion thread stopped with the given error before the operation completed.
+    ///
+    /// The kernel may still be using the buffer, so it must be leaked rather than handed back.
+    Failed(i32),
+
+    /// The operation's future was dropped, so its buffer is kept alive until it completes.
+    Abandoned(#[allow(dead_code)] Vec<u8>),
+}
+
+/// An io_uring instance with a thread processing its completions.
+struct Ring {
+    /// The ring itself.
+    ring: IoUring,
+
+    /// Held while pushing into the submission queue.
+    submit_lock: Mutex<()>,
+
+    /// Operations that have been submitted but not yet retrieved.
+    ops: Mutex<HashMap<u64, State>>,
+
+    /// The ID for the next submitted operation.
+    next_id: AtomicU64,
+
+    /// Set when the completion thread has stopped, after which no operations are submitted.
+    failed: AtomicBool,
+}
+
+impl Ring {
+    /// Runs the completion thread.
+    fn main_loop(&self) {
+        loop {
+            match self.ring.submitter().submit_and_wait(1) {
+                Ok(_) => {}
+                Err(err) if err.raw_os_error() == Some(libc::EINTR) => {}
+                Err(err) if err.raw_os_error() == Some(libc::EBUSY) => {}
+                Err(err) => return self.fail(err),
+            }
+
+            // SAFETY: Only this thread ever accesses the completion queue.
+            let completions: Vec<_> = unsafe { self.ring.completion_shared() }
+                .map(|cqe| (cqe.user_data(), cqe.result()))
+                .collect();
+
+            let mut ops = self.ops.lock().unwrap();
+            for (id, res) in completions {
+                match ops.get_mut(&id) {
+                    Some(State::Waiting(waker)) => {
+                        let waker = waker.take();
+                        ops.insert(id, State::Done(res));
+                        if let Some(waker) = waker {
+                            waker.wake();
+                        }
+                    }
+                    Some(State::Abandoned(_)) => {
+                        ops.remove(&id);
+                    }
+                    Some(State::Done(_)) | Some(State::Failed(_)) | None => {}
+                }
+            }
+        }
+    }
+
+    /// Fails all waiting operations with `err` and stops accepting new ones.
+    ///
+    /// Without the completion thread, there's no telling when the kernel is done with the buffers
+    /// of operations in flight, so all of them are leaked.
+    fn fail(&self, err: io::Error) {
+        let mut ops = self.ops.lock().unwrap();
+        self.failed.store(true, Ordering::SeqCst);
+
+        let errno = err.raw_os_error().unwrap_or(libc::EIO);
+        for state in ops.values_mut() {
+            if let State::Waiting(waker) = state {
+                let waker = waker.take();
+                *state = State::Failed(errno);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+        }
+
+        // Buffers of abandoned operations stay in the map, which is never dropped.
+    }
+
+    /// Submits an operation, returning its ID.
+    ///
+    /// # Safety
+    ///
+    /// Buffers referenced by the entry must stay alive until the operation completes.
+    unsafe fn submit(&self, entry: squeue::Entry, waker: Waker) -> io::Result<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut ops = self.ops.lock().unwrap();
+            if self.failed.load(Ordering::SeqCst) {
+                return Err(io::Error::other("io_uring completion thread has stopped"));
+            }
+            ops.insert(id, State::Waiting(Some(waker)));
+        }
+
+        let entry = entry.user_data(id);
+        let _guard = self.submit_lock.lock().unwrap();
+        loop {
+            // SAFETY: Submission queues are only created while holding the lock.
+            if self.ring.submission_shared().push(&entry).is_ok() {
+                // If submitting fails, the entry is submitted along with the next one.
+                let _ = self.ring.submitter().submit();
+                return Ok(id);
+            }
+
+            // The queue is full, so submit its entries to make room.
+            if let Err(err) = self.ring.submitter().submit() {
+                if err.raw_os_error() != Some(libc::EBUSY) {
+                    self.ops.lock().unwrap().remove(&id);
+                    return Err(err);
+                }
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+/// A future for a read or write operation.
+pub(crate) struct Op {
+    /// The operation to submit on the first poll.
+    entry: Option<squeue::Entry>,
+
+    /// The ID of the operation once submitted.
+    id: Option<u64>,
+
+    /// The buffer read into or written from.
+    buf: Option<Vec<u8>>,
+}
+
+impl Op {
+    fn new(entry: squeue::Entry, buf: Vec<u8>) -> Op {
+        Op {
+            entry: Some(entry),
+            id: None,
+            buf: Some(buf),
+        }
+    }
+}
+
+impl Future for Op {
+    /// The result and the buffer, which is empty if the operation failed without completing.
+    type Output = (io::Result<usize>, Vec<u8>);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let ring = RING.expect("io_uring is not available");
+
+        let id = match self.id {
+            Some(id) => id,
+            None => {
+                let entry = self.entry.take().expect("`Op` polled after completion");
+
+                // SAFETY: The buffer is owned by this future and is handed over to the completion
+                // thread if the future is dropped before the operation completes.
+                match unsafe { ring.submit(entry, cx.waker().clone()) } {
+                    Ok(id) => {
+                        self.id = Some(id);
+                        return Poll::Pending;
+                    }
+                    Err(err) => return Poll::Ready((Err(err), self.buf.take().unwrap())),
+                }
+            }
+        };
+
+        let mut ops = ring.ops.lock().unwrap();
+        match ops.get_mut(&id) {
+            Some(State::Done(res)) => {
+                let res = *res;
+                ops.remove(&id);
+                drop(ops);
+                self.id = None;
+
+                let res = if res < 0 {
+                    Err(io::Error::from_raw_os_error(-res))
+                } else {
+                    Ok(res as usize)
+                };
+                Poll::Ready((res, self.buf.take().unwrap()))
+            }
+            Some(State::Failed(errno)) => {
+                let errno = *errno;
+                ops.remove(&id);
+                drop(ops);
+                self.id = None;
+
+                // The kernel may still write into or read from the buffer, so leak it.
+                mem::forget(self.buf.take());
+                Poll::Ready((Err(io::Error::from_raw_os_error(errno)), Vec::new()))
+            }
+            Some(State::Waiting(waker)) => {
+                match waker {
+                    Some(w) if w.will_wake(cx.waker()) => {}
+                    _ => *waker = Some(cx.waker().clone()),
+                }
+                Poll::Pending
+            }
+            Some(State::Abandoned(_)) | None => unreachable!("unknown io_uring operation"),
+        }
+    }
+}
+
+impl Drop for Op {
+    fn drop(&mut self) {
+        if let (Some(id), Some(ring)) = (self.id, *RING) {
+            let mut ops = ring.ops.lock().unwrap();
+            match ops.get(&id) {
+                Some(State::Waiting(_)) => {
+                    // The kernel may still be using the buffer, so let the completion thread drop
+                    // it.
+                    let buf = self.buf.take().unwrap_or_default();
+                    ops.insert(id, State::Abandoned(buf));
+                }
+                Some(State::Failed(_)) => {
+                    // The operation might never complete, so leak the buffer.
+                    ops.remove(&id);
+                    mem::forget(self.buf.take());
+                }
+                _ => {
+                    ops.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+/// Copies bytes from a file into a pipe until the end of the file or until the pipe is closed.
+pub(crate) async fn copy_from_file(fd: RawFd, writer: &mut Writer) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(CHUNK_SIZE);
+    loop {
+        // Read no more than fits into the pipe so that the bytes are never left over, even if
+        // the reader closes the pipe while the read is in flight.
+        let vacant = future::poll_fn(|cx| writer.poll_vacant(cx)).await;
+        if vacant == 0 {
+            return Ok(());
+        }
+        buf.resize(vacant.min(CHUNK_SIZE), 0);
+
+        let (res, b) = read(fd, buf).await;
+        buf = b;
+
+        let n = res?;
+        if n == 0 {
+            return Ok(());
+        }
+        writer.fill_from(&buf[..n])?;
+    }
+}
+
+/// Copies bytes from a pipe into a file until the pipe is closed.
+pub(crate) async fn copy_to_file(fd: RawFd, reader: &mut Reader) -> io::Result<()> {
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        buf.resize(CHUNK_SIZE, 0);
+        let n = future::poll_fn(|cx| reader.poll_read(cx, &mut buf[..])).await?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        buf.truncate(n);
+        while !buf.is_empty() {
+            let (res, b) = write(fd, buf).await;
+            buf = b;
+
+            match res? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n => drop(buf.drain(..n)),
+            }
+        }
+    }
+}